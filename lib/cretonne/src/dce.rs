@@ -44,6 +44,7 @@ pub fn do_dce(func: &mut Function, domtree: &mut DominatorTree) {
 
     let mut live = Vec::with_capacity(func.dfg.num_values());
     live.resize(func.dfg.num_values(), false);
+    let mut removed = 0;
 
     for &ebb in domtree.cfg_postorder().iter() {
         let mut pos = FuncCursor::new(func).at_bottom(ebb);
@@ -63,6 +64,8 @@ pub fn do_dce(func: &mut Function, domtree: &mut DominatorTree) {
                 }
             }
             pos.remove_inst();
+            removed += 1;
         }
     }
+    timing::record_effect(timing::Pass::dce, removed);
 }
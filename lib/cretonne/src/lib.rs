@@ -30,6 +30,13 @@
                 useless_let_if_seq,
                 len_without_is_empty))]
 
+#[cfg(feature = "otel")]
+extern crate opentelemetry;
+#[cfg(feature = "sqlite")]
+extern crate rusqlite;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+
 pub use context::Context;
 pub use legalizer::legalize_function;
 pub use verifier::verify_function;
@@ -61,6 +61,9 @@ struct Context<'a> {
 
     // Uses of register values in the current instruction.
     reg_uses: &'a mut Vec<RegUse>,
+
+    // Number of values spilled so far, reported as the effect of the spilling pass.
+    num_spilled: u64,
 }
 
 impl Spilling {
@@ -104,8 +107,10 @@ impl Spilling {
             pressure: Pressure::new(&reginfo, &usable_regs),
             spills: &mut self.spills,
             reg_uses: &mut self.reg_uses,
+            num_spilled: 0,
         };
-        ctx.run(tracker)
+        ctx.run(tracker);
+        timing::record_effect(timing::Pass::ra_spilling, ctx.num_spilled);
     }
 }
 
@@ -496,6 +501,7 @@ impl<'a> Context<'a> {
             let rc = self.reginfo.rc(rci);
             self.pressure.free(rc);
             self.spills.push(value);
+            self.num_spilled += 1;
             dbg!("Spilled {}:{} -> {}", value, rc, self.pressure);
         } else {
             panic!("Cannot spill {} that was already on the stack", value);
@@ -0,0 +1,151 @@
+//! Clock used for timing passes.
+//!
+//! On most targets, this is a thin wrapper around `std::time::Instant`.
+//!
+//! On `wasm32-unknown-unknown`, `Instant::now()` panics because there is no system clock. On that
+//! target, the clock is read from a `performance_now` function imported from the `env` module
+//! which must return a monotonic time in milliseconds as an `f64`. A JavaScript embedder can
+//! simply provide `performance.now()`:
+//!
+//! ```js
+//! WebAssembly.instantiate(bytes, { env: { performance_now: () => performance.now() } });
+//! ```
+//!
+//! The clock can be replaced on the current thread with `set_clock()`. Tests use this to install
+//! a `TestClock` which only advances when told to, so timings can be checked against known
+//! durations.
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::time::Duration;
+
+/// A source of time for timing passes.
+pub trait Clock {
+    /// Get the current time, measured from an arbitrary origin. The time must never decrease.
+    fn now(&self) -> Duration;
+}
+
+/// A clock that only advances when `advance()` is called, for testing.
+///
+/// Clones of a test clock share the same time, so a test can keep a clone to advance the clock
+/// installed with `set_clock_for_testing()`.
+#[derive(Clone, Debug, Default)]
+pub struct TestClock(Rc<Cell<Duration>>);
+
+impl TestClock {
+    /// Advance the clock by `dur`.
+    pub fn advance(&self, dur: Duration) {
+        self.0.set(self.0.get() + dur)
+    }
+}
+
+impl Clock for TestClock {
+    fn now(&self) -> Duration {
+        self.0.get()
+    }
+}
+
+thread_local! {
+    /// Clock replacing the system clock on this thread, if any.
+    static CLOCK: RefCell<Option<Box<dyn Clock>>> = RefCell::new(None);
+}
+
+/// Replace the clock used to time passes on the current thread, or restore the system clock
+/// with `None`.
+///
+/// Passes that are running when the clock is replaced are timed as taking no time.
+pub fn set_clock(clock: Option<Box<dyn Clock>>) {
+    CLOCK.with(|c| *c.borrow_mut() = clock)
+}
+
+/// Install a new `TestClock` on the current thread, and return it.
+pub fn set_clock_for_testing() -> TestClock {
+    let clock = TestClock::default();
+    set_clock(Some(Box::new(clock.clone())));
+    clock
+}
+
+/// Read the clock installed with `set_clock()`, if any.
+fn custom_now() -> Option<Duration> {
+    CLOCK.with(|c| c.borrow().as_ref().map(|clock| clock.now()))
+}
+
+/// A point in time read from the clock.
+#[derive(Clone, Copy, Debug)]
+pub enum Timestamp {
+    /// Time read from the system clock.
+    System(imp::Timestamp),
+    /// Time read from a clock installed with `set_clock()`.
+    Custom(Duration),
+}
+
+/// Read the current time.
+pub fn now() -> Timestamp {
+    match custom_now() {
+        Some(time) => Timestamp::Custom(time),
+        None => Timestamp::System(imp::now()),
+    }
+}
+
+impl Timestamp {
+    /// Get the time elapsed since this timestamp was taken.
+    pub fn elapsed(self) -> Duration {
+        match (self, custom_now()) {
+            (Timestamp::System(start), None) => start.elapsed(),
+            (Timestamp::Custom(start), Some(time)) => time.checked_sub(start).unwrap_or_default(),
+            // The clock was replaced since this timestamp was taken.
+            _ => Duration::default(),
+        }
+    }
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+mod imp {
+    use std::time::{Duration, Instant};
+
+    /// A point in time read from the clock.
+    #[derive(Clone, Copy, Debug)]
+    pub struct Timestamp(Instant);
+
+    /// Read the current time.
+    pub fn now() -> Timestamp {
+        Timestamp(Instant::now())
+    }
+
+    impl Timestamp {
+        /// Get the time elapsed since this timestamp was taken.
+        pub fn elapsed(self) -> Duration {
+            self.0.elapsed()
+        }
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+mod imp {
+    use std::time::Duration;
+
+    extern "C" {
+        /// Monotonic time in milliseconds, provided by the host.
+        fn performance_now() -> f64;
+    }
+
+    /// A point in time read from the clock, in milliseconds.
+    #[derive(Clone, Copy, Debug)]
+    pub struct Timestamp(f64);
+
+    /// Read the current time.
+    pub fn now() -> Timestamp {
+        Timestamp(unsafe { performance_now() })
+    }
+
+    impl Timestamp {
+        /// Get the time elapsed since this timestamp was taken.
+        pub fn elapsed(self) -> Duration {
+            // Guard against a misbehaving host clock going backwards.
+            let ms = (now().0 - self.0).max(0.0);
+            let secs = (ms / 1000.0) as u64;
+            let nanos = ((ms % 1000.0) * 1_000_000.0) as u32;
+            Duration::new(secs, nanos)
+        }
+    }
+}
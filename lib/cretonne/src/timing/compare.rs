@@ -0,0 +1,394 @@
+//! Comparing pass timings between runs.
+//!
+//! Timings from runs over different inputs can't be compared directly. Normalizing them by the
+//! size of the input with `PassTimes::per_instruction()` gives a per-instruction cost for each
+//! pass that can be compared across runs whose inputs are only roughly the same, e.g. the same
+//! test suite compiled by two versions of Cretonne.
+//!
+//! Timings of runs over the same input can be compared directly with `PassTimes::regressions()`,
+//! and `PassTimes::gate()` packages the comparison for use as a CI check. `PassTimes::diff()`
+//! gives a full report of the changes in every pass.
+
+use super::details::{from_nanos, secs};
+use super::{Pass, PassRow, PassTimes, NUM_PASSES, PASSES};
+use std::fmt;
+use std::time::Duration;
+
+/// Pass timings normalized by the number of instructions compiled.
+#[derive(Clone, Debug)]
+pub struct NormalizedPassTimes {
+    /// Total time spent in each pass per instruction, in nanoseconds, or `None` if the pass
+    /// didn't run.
+    pass: [Option<f64>; NUM_PASSES],
+}
+
+impl PassTimes {
+    /// Normalize these timings by the number of instructions compiled, `count`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `count` is zero.
+    pub fn per_instruction(&self, count: u64) -> NormalizedPassTimes {
+        assert!(count > 0, "cannot normalize by zero instructions");
+        let mut norm = NormalizedPassTimes { pass: [None; NUM_PASSES] };
+        for (out, time) in norm.pass.iter_mut().zip(self.pass.iter()) {
+            if time.count > 0 {
+                *out = Some(time.total_ns as f64 / count as f64);
+            }
+        }
+        norm
+    }
+}
+
+impl NormalizedPassTimes {
+    /// Get the time spent in `pass` per instruction, in nanoseconds, or `None` if the pass didn't
+    /// run.
+    pub fn get(&self, pass: Pass) -> Option<f64> {
+        self.pass.get(pass.idx()).and_then(|&ns| ns)
+    }
+
+    /// Compare these timings against `baseline`.
+    ///
+    /// Returns the relative change in per-instruction cost of each pass that ran in both, in
+    /// definition order. For example, 0.25 means the pass is 25% slower per instruction than in
+    /// the baseline. Passes with no time in the baseline are skipped.
+    pub fn diff(&self, baseline: &NormalizedPassTimes) -> Vec<(Pass, f64)> {
+        PASSES
+            .iter()
+            .filter_map(|&pass| match (self.get(pass), baseline.get(pass)) {
+                (Some(new), Some(old)) if old > 0.0 => Some((pass, new / old - 1.0)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// A pass that got slower than in a baseline, as returned by `PassTimes::regressions()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Regression {
+    /// The pass.
+    pub pass: Pass,
+
+    /// Total time spent in the pass in the baseline.
+    pub baseline: Duration,
+
+    /// Total time spent in the pass now.
+    pub current: Duration,
+}
+
+impl fmt::Display for Regression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (old, new) = (secs(self.baseline), secs(self.current));
+        write!(
+            f,
+            "{}: {:.3}s -> {:.3}s ({:+.1}%)",
+            self.pass,
+            old,
+            new,
+            (new / old - 1.0) * 100.0
+        )
+    }
+}
+
+/// Outcome of `PassTimes::gate()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GateResult {
+    /// No pass regressed beyond the tolerance.
+    Ok,
+    /// Some passes regressed beyond the tolerance.
+    Regressed(Vec<Regression>),
+}
+
+impl GateResult {
+    /// Get a process exit code for this result: 0 when ok, 1 when some passes regressed.
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            GateResult::Ok => 0,
+            GateResult::Regressed(_) => 1,
+        }
+    }
+}
+
+impl fmt::Display for GateResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GateResult::Ok => writeln!(f, "no pass timing regressions"),
+            GateResult::Regressed(ref regs) => {
+                writeln!(f, "{} pass timing regressions:", regs.len())?;
+                for reg in regs {
+                    writeln!(f, "  {}", reg)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The change in the timing of a single pass, as part of a `TimingDiff`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PassDiff {
+    /// The pass.
+    pub pass: Pass,
+
+    /// Timing of the pass in the baseline, or `None` if it didn't run.
+    pub baseline: Option<PassRow>,
+
+    /// Timing of the pass now, or `None` if it didn't run.
+    pub current: Option<PassRow>,
+}
+
+impl PassDiff {
+    /// Get the absolute change in total time, in seconds, if the pass ran in both.
+    pub fn total_change(&self) -> Option<f64> {
+        self.both().map(|(old, new)| secs(new.total) - secs(old.total))
+    }
+
+    /// Get the relative change in total time, if the pass ran in both and took some time in the
+    /// baseline. For example, 0.25 means the pass is 25% slower than in the baseline.
+    pub fn total_ratio(&self) -> Option<f64> {
+        self.both().and_then(|(old, new)| ratio(old.total, new.total))
+    }
+
+    /// Get the absolute change in self time, in seconds, if the pass ran in both.
+    pub fn self_change(&self) -> Option<f64> {
+        self.both().map(|(old, new)| secs(new.self_time) - secs(old.self_time))
+    }
+
+    /// Get the relative change in self time, if the pass ran in both and took some time in the
+    /// baseline.
+    pub fn self_ratio(&self) -> Option<f64> {
+        self.both().and_then(|(old, new)| ratio(old.self_time, new.self_time))
+    }
+
+    /// Get the baseline and current timings if the pass ran in both.
+    fn both(&self) -> Option<(PassRow, PassRow)> {
+        match (self.baseline, self.current) {
+            (Some(old), Some(new)) => Some((old, new)),
+            _ => None,
+        }
+    }
+}
+
+/// Get the relative change from `old` to `new`.
+fn ratio(old: Duration, new: Duration) -> Option<f64> {
+    if old == Duration::default() {
+        None
+    } else {
+        Some(secs(new) / secs(old) - 1.0)
+    }
+}
+
+/// The changes in pass timings relative to a baseline, as returned by `PassTimes::diff()`.
+///
+/// The `Display` implementation lists every pass that ran in either, marking passes that were
+/// added with `+`, removed with `-`, and slowed down by more than the threshold with `!`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimingDiff {
+    /// One entry per pass that ran in the baseline or now, in definition order.
+    pub passes: Vec<PassDiff>,
+
+    /// Relative increase in total time beyond which a pass is considered regressed. Defaults to
+    /// 0.1, i.e. 10% slower.
+    pub threshold: f64,
+}
+
+impl TimingDiff {
+    /// Set the regression threshold.
+    pub fn with_threshold(mut self, threshold: f64) -> TimingDiff {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Get the passes whose total time increased by more than the threshold.
+    pub fn regressed<'a>(&'a self) -> impl Iterator<Item = &'a PassDiff> + 'a {
+        self.passes.iter().filter(move |diff| self.is_regressed(diff))
+    }
+
+    /// Did the total time of `diff` increase by more than the threshold?
+    fn is_regressed(&self, diff: &PassDiff) -> bool {
+        match diff.total_ratio() {
+            Some(r) => r > self.threshold,
+            None => false,
+        }
+    }
+}
+
+impl fmt::Display for TimingDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for diff in &self.passes {
+            match (diff.baseline, diff.current) {
+                (Some(old), Some(new)) => {
+                    let percent = |r: Option<f64>| {
+                        r.map_or("n/a".to_string(), |r| format!("{:+.1}%", r * 100.0))
+                    };
+                    writeln!(
+                        f,
+                        "{} {}: total {:.3}s -> {:.3}s ({}), self {:.3}s -> {:.3}s ({})",
+                        if self.is_regressed(diff) { '!' } else { ' ' },
+                        diff.pass,
+                        secs(old.total),
+                        secs(new.total),
+                        percent(diff.total_ratio()),
+                        secs(old.self_time),
+                        secs(new.self_time),
+                        percent(diff.self_ratio())
+                    )?
+                }
+                (None, Some(new)) => {
+                    writeln!(f, "+ {}: added, total {:.3}s", diff.pass, secs(new.total))?
+                }
+                (Some(old), None) => {
+                    writeln!(f, "- {}: removed, total {:.3}s", diff.pass, secs(old.total))?
+                }
+                (None, None) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PassTimes {
+    /// Compare these timings against `baseline`.
+    ///
+    /// Every pass that ran in either is listed with its baseline and current timings. Use
+    /// `TimingDiff::with_threshold()` to change which passes are reported as regressed.
+    pub fn diff(&self, baseline: &PassTimes) -> TimingDiff {
+        let mut current = self.rows().peekable();
+        let mut old = baseline.rows().peekable();
+        let passes = PASSES
+            .iter()
+            .filter_map(|&pass| {
+                let current = match current.peek() {
+                    Some(row) if row.pass == pass => current.next(),
+                    _ => None,
+                };
+                let baseline = match old.peek() {
+                    Some(row) if row.pass == pass => old.next(),
+                    _ => None,
+                };
+                if current.is_none() && baseline.is_none() {
+                    None
+                } else {
+                    Some(PassDiff {
+                        pass,
+                        baseline,
+                        current,
+                    })
+                }
+            })
+            .collect();
+        TimingDiff {
+            passes,
+            threshold: 0.1,
+        }
+    }
+
+    /// Find the passes that got slower than in `baseline` by more than `tolerance`.
+    ///
+    /// The tolerance is relative, so 0.1 allows a pass to be 10% slower than in the baseline.
+    /// Only passes that ran in both are compared. The regressions are listed in definition
+    /// order.
+    pub fn regressions(&self, baseline: &PassTimes, tolerance: f64) -> Vec<Regression> {
+        PASSES
+            .iter()
+            .zip(self.pass.iter().zip(baseline.pass.iter()))
+            .filter(|&(_, (new, old))| {
+                new.count > 0 && old.count > 0 &&
+                    new.total_ns as f64 > old.total_ns as f64 * (1.0 + tolerance)
+            })
+            .map(|(&pass, (new, old))| {
+                Regression {
+                    pass,
+                    baseline: from_nanos(old.total_ns),
+                    current: from_nanos(new.total_ns),
+                }
+            })
+            .collect()
+    }
+
+    /// Check these timings against `baseline` for regressions beyond `tolerance`.
+    ///
+    /// This is meant for CI scripts: load a baseline, run, then exit with
+    /// `times.gate(&baseline, tolerance).exit_code()`, printing the result first.
+    pub fn gate(&self, baseline: &PassTimes, tolerance: f64) -> GateResult {
+        let regs = self.regressions(baseline, tolerance);
+        if regs.is_empty() {
+            GateResult::Ok
+        } else {
+            GateResult::Regressed(regs)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(regalloc_ns: u64) -> PassTimes {
+        PassTimes::decode(&format!(
+            "cretonne-timing 1\npass regalloc {} 0 1\n",
+            regalloc_ns
+        )).unwrap()
+    }
+
+    #[test]
+    fn normalized_diff() {
+        let old = sample(1000).per_instruction(100);
+        let new = sample(1320).per_instruction(110);
+        assert_eq!(old.get(Pass::regalloc), Some(10.0));
+        assert_eq!(new.get(Pass::regalloc), Some(12.0));
+        assert_eq!(old.get(Pass::dce), None);
+
+        let diff = new.diff(&old);
+        assert_eq!(diff.len(), 1);
+        assert_eq!(diff[0].0, Pass::regalloc);
+        assert!((diff[0].1 - 0.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn timing_diff() {
+        let baseline = PassTimes::decode(
+            "cretonne-timing 1\npass regalloc 1000000000 500000000 1\n\
+             pass ra_liveness 500000000 0 1\npass licm 10000000 0 1\n",
+        ).unwrap();
+        let current = PassTimes::decode(
+            "cretonne-timing 1\npass regalloc 1300000000 550000000 1\n\
+             pass ra_liveness 540000000 0 1\npass dce 20000000 0 1\n",
+        ).unwrap();
+
+        let diff = current.diff(&baseline);
+        let passes: Vec<_> = diff.passes.iter().map(|d| d.pass).collect();
+        assert_eq!(passes, [Pass::dce, Pass::licm, Pass::regalloc, Pass::ra_liveness]);
+        assert!((diff.passes[2].total_change().unwrap() - 0.3).abs() < 1e-9);
+        assert!((diff.passes[2].self_ratio().unwrap() - 0.5).abs() < 1e-9);
+        assert_eq!(diff.passes[0].total_ratio(), None);
+
+        let regressed: Vec<_> = diff.regressed().map(|d| d.pass).collect();
+        assert_eq!(regressed, [Pass::regalloc]);
+        assert_eq!(diff.clone().with_threshold(0.05).regressed().count(), 2);
+        assert_eq!(
+            diff.to_string(),
+            "+ Dead code elimination: added, total 0.020s\n\
+             - Loop invariant code motion: removed, total 0.010s\n\
+             ! Register allocation: total 1.000s -> 1.300s (+30.0%), \
+             self 0.500s -> 0.750s (+50.0%)\n  \
+             RA liveness analysis: total 0.500s -> 0.540s (+8.0%), \
+             self 0.500s -> 0.540s (+8.0%)\n"
+        );
+    }
+
+    #[test]
+    fn gate() {
+        let baseline = sample(1_000_000_000);
+        assert_eq!(sample(1_050_000_000).gate(&baseline, 0.1), GateResult::Ok);
+        assert_eq!(sample(1_050_000_000).gate(&baseline, 0.1).exit_code(), 0);
+        assert_eq!(sample(1_000_000_000).gate(&PassTimes::default(), 0.1), GateResult::Ok);
+
+        let result = sample(1_300_000_000).gate(&baseline, 0.1);
+        assert_eq!(result.exit_code(), 1);
+        assert_eq!(
+            result.to_string(),
+            "1 pass timing regressions:\n  Register allocation: 1.000s -> 1.300s (+30.0%)\n"
+        );
+    }
+}
@@ -0,0 +1,121 @@
+//! Passes registered at runtime.
+//!
+//! The predefined passes cover Cretonne itself. Frontends that want to time their own passes in
+//! the same report, e.g. the lowering of a source language to Cretonne IR, register them with
+//! `register_pass()` and start them with `DynPass::start()`.
+//!
+//! Registered passes are accumulated alongside the predefined ones and listed after them in the
+//! timing report, in registration order. They nest with predefined passes like any other pass,
+//! but they are only included in the reports that list all the passes with their description.
+//! In particular, they aren't saved by `PassTimes::encode()`.
+
+use super::details::{self, TimingToken};
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::Mutex;
+
+/// Names of the registered passes, indexed by their id.
+static NAMES: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+thread_local! {
+    /// Copy of `NAMES` on the current thread, refreshed when it is missing a pass. Passes are
+    /// never unregistered, so looking up a name only takes the lock the first time a thread sees
+    /// a newly registered pass.
+    static CACHED_NAMES: RefCell<Vec<&'static str>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A compilation pass registered at runtime with `register_pass()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DynPass(usize);
+
+/// Register a pass named `name` that can be timed along with the predefined passes.
+///
+/// Registering the same name again returns the same pass, so frontends can register their
+/// passes lazily without coordinating between threads. Like the names of the predefined passes,
+/// `name` is typically a string literal.
+pub fn register_pass(name: &'static str) -> DynPass {
+    let mut names = NAMES.lock().unwrap();
+    let id = match names.iter().position(|&n| n == name) {
+        Some(id) => id,
+        None => {
+            names.push(name);
+            names.len() - 1
+        }
+    };
+    DynPass(id)
+}
+
+/// Get the name of the registered pass with the given id.
+pub(super) fn name(id: usize) -> &'static str {
+    CACHED_NAMES.with(|rc| {
+        let mut cached = rc.borrow_mut();
+        if id >= cached.len() {
+            cached.clone_from(&NAMES.lock().unwrap());
+        }
+        cached.get(id).cloned().unwrap_or("<unregistered pass>")
+    })
+}
+
+impl DynPass {
+    /// Get the index of this pass in the order the passes were registered.
+    pub fn idx(self) -> usize {
+        self.0
+    }
+
+    /// Get the name this pass was registered with.
+    pub fn name(self) -> &'static str {
+        name(self.0)
+    }
+
+    /// Start timing this pass as a child of the currently running pass, if any.
+    pub fn start(self) -> TimingToken {
+        details::start_custom(self)
+    }
+}
+
+impl fmt::Display for DynPass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{compile, take_current, Pass};
+    use super::*;
+
+    #[test]
+    fn register() {
+        let lower = register_pass("custom::register lower");
+        assert_eq!(register_pass("custom::register lower"), lower);
+        assert_ne!(register_pass("custom::register check"), lower);
+        assert_eq!(lower.to_string(), "custom::register lower");
+        // Other threads see the name too.
+        let name = ::std::thread::spawn(move || lower.name()).join().unwrap();
+        assert_eq!(name, "custom::register lower");
+    }
+
+    #[test]
+    fn accumulate() {
+        let lower = register_pass("custom::accumulate lower");
+        take_current();
+        {
+            let _lower = lower.start();
+            let _ = compile();
+        }
+        let _ = lower.start();
+        let times = take_current();
+
+        let stats = times.dyn_stats(lower);
+        assert_eq!(stats.count, 2);
+        assert_eq!(times.stats(Pass::compile).count, 1);
+        // The compile pass is nested in the registered pass.
+        assert_eq!(times.total(), stats.total);
+        assert!(!times.is_empty());
+
+        let report = times.to_string();
+        let passes: Vec<_> = report.lines().skip(3).map(|l| &l[37..]).collect();
+        assert_eq!(passes.len(), 3);
+        assert_eq!(passes[..2], ["Compilation passes", "custom::accumulate lower"]);
+    }
+}
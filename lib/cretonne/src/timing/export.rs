@@ -0,0 +1,228 @@
+//! Exporting pass timings in machine-readable formats: JSON, CSV, folded stacks, and the
+//! Prometheus text exposition format.
+//!
+//! Passes are identified by their snake_case names, as returned by `Pass::name()`, and times are
+//! exported as integer nanoseconds so no precision is lost. Only passes that have run are
+//! exported, like in the `Display` report. Folded stacks and Prometheus metrics are the exceptions,
+//! since the tools that consume them expect microseconds and seconds respectively.
+
+use super::details::nanos;
+use super::PassTimes;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+impl PassTimes {
+    /// Encode these timings as a JSON object.
+    ///
+    /// The object has one member per pass that has run, keyed by the pass name, with the total
+    /// and self times in nanoseconds and the number of times the pass was run:
+    ///
+    /// ```text
+    /// {"regalloc": {"total_ns": 1200, "self_ns": 300, "count": 1}, ...}
+    /// ```
+    pub fn to_json(&self) -> String {
+        let members: Vec<String> = self.rows()
+            .map(|row| {
+                format!(
+                    "\"{}\": {{\"total_ns\": {}, \"self_ns\": {}, \"count\": {}}}",
+                    row.pass.name(),
+                    nanos(row.total),
+                    nanos(row.self_time),
+                    row.count
+                )
+            })
+            .collect();
+        format!("{{{}}}", members.join(", "))
+    }
+
+    /// Write these timings as CSV to `w`.
+    ///
+    /// The output has a `pass,total_ns,self_ns,count` header row followed by one row per pass that
+    /// has run.
+    pub fn write_csv<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, "pass,total_ns,self_ns,count")?;
+        for row in self.rows() {
+            writeln!(
+                w,
+                "{},{},{},{}",
+                row.pass.name(),
+                nanos(row.total),
+                nanos(row.self_time),
+                row.count
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write these timings as Prometheus counters to `w`, in the text exposition format.
+    ///
+    /// The total time of each pass that has run is written in seconds as the
+    /// `cretonne_pass_seconds` counter, and the number of times it was run as the
+    /// `cretonne_pass_invocations_total` counter, both labeled with the pass name:
+    ///
+    /// ```text
+    /// # HELP cretonne_pass_seconds Total time spent in each compilation pass.
+    /// # TYPE cretonne_pass_seconds counter
+    /// cretonne_pass_seconds{pass="regalloc"} 1.234
+    /// ```
+    pub fn write_prometheus<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, "# HELP cretonne_pass_seconds Total time spent in each compilation pass.")?;
+        writeln!(w, "# TYPE cretonne_pass_seconds counter")?;
+        for row in self.rows() {
+            writeln!(
+                w,
+                "cretonne_pass_seconds{{pass=\"{}\"}} {}",
+                row.pass.name(),
+                nanos(row.total) as f64 / 1e9
+            )?;
+        }
+        writeln!(
+            w,
+            "# HELP cretonne_pass_invocations_total Number of times each compilation pass ran."
+        )?;
+        writeln!(w, "# TYPE cretonne_pass_invocations_total counter")?;
+        for row in self.rows() {
+            writeln!(
+                w,
+                "cretonne_pass_invocations_total{{pass=\"{}\"}} {}",
+                row.pass.name(),
+                row.count
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write these timings as folded stacks to `w`, for generating flame graphs with
+    /// `flamegraph.pl` or similar tools.
+    ///
+    /// There is one line per stack of nested passes, with the pass names from the outermost to
+    /// the innermost pass joined by semicolons, followed by the self time of the innermost pass
+    /// in that stack in microseconds:
+    ///
+    /// ```text
+    /// compile;regalloc;ra_coloring 1234
+    /// ```
+    ///
+    /// Stacks with less than half a microsecond of self time are omitted.
+    pub fn write_folded<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        // Subtract the time spent in each stack from the stack it's nested in.
+        let mut self_ns: HashMap<&[_], u64> = self.stacks
+            .iter()
+            .map(|(stack, &ns)| (stack.as_slice(), ns))
+            .collect();
+        for (stack, &ns) in &self.stacks {
+            if let Some((_, parent)) = stack.split_last() {
+                if let Some(parent_ns) = self_ns.get_mut(parent) {
+                    *parent_ns = parent_ns.saturating_sub(ns);
+                }
+            }
+        }
+
+        // Sort the stacks so the output is deterministic.
+        let mut stacks: Vec<_> = self_ns.into_iter().collect();
+        stacks.sort_by_key(|&(stack, _)| stack.iter().map(|pass| pass.idx()).collect::<Vec<_>>());
+        for (stack, ns) in stacks {
+            let micros = (ns + 500) / 1000;
+            if micros == 0 {
+                continue;
+            }
+            let names: Vec<_> = stack.iter().map(|pass| pass.name()).collect();
+            writeln!(w, "{} {}", names.join(";"), micros)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use timing::PassTimes;
+
+    fn sample() -> PassTimes {
+        PassTimes::decode(
+            "cretonne-timing 1\npass regalloc 1200 900 1\npass ra_liveness 900 0 3\n",
+        ).unwrap()
+    }
+
+    #[test]
+    fn folded() {
+        let times = PassTimes::decode(
+            "cretonne-timing 1\n\
+             pass compile 10000000 6000000 1\n\
+             pass regalloc 6000000 4000000 1\n\
+             pass ra_coloring 4000000 0 1\n\
+             stack compile 10000000\n\
+             stack compile;regalloc 6000000\n\
+             stack compile;regalloc;ra_coloring 4000000\n",
+        ).unwrap();
+        let mut folded = Vec::new();
+        times.write_folded(&mut folded).unwrap();
+        assert_eq!(
+            String::from_utf8(folded).unwrap(),
+            "compile 4000\n\
+             compile;regalloc 2000\n\
+             compile;regalloc;ra_coloring 4000\n"
+        );
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(PassTimes::default().to_json(), "{}");
+        assert_eq!(
+            sample().to_json(),
+            "{\"regalloc\": {\"total_ns\": 1200, \"self_ns\": 300, \"count\": 1}, \
+             \"ra_liveness\": {\"total_ns\": 900, \"self_ns\": 900, \"count\": 3}}"
+        );
+    }
+
+    #[test]
+    fn prometheus() {
+        let times = PassTimes::decode(
+            "cretonne-timing 1\npass regalloc 1234000000 900 1\npass ra_liveness 500000 0 3\n",
+        ).unwrap();
+        let mut prom = Vec::new();
+        times.write_prometheus(&mut prom).unwrap();
+        let prom = String::from_utf8(prom).unwrap();
+        let metrics: Vec<_> = prom.lines().filter(|l| !l.starts_with('#')).collect();
+        assert_eq!(
+            metrics,
+            [
+                "cretonne_pass_seconds{pass=\"regalloc\"} 1.234",
+                "cretonne_pass_seconds{pass=\"ra_liveness\"} 0.0005",
+                "cretonne_pass_invocations_total{pass=\"regalloc\"} 1",
+                "cretonne_pass_invocations_total{pass=\"ra_liveness\"} 3",
+            ]
+        );
+        assert!(prom.contains("# TYPE cretonne_pass_seconds counter\n"));
+    }
+
+    #[test]
+    fn csv() {
+        let mut csv = Vec::new();
+        sample().write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("pass,total_ns,self_ns,count"));
+
+        // Parse the rows back and check the totals.
+        let rows: Vec<(String, u64, u64, u64)> = lines
+            .map(|line| {
+                let fields: Vec<&str> = line.split(',').collect();
+                assert_eq!(fields.len(), 4);
+                (
+                    fields[0].to_string(),
+                    fields[1].parse().unwrap(),
+                    fields[2].parse().unwrap(),
+                    fields[3].parse().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ("regalloc".to_string(), 1200, 300, 1),
+                ("ra_liveness".to_string(), 900, 900, 3),
+            ]
+        );
+        assert_eq!(rows.iter().map(|r| r.2).sum::<u64>(), 1200);
+    }
+}
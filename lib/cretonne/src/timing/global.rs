@@ -0,0 +1,217 @@
+//! Lock-free accumulation of the pass timings of all threads in global mode.
+//!
+//! In global mode, every pass that ends on any thread adds to one shared total, see
+//! `set_global()`. Guarding that total with a lock would serialize the compiling threads at the
+//! end of each pass, so the counters of each predefined pass are kept in atomics instead.
+//!
+//! Only flat counters can be accumulated this way. The timings of registered passes, the nesting
+//! of the passes, and the histograms of their run times aren't accumulated in global mode. The
+//! target ISA names are recorded rarely enough to be kept behind a lock.
+
+use super::details::{PassTime, PassTimes};
+use super::NUM_PASSES;
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// The counters of a `PassTime`, updated atomically.
+struct AtomicPassTime {
+    total_ns: AtomicU64,
+    child_ns: AtomicU64,
+    count: AtomicU64,
+    min_ns: AtomicU64,
+    max_ns: AtomicU64,
+
+    /// Bits of the `f64` sum of the squares of the single run times.
+    sq_ns: AtomicU64,
+
+    /// Accumulated effect, only meaningful if `has_effect` is set.
+    effect: AtomicU64,
+    has_effect: AtomicBool,
+}
+
+impl AtomicPassTime {
+    const fn new() -> Self {
+        AtomicPassTime {
+            total_ns: AtomicU64::new(0),
+            child_ns: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+            min_ns: AtomicU64::new(u64::MAX),
+            max_ns: AtomicU64::new(0),
+            sq_ns: AtomicU64::new(0),
+            effect: AtomicU64::new(0),
+            has_effect: AtomicBool::new(false),
+        }
+    }
+
+    /// Account for a single run taking `duration` nanoseconds.
+    fn add_run(&self, duration: u64) {
+        self.total_ns.fetch_add(duration, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.min_ns.fetch_min(duration, Ordering::Relaxed);
+        self.max_ns.fetch_max(duration, Ordering::Relaxed);
+        let square = duration as f64 * duration as f64;
+        let _ = self.sq_ns.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+            Some((f64::from_bits(bits) + square).to_bits())
+        });
+    }
+
+    /// Take the accumulated counters and reset them.
+    fn take(&self) -> PassTime {
+        let count = self.count.swap(0, Ordering::Relaxed);
+        let min_ns = self.min_ns.swap(u64::MAX, Ordering::Relaxed);
+        let max_ns = self.max_ns.swap(0, Ordering::Relaxed);
+        let effect = self.effect.swap(0, Ordering::Relaxed);
+        let mut time = PassTime {
+            total_ns: self.total_ns.swap(0, Ordering::Relaxed),
+            child_ns: self.child_ns.swap(0, Ordering::Relaxed),
+            sq_ns: f64::from_bits(self.sq_ns.swap(0, Ordering::Relaxed)),
+            effect: if self.has_effect.swap(false, Ordering::Relaxed) {
+                Some(effect)
+            } else {
+                None
+            },
+            ..PassTime::default()
+        };
+        time.add_runs(count, min_ns, max_ns);
+        time
+    }
+}
+
+/// Pass timings accumulated by all threads.
+pub(super) struct GlobalTimes {
+    /// Counters of the predefined passes, indexed by `Pass::idx()`.
+    pass: [AtomicPassTime; NUM_PASSES],
+
+    /// Names of the target ISAs compiled for, as recorded by `set_isa()`.
+    isas: Mutex<Vec<String>>,
+}
+
+impl GlobalTimes {
+    pub(super) const fn new() -> Self {
+        GlobalTimes {
+            pass: [const { AtomicPassTime::new() }; NUM_PASSES],
+            isas: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Account for a run of the pass in `slot` taking `duration` nanoseconds, directly inside
+    /// the pass in the `parent` slot, if any. Registered passes are only accounted as the
+    /// children of their parent.
+    pub(super) fn end_pass(&self, slot: usize, parent: Option<usize>, duration: u64) {
+        if let Some(time) = self.pass.get(slot) {
+            time.add_run(duration);
+        }
+        if let Some(time) = parent.and_then(|parent| self.pass.get(parent)) {
+            time.child_ns.fetch_add(duration, Ordering::Relaxed);
+        }
+    }
+
+    /// Add `amount` to the effect of the predefined pass with index `idx`.
+    pub(super) fn record_effect(&self, idx: usize, amount: u64) {
+        if let Some(time) = self.pass.get(idx) {
+            time.effect.fetch_add(amount, Ordering::Relaxed);
+            time.has_effect.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Record that passes are compiling for the target ISA `name`.
+    pub(super) fn add_isa(&self, name: &str) {
+        let mut isas = self.isas.lock().unwrap();
+        if !isas.iter().any(|isa| isa == name) {
+            isas.push(name.to_string());
+        }
+    }
+
+    /// Take the accumulated timings and reset them.
+    ///
+    /// The counters are taken one by one, so a pass ending meanwhile on another thread may be
+    /// split between these timings and the next ones.
+    pub(super) fn take(&self) -> PassTimes {
+        let mut times = PassTimes::default();
+        for (time, global) in times.pass.iter_mut().zip(&self.pass) {
+            *time = global.take();
+        }
+        times.isas = mem::take(&mut *self.isas.lock().unwrap());
+        times
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Pass;
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn accumulate() {
+        let global = GlobalTimes::new();
+        let compile = Pass::compile.idx();
+        let gvn = Pass::gvn.idx();
+        global.end_pass(gvn, Some(compile), 3);
+        global.end_pass(gvn, Some(compile), 5);
+        global.end_pass(compile, None, 10);
+        global.record_effect(gvn, 2);
+        global.add_isa("x86");
+        global.add_isa("x86");
+
+        let times = global.take();
+        let stats = times.stats(Pass::gvn);
+        assert_eq!(stats.count, 2);
+        assert_eq!(stats.total, Duration::from_nanos(8));
+        assert_eq!(stats.min, Duration::from_nanos(3));
+        assert_eq!(stats.max, Duration::from_nanos(5));
+        assert_eq!(times.pass[gvn].sq_ns, 34.0);
+        assert_eq!(times.pass[gvn].effect, Some(2));
+        assert_eq!(times.pass[compile].child_ns, 8);
+        assert_eq!(times.pass[compile].effect, None);
+        assert_eq!(times.isas(), ["x86"]);
+        assert!(global.take().is_empty());
+    }
+
+    /// Time `threads` threads each calling `end_pass` `rounds` times.
+    fn bench<F: Fn(u64) + Sync + Send + 'static>(threads: usize, rounds: u64, end_pass: F) -> f64 {
+        let end_pass = Arc::new(end_pass);
+        let start = Instant::now();
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                let end_pass = end_pass.clone();
+                thread::spawn(move || for ns in 0..rounds {
+                    end_pass(ns);
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        start.elapsed().as_secs_f64() * 1e9 / (threads as u64 * rounds) as f64
+    }
+
+    /// Compare the throughput of the lock-free accumulation with a `Mutex<PassTimes>`.
+    ///
+    /// Run with `cargo test --release --lib global_throughput -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn global_throughput() {
+        const ROUNDS: u64 = 1_000_000;
+        let gvn = Pass::gvn.idx();
+        let parent = Some(Pass::compile.idx());
+        let path = [Pass::compile, Pass::gvn];
+        for &threads in &[1, 2, 4, 8] {
+            let global = Arc::new(GlobalTimes::new());
+            let atomic = bench(threads, ROUNDS, move |ns| global.end_pass(gvn, parent, ns));
+            let locked = Arc::new(Mutex::new(PassTimes::default()));
+            let mutex = bench(threads, ROUNDS, move |ns| {
+                locked.lock().unwrap().end_pass(gvn, parent, &path, ns)
+            });
+            println!(
+                "{} threads: {:.1} ns/pass lock-free, {:.1} ns/pass with a mutex",
+                threads,
+                atomic,
+                mutex
+            );
+        }
+    }
+}
@@ -0,0 +1,194 @@
+//! Histograms of the run times of single passes.
+//!
+//! The total and average time of a pass don't tell whether it is uniformly slow or dominated by a
+//! long tail of slow runs. When histograms are enabled with `set_histograms(true)`, the duration
+//! of every run of a pass is counted in a histogram, and `PassTimes::percentiles()` gives the
+//! median and tail latencies of the pass.
+//!
+//! The histograms have log-linear buckets like HDR histograms: Each power of two is divided into
+//! 32 buckets, so a quantile is known to within about 3% of its value, and a histogram needs at
+//! most a few hundred buckets, which are only allocated for the passes that ran. Histograms aren't
+//! saved by `PassTimes::encode()`.
+
+use super::details::from_nanos;
+use super::{Pass, PassTimes};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Number of bits of a duration resolved within each power of two.
+const SUB_BITS: u32 = 5;
+
+/// Number of buckets per power of two.
+const SUB_BUCKETS: u64 = 1 << SUB_BITS;
+
+/// The quantiles returned by `PassTimes::percentiles()`.
+const PERCENTILES: [f64; 3] = [0.5, 0.9, 0.99];
+
+/// Are histograms being recorded?
+static HISTOGRAMS: AtomicBool = AtomicBool::new(false);
+
+/// Enable or disable recording the run time histograms of the passes in all threads.
+///
+/// Recording a run costs a hash table lookup, and each pass that ran gets a histogram of up to a
+/// few kilobytes, so this is disabled by default.
+pub fn set_histograms(enable: bool) {
+    HISTOGRAMS.store(enable, Ordering::Relaxed);
+}
+
+/// Are histograms being recorded?
+pub(super) fn enabled() -> bool {
+    HISTOGRAMS.load(Ordering::Relaxed)
+}
+
+/// Get the bucket counting a run of `ns` nanoseconds.
+fn bucket(ns: u64) -> usize {
+    if ns < SUB_BUCKETS {
+        return ns as usize;
+    }
+    let shift = 63 - ns.leading_zeros() - SUB_BITS;
+    (u64::from(shift + 1) * SUB_BUCKETS + (ns >> shift) - SUB_BUCKETS) as usize
+}
+
+/// Get the range of run times counted by `bucket`, in nanoseconds.
+fn bucket_range(bucket: usize) -> (u64, u64) {
+    let bucket = bucket as u64;
+    if bucket < SUB_BUCKETS {
+        return (bucket, bucket);
+    }
+    let shift = bucket / SUB_BUCKETS - 1;
+    let low = (SUB_BUCKETS + bucket % SUB_BUCKETS) << shift;
+    (low, low + ((1 << shift) - 1))
+}
+
+/// Counts of the runs of a pass by duration.
+#[derive(Clone, Debug, Default)]
+pub(super) struct Histogram {
+    /// Number of runs in each bucket. Only buckets up to the last non-empty one are present.
+    counts: Vec<u64>,
+
+    /// Total number of runs.
+    total: u64,
+}
+
+impl Histogram {
+    /// Count a run of `ns` nanoseconds.
+    pub(super) fn record(&mut self, ns: u64) {
+        let b = bucket(ns);
+        if self.counts.len() <= b {
+            self.counts.resize(b + 1, 0);
+        }
+        self.counts[b] += 1;
+        self.total += 1;
+    }
+
+    /// Add the runs counted in `other`.
+    pub(super) fn add(&mut self, other: &Histogram) {
+        if self.counts.len() < other.counts.len() {
+            self.counts.resize(other.counts.len(), 0);
+        }
+        for (a, b) in self.counts.iter_mut().zip(&other.counts) {
+            *a += b;
+        }
+        self.total += other.total;
+    }
+
+    /// Get the run time below which a fraction `q` of the runs fall, or `None` if there are no
+    /// runs. The midpoint of the bucket holding the quantile is returned.
+    fn quantile(&self, q: f64) -> Option<Duration> {
+        if self.total == 0 {
+            return None;
+        }
+        let rank = ((q * self.total as f64).ceil() as u64).max(1).min(self.total);
+        let mut seen = 0;
+        self.counts.iter().position(|&count| {
+            seen += count;
+            seen >= rank
+        }).map(|b| {
+            let (low, high) = bucket_range(b);
+            from_nanos(low + (high - low) / 2)
+        })
+    }
+}
+
+impl PassTimes {
+    /// Get the run time of `pass` below which a fraction `q` of its runs fall.
+    ///
+    /// For example, `quantile(Pass::regalloc, 0.99)` is the 99th percentile of the time spent
+    /// allocating registers for a function. Returns `None` if no histogram was recorded for the
+    /// pass, see `set_histograms()`.
+    pub fn quantile(&self, pass: Pass, q: f64) -> Option<Duration> {
+        self.histograms.get(&pass.idx()).and_then(|hist| hist.quantile(q))
+    }
+
+    /// Get the 50th, 90th, and 99th percentiles of the run time of `pass`, as pairs of quantile
+    /// and duration.
+    ///
+    /// Returns an empty vector if no histogram was recorded for the pass, see
+    /// `set_histograms()`.
+    pub fn percentiles(&self, pass: Pass) -> Vec<(f64, Duration)> {
+        PERCENTILES
+            .iter()
+            .filter_map(|&q| self.quantile(pass, q).map(|dur| (q, dur)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::details::nanos;
+    use super::*;
+
+    #[test]
+    fn buckets() {
+        for &ns in &[0, 1, 31, 32, 33, 63, 64, 65, 1000, 123_456_789, u64::MAX] {
+            let (low, high) = bucket_range(bucket(ns));
+            assert!(low <= ns && ns <= high, "{} in {}..{}", ns, low, high);
+        }
+        assert_eq!(bucket(31) + 1, bucket(32));
+        assert_eq!(bucket(63) + 1, bucket(64));
+        assert_eq!(bucket(64), bucket(65));
+        // Buckets are within 1/32 of their values.
+        let (low, high) = bucket_range(bucket(1_000_000));
+        assert!((high - low) * 32 <= low);
+    }
+
+    #[test]
+    fn quantiles() {
+        let mut hist = Histogram::default();
+        assert_eq!(hist.quantile(0.5), None);
+        for ns in 1..11 {
+            hist.record(ns);
+        }
+        let mut slow = Histogram::default();
+        slow.record(1_000_000);
+        hist.add(&slow);
+        assert_eq!(hist.quantile(0.0), Some(Duration::new(0, 1)));
+        assert_eq!(hist.quantile(0.5), Some(Duration::new(0, 6)));
+        assert_eq!(hist.quantile(0.9), Some(Duration::new(0, 10)));
+        let p99 = nanos(hist.quantile(0.99).unwrap());
+        assert!(p99 > 990_000 && p99 < 1_010_000);
+    }
+
+    #[test]
+    fn percentiles() {
+        use super::super::{binemit, set_clock, set_clock_for_testing, take_current};
+
+        take_current();
+        let clock = set_clock_for_testing();
+        set_histograms(true);
+        for ms in 1..101 {
+            let _emit = binemit();
+            clock.advance(Duration::from_millis(ms));
+        }
+        set_histograms(false);
+        set_clock(None);
+        let times = take_current();
+        let percentiles = times.percentiles(Pass::binemit);
+        assert_eq!(percentiles.len(), 3);
+        for (&(q, dur), &expected) in percentiles.iter().zip(&[50, 90, 99]) {
+            let ms = nanos(dur) as f64 * 1e-6;
+            assert!((ms / f64::from(expected) - 1.0).abs() < 0.04, "p{}: {} ms", q * 100.0, ms);
+        }
+        assert_eq!(times.percentiles(Pass::regalloc), Vec::new());
+    }
+}
@@ -0,0 +1,2452 @@
+//! Pass timing.
+//!
+//! This modules provides facilities for timing the execution of individual compilation passes.
+//!
+//! With the `no-timing` feature, timing is compiled out entirely. The pass functions return
+//! empty tokens, and only the `TimingToken`, `PassTimes`, `PassTimesStats` and `DynPass` types
+//! and the `take_current()`, `swap_current()`, `add_to_current()`, `snapshot()`, `set_isa()`,
+//! `check_discipline()`, `record_effect()`, `register_pass()` and `is_enabled()` functions are
+//! provided. The timings are always empty, and `is_enabled()` always returns `false`.
+
+use std::fmt;
+use std::time::Duration;
+
+#[cfg(not(feature = "no-timing"))]
+mod clock;
+#[cfg(not(feature = "no-timing"))]
+mod compare;
+#[cfg(not(feature = "no-timing"))]
+mod custom;
+#[cfg(not(feature = "no-timing"))]
+mod export;
+#[cfg(not(feature = "no-timing"))]
+mod global;
+#[cfg(not(feature = "no-timing"))]
+mod histogram;
+#[cfg(all(feature = "otel", not(feature = "no-timing")))]
+mod otel;
+#[cfg(not(feature = "no-timing"))]
+mod overhead;
+#[cfg(not(feature = "no-timing"))]
+mod persist;
+#[cfg(not(feature = "no-timing"))]
+mod provenance;
+#[cfg(all(feature = "sqlite", not(feature = "no-timing")))]
+mod sqlite;
+#[cfg(not(feature = "no-timing"))]
+mod stats;
+#[cfg(all(feature = "svg", not(feature = "no-timing")))]
+mod svg;
+#[cfg(not(feature = "no-timing"))]
+mod trace;
+#[cfg(all(feature = "tracing", not(feature = "no-timing")))]
+mod tracing;
+
+#[cfg(not(feature = "no-timing"))]
+pub use self::details::{add_to_current, add_to_current_labeled, budget_remaining, calibrate,
+                        check_discipline, clear_budget, collect_from, is_active, is_enabled,
+                        is_global, is_pass_enabled, is_recording, record_effect, set_budget,
+                        set_enabled, set_enabled_passes, set_global, set_isa, set_recording,
+                        skip_next, snapshot, swap_current, take_current, take_global, PassStats,
+                        PassTimes, TimeUnit, TimingToken};
+#[cfg(not(feature = "no-timing"))]
+pub use self::clock::{set_clock, set_clock_for_testing, Clock, TestClock};
+#[cfg(not(feature = "no-timing"))]
+pub use self::compare::{GateResult, NormalizedPassTimes, PassDiff, Regression, TimingDiff};
+#[cfg(not(feature = "no-timing"))]
+pub use self::custom::{register_pass, DynPass};
+#[cfg(not(feature = "no-timing"))]
+pub use self::histogram::set_histograms;
+#[cfg(not(feature = "no-timing"))]
+pub use self::overhead::set_overhead_limit;
+#[cfg(not(feature = "no-timing"))]
+pub use self::stats::PassTimesStats;
+#[cfg(not(feature = "no-timing"))]
+pub use self::trace::{set_tracing, write_trace};
+#[cfg(feature = "no-timing")]
+pub use self::details::{add_to_current, check_discipline, is_enabled, record_effect,
+                        register_pass, set_isa, snapshot, swap_current, take_current, DynPass,
+                        PassTimes, PassTimesStats, TimingToken};
+
+// Each pass that can be timed is predefined with the `define_passes!` macro. Each pass has a
+// snake_case name, the phase of compilation it belongs to, and a plain text description used when
+// printing out the timing report.
+//
+// This macro defines:
+//
+// - A public C-style enum containing all the pass names and a `None` variant.
+// - A usize constant with the number of defined passes.
+// - A const array of all the passes.
+// - A const array of pass names.
+// - A const array of pass phases.
+// - A const array of pass descriptions.
+// - A public function per pass used to start the timing of that pass.
+// - With the `tracing` feature, a function creating a `tracing` span named after a pass.
+macro_rules! define_passes {
+    { $enum:ident, $num_passes:ident, $passes:ident, $names:ident, $phases:ident,
+      $descriptions:ident;
+      $($pass:ident: $phase:ident, $desc:expr,)+
+    } => {
+        /// A compilation pass that can be timed.
+        #[allow(non_camel_case_types)]
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        pub enum $enum {
+            $(#[doc=$desc] $pass,)+
+            /// No pass is running.
+            None,
+        }
+
+        const $num_passes: usize = $enum::None as usize;
+
+        const $passes: [$enum; $num_passes] = [ $($enum::$pass),+ ];
+
+        const $names: [&str; $num_passes] = [ $(stringify!($pass)),+ ];
+
+        const $phases: [Phase; $num_passes] = [ $(Phase::$phase),+ ];
+
+        const $descriptions: [&str; $num_passes] = [ $($desc),+ ];
+
+        $(
+            #[doc=$desc]
+            pub fn $pass() -> TimingToken {
+                details::start_pass($enum::$pass)
+            }
+        )+
+
+        /// Create a `tracing` span named after `pass`.
+        ///
+        /// Span names must be string literals, so each pass needs its own `span!` invocation.
+        #[cfg(all(feature = "tracing", not(feature = "no-timing")))]
+        fn pass_span(pass: $enum) -> ::tracing::Span {
+            match pass {
+                $($enum::$pass => ::tracing::span!(::tracing::Level::INFO, stringify!($pass)),)+
+                $enum::None => ::tracing::Span::none(),
+            }
+        }
+    }
+}
+
+// Pass definitions.
+define_passes!{
+    Pass, NUM_PASSES, PASSES, NAMES, PHASES, DESCRIPTIONS;
+
+    process_file: Frontend, "Processing test file",
+    parse_text: Frontend, "Parsing textual Cretonne IR",
+    wasm_translate_module: Frontend, "Translate WASM module",
+    wasm_translate_function: Frontend, "Translate WASM function",
+
+    verifier: Optimize, "Verify Cretonne IR",
+    verify_cssa: Regalloc, "Verify CSSA",
+    verify_liveness: Regalloc, "Verify live ranges",
+    verify_locations: Regalloc, "Verify value locations",
+    verify_flags: Optimize, "Verify CPU flags",
+
+    compile: Optimize, "Compilation passes",
+    flowgraph: Optimize, "Control flow graph",
+    domtree: Optimize, "Dominator tree",
+    loop_analysis: Optimize, "Loop analysis",
+    postopt: Optimize, "Post-legalization rewriting",
+    preopt: Optimize, "Pre-legalization rewriting",
+    dce: Optimize, "Dead code elimination",
+    legalize: Optimize, "Legalization",
+    gvn: Optimize, "Global value numbering",
+    licm: Optimize, "Loop invariant code motion",
+    unreachable_code: Optimize, "Remove unreachable blocks",
+
+    regalloc: Regalloc, "Register allocation",
+    ra_liveness: Regalloc, "RA liveness analysis",
+    ra_cssa: Regalloc, "RA coalescing CSSA",
+    ra_spilling: Regalloc, "RA spilling",
+    ra_reload: Regalloc, "RA reloading",
+    ra_coloring: Regalloc, "RA coloring",
+
+    prologue_epilogue: Backend, "Prologue/epilogue insertion",
+    binemit: Backend, "Binary machine code emission",
+    layout_renumber: Backend, "Layout full renumbering",
+}
+
+impl Pass {
+    /// Get all the defined passes in definition order, excluding `Pass::None`.
+    ///
+    /// The index of a pass in this slice is its `idx()`.
+    pub fn all() -> &'static [Pass] {
+        &PASSES
+    }
+
+    /// Get the index of this pass in the order the passes are defined.
+    pub fn idx(self) -> usize {
+        self as usize
+    }
+
+    /// Get the snake_case name of this pass, as used for the pass function.
+    pub fn name(self) -> &'static str {
+        NAMES.get(self.idx()).cloned().unwrap_or("none")
+    }
+
+    /// Get the plain text description of this pass, as shown in the timing report.
+    pub fn description(self) -> &'static str {
+        DESCRIPTIONS.get(self.idx()).cloned().unwrap_or("<no pass>")
+    }
+
+    /// Get the phase of compilation this pass belongs to.
+    pub fn phase(self) -> Option<Phase> {
+        PHASES.get(self.idx()).cloned()
+    }
+
+    /// Look up a pass by its snake_case name.
+    ///
+    /// This can validate a user-supplied list of pass names. `Pass::None` can't be looked up.
+    pub fn from_name(name: &str) -> Option<Pass> {
+        NAMES.iter().position(|&n| n == name).map(|idx| PASSES[idx])
+    }
+}
+
+impl fmt::Display for Pass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.description())
+    }
+}
+
+/// A phase of compilation, grouping related passes for `PassTimes::phase_summary()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Phase {
+    /// Reading and translating the input, e.g. parsing text or translating WebAssembly.
+    Frontend,
+    /// Analyses, legalization and optimizations of Cretonne IR.
+    Optimize,
+    /// Register allocation and the verification of its results.
+    Regalloc,
+    /// Code generation after register allocation.
+    Backend,
+}
+
+impl Phase {
+    /// All the phases, in the order they run.
+    pub const ALL: [Phase; 4] = [Phase::Frontend, Phase::Optimize, Phase::Regalloc, Phase::Backend];
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Phase::Frontend => "Frontend",
+            Phase::Optimize => "Optimization",
+            Phase::Regalloc => "Register allocation",
+            Phase::Backend => "Backend",
+        })
+    }
+}
+
+/// Accumulated timing information for a single pass, as returned by `PassTimes::rows()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PassRow {
+    /// The pass.
+    pub pass: Pass,
+
+    /// Total time spent running this pass, including child passes.
+    pub total: Duration,
+
+    /// Time spent running this pass, excluding child passes.
+    pub self_time: Duration,
+
+    /// Number of times this pass was run.
+    pub count: u64,
+
+    /// Accumulated effect reported with `record_effect()`, if any.
+    pub effect: Option<u64>,
+}
+
+/// Pass timings tagged with a label identifying where they came from.
+///
+/// This is useful when combining timings from several sources into one report, for example one
+/// per target ISA.
+pub struct TaggedPassTimes {
+    /// Label identifying the source of the timings.
+    pub label: String,
+
+    /// The timings.
+    pub times: PassTimes,
+}
+
+impl fmt::Display for TaggedPassTimes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.label.is_empty() {
+            writeln!(f, "{}:", self.label)?;
+        }
+        write!(f, "{}", self.times)
+    }
+}
+
+/// Implementation details.
+///
+/// This whole module is gated on the `no-timing` feature, which replaces it with a dummy
+/// implementation for performance-sensitive builds or restricted environments. The dummy
+/// implementation must provide the API listed in the module documentation, along with
+/// `start_pass`.
+#[cfg(not(feature = "no-timing"))]
+mod details {
+    use super::clock::{self, Timestamp};
+    use super::custom::{self, DynPass};
+    use super::global::GlobalTimes;
+    use super::histogram::{self, Histogram};
+    use super::{overhead, trace};
+    use super::{Pass, PassRow, Phase, DESCRIPTIONS, NUM_PASSES, PASSES};
+    #[cfg(feature = "otel")]
+    use super::otel;
+    #[cfg(feature = "tracing")]
+    use super::tracing;
+    use std::cell::{Cell, RefCell};
+    use std::cmp::Reverse;
+    use std::collections::{BTreeMap, HashMap};
+    use std::fmt;
+    use std::mem;
+    use std::ops;
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    /// A timing token is responsible for timing the currently running pass. Timing starts when it
+    /// is created and ends when it is dropped.
+    ///
+    /// Multiple passes can be active at the same time, and they should be started and stopped in a
+    /// LIFO fashion. A token dropped out of order is tolerated, but the passes running inside it
+    /// are then accounted for as children of its parent.
+    ///
+    /// When timing is disabled, the token is inert, except that the pass is still considered
+    /// active by `is_active()` until the token is dropped.
+    ///
+    /// The clock of a token can be paused to exclude work done during the pass from its time,
+    /// e.g. writing out the emitted code.
+    pub struct TimingToken {
+        /// The pass this token was created for.
+        pass: Pass,
+
+        /// The pass being timed, or `None` when timing is disabled.
+        active: Option<ActivePass>,
+    }
+
+    /// The state of a pass being timed by a `TimingToken`.
+    struct ActivePass {
+        /// Time the clock was last started or resumed, or `None` while it is paused.
+        start: Option<Timestamp>,
+
+        /// Time accumulated before the clock was last paused, in nanoseconds.
+        elapsed_ns: u64,
+
+        // Slot of the pass being timed by this token. See `PassTimes::time_mut()`.
+        slot: usize,
+
+        // Was a trace event recorded for the start of this pass?
+        traced: bool,
+
+        // Is this a run of `calibrate()`? Calibration runs are always accumulated on the current
+        // thread.
+        calibrating: bool,
+
+        // OpenTelemetry span for this pass. It is ended when the token is dropped. Registered
+        // passes don't get a span.
+        #[cfg(feature = "otel")]
+        _span: Option<otel::PassSpan>,
+
+        // `tracing` span for this pass. It is exited when the token is dropped. Registered passes
+        // don't get a span.
+        #[cfg(feature = "tracing")]
+        _tracing_span: Option<tracing::PassSpan>,
+    }
+
+    /// Accumulated timing information for a single pass.
+    ///
+    /// Times are stored as integer nanoseconds rather than `Duration`s, which keeps the table
+    /// compact and lets the counters be updated with plain integer (or atomic) additions. The
+    /// public API converts to and from `Duration` at the boundary.
+    #[derive(Clone, Default)]
+    pub(super) struct PassTime {
+        /// Total time spent running this pas including children, in nanoseconds.
+        pub(super) total_ns: u64,
+
+        /// Time spent running in child passes, in nanoseconds.
+        pub(super) child_ns: u64,
+
+        /// Number of times this pass was run.
+        pub(super) count: u64,
+
+        /// Shortest and longest single run of this pass, in nanoseconds. Only meaningful when
+        /// `count` is non-zero.
+        pub(super) min_ns: u64,
+        pub(super) max_ns: u64,
+
+        /// Sum of the squares of the single run times, in square nanoseconds.
+        pub(super) sq_ns: f64,
+
+        /// Accumulated effect reported with `record_effect()`, if any.
+        pub(super) effect: Option<u64>,
+    }
+
+    impl PassTime {
+        /// Account for `count` more runs whose single run times range over `min_ns..max_ns`.
+        pub(super) fn add_runs(&mut self, count: u64, min_ns: u64, max_ns: u64) {
+            if count == 0 {
+                return;
+            }
+            if self.count == 0 {
+                self.min_ns = min_ns;
+                self.max_ns = max_ns;
+            } else {
+                self.min_ns = self.min_ns.min(min_ns);
+                self.max_ns = self.max_ns.max(max_ns);
+            }
+            self.count += count;
+        }
+
+        /// Total time spent running this pass, including children.
+        pub(super) fn total(&self) -> Duration {
+            from_nanos(self.total_ns)
+        }
+
+        /// Time spent running this pass, excluding children.
+        fn self_time(&self) -> Duration {
+            from_nanos(self.total_ns.saturating_sub(self.child_ns))
+        }
+
+        /// Sample standard deviation of the single run times, or zero with fewer than two runs.
+        fn stddev(&self) -> Duration {
+            if self.count < 2 {
+                return Duration::default();
+            }
+            let n = self.count as f64;
+            let mean = self.total_ns as f64 / n;
+            let var = (self.sq_ns - n * mean * mean) / (n - 1.0);
+            from_nanos(var.max(0.0).sqrt().round() as u64)
+        }
+    }
+
+    /// Convert a duration to nanoseconds.
+    pub(super) fn nanos(dur: Duration) -> u64 {
+        dur.as_secs() * 1_000_000_000 + u64::from(dur.subsec_nanos())
+    }
+
+    /// Convert nanoseconds to a duration.
+    pub(super) fn from_nanos(ns: u64) -> Duration {
+        Duration::new(ns / 1_000_000_000, (ns % 1_000_000_000) as u32)
+    }
+
+    /// Convert a duration to floating point seconds.
+    pub(crate) fn secs(dur: Duration) -> f64 {
+        dur.as_secs() as f64 + f64::from(dur.subsec_nanos()) * 1e-9
+    }
+
+    /// Convert a duration to floating point milliseconds.
+    #[cfg(feature = "svg")]
+    pub(crate) fn millis(dur: Duration) -> f64 {
+        secs(dur) * 1e3
+    }
+
+    /// Distribution of the run times of a single pass, as returned by `PassTimes::stats()`.
+    ///
+    /// A pass with a high total time may be uniformly slow, or it may be dominated by a single
+    /// slow run, e.g. register allocation of one pathological function. Comparing `max` to
+    /// `average()` tells the two apart.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct PassStats {
+        /// Number of times the pass was run.
+        pub count: u64,
+
+        /// Total time spent running the pass, including child passes.
+        pub total: Duration,
+
+        /// Shortest single run of the pass.
+        pub min: Duration,
+
+        /// Longest single run of the pass.
+        pub max: Duration,
+    }
+
+    impl PassStats {
+        /// Get the average time of a single run of the pass, or zero if it didn't run.
+        pub fn average(&self) -> Duration {
+            nanos(self.total)
+                .checked_div(self.count)
+                .map_or_else(Duration::default, from_nanos)
+        }
+    }
+
+    /// Accumulated timing for all passes.
+    #[derive(Clone, Default)]
+    pub struct PassTimes {
+        pub(super) pass: [PassTime; NUM_PASSES],
+
+        /// Timings of the passes registered with `register_pass()`, indexed by `DynPass::idx()`.
+        /// Only passes up to the last one that has run are present.
+        pub(super) custom: Vec<PassTime>,
+
+        /// Time spent in each child pass in nanoseconds, keyed by `(parent, child)`.
+        pub(super) edges: HashMap<(Pass, Pass), u64>,
+
+        /// Total time spent in each stack of nested passes in nanoseconds, keyed by the passes
+        /// from the outermost to the innermost one.
+        pub(super) stacks: HashMap<Vec<Pass>, u64>,
+
+        /// Names of the target ISAs compiled for, as recorded by `set_isa()`.
+        pub(super) isas: Vec<String>,
+
+        /// Histograms of the single run times of the passes, keyed by slot, when recorded. See
+        /// `set_histograms()`.
+        pub(super) histograms: HashMap<usize, Histogram>,
+
+        /// Total time of each predefined pass in nanoseconds, indexed by `Pass::idx()`, per label
+        /// of the timings added with `add_labeled()`.
+        pub(super) contributors: BTreeMap<String, Vec<u64>>,
+    }
+
+    impl PassTimes {
+        /// Add the timings in `other` to `self`.
+        pub fn add(&mut self, other: &PassTimes) {
+            for (a, b) in self.pass.iter_mut().zip(&other.pass) {
+                a.total_ns += b.total_ns;
+                a.child_ns += b.child_ns;
+                a.add_runs(b.count, b.min_ns, b.max_ns);
+                a.sq_ns += b.sq_ns;
+                a.effect = add_effects(a.effect, b.effect);
+            }
+            for (idx, b) in other.custom.iter().enumerate() {
+                let a = self.time_mut(NUM_PASSES + idx);
+                a.total_ns += b.total_ns;
+                a.child_ns += b.child_ns;
+                a.add_runs(b.count, b.min_ns, b.max_ns);
+                a.sq_ns += b.sq_ns;
+                a.effect = add_effects(a.effect, b.effect);
+            }
+            for (&slot, hist) in &other.histograms {
+                self.histograms.entry(slot).or_default().add(hist);
+            }
+            for (&edge, &ns) in &other.edges {
+                *self.edges.entry(edge).or_insert(0) += ns;
+            }
+            for (stack, &ns) in &other.stacks {
+                *self.stacks.entry(stack.clone()).or_insert(0) += ns;
+            }
+            for isa in &other.isas {
+                self.add_isa(isa);
+            }
+            self.add_contributors(other);
+        }
+
+        /// Record that these timings include compilations for the target ISA `name`.
+        pub(super) fn add_isa(&mut self, name: &str) {
+            if !self.isas.iter().any(|isa| isa == name) {
+                self.isas.push(name.to_string());
+            }
+        }
+
+        /// Get the names of the target ISAs these timings were recorded for, in the order they
+        /// were first recorded with `set_isa()`.
+        pub fn isas(&self) -> &[String] {
+            &self.isas
+        }
+
+        /// Get the distribution of the run times of `pass`.
+        ///
+        /// All the fields are zero if the pass hasn't run.
+        pub fn stats(&self, pass: Pass) -> PassStats {
+            self.pass.get(pass.idx()).map_or_else(PassStats::default, |time| {
+                PassStats {
+                    count: time.count,
+                    total: time.total(),
+                    min: from_nanos(time.min_ns),
+                    max: from_nanos(time.max_ns),
+                }
+            })
+        }
+
+        /// Get the distribution of the run times of the registered pass `pass`.
+        ///
+        /// All the fields are zero if the pass hasn't run.
+        pub fn dyn_stats(&self, pass: DynPass) -> PassStats {
+            self.time(NUM_PASSES + pass.idx()).map_or_else(PassStats::default, |time| {
+                PassStats {
+                    count: time.count,
+                    total: time.total(),
+                    min: from_nanos(time.min_ns),
+                    max: from_nanos(time.max_ns),
+                }
+            })
+        }
+
+        /// Get the timing of the pass in `slot`, if it has one.
+        ///
+        /// Slots number all the passes: first the predefined passes in definition order, then the
+        /// passes registered with `register_pass()` in registration order.
+        fn time(&self, slot: usize) -> Option<&PassTime> {
+            self.pass.get(slot).or_else(|| self.custom.get(slot - NUM_PASSES))
+        }
+
+        /// Get the timing of the pass in `slot`, adding it if needed. See `time()`.
+        fn time_mut(&mut self, slot: usize) -> &mut PassTime {
+            if slot < NUM_PASSES {
+                return &mut self.pass[slot];
+            }
+            let idx = slot - NUM_PASSES;
+            if self.custom.len() <= idx {
+                self.custom.resize(idx + 1, PassTime::default());
+            }
+            &mut self.custom[idx]
+        }
+
+        /// Iterate over the timings of all the slots, whether they have run or not. See
+        /// `time()`.
+        fn times<'a>(&'a self) -> impl Iterator<Item = &'a PassTime> + 'a {
+            self.pass.iter().chain(&self.custom)
+        }
+
+        /// Get the timing information for all the predefined passes that have run, in
+        /// definition order.
+        ///
+        /// Passes registered with `register_pass()` aren't included. Use `dyn_stats()` for them.
+        pub fn rows<'a>(&'a self) -> impl Iterator<Item = PassRow> + 'a {
+            PASSES
+                .iter()
+                .zip(&self.pass)
+                .filter(|&(_, time)| time.count > 0)
+                .map(|(&pass, time)| {
+                    PassRow {
+                        pass,
+                        total: time.total(),
+                        self_time: time.self_time(),
+                        count: time.count,
+                        effect: time.effect,
+                    }
+                })
+        }
+
+        /// Get the total time spent in top-level passes.
+        ///
+        /// Time spent in child passes is already included in the total time of their parents, so
+        /// it isn't counted twice.
+        pub fn total(&self) -> Duration {
+            from_nanos(self.top_level_ns())
+        }
+
+        /// Get the time spent in `pass`, excluding its child passes.
+        pub fn self_time(&self, pass: Pass) -> Duration {
+            self.pass.get(pass.idx()).map_or_else(Duration::default, PassTime::self_time)
+        }
+
+        /// Get the time spent in `pass`, including its child passes.
+        ///
+        /// Unlike the report, this isn't rounded.
+        pub fn total_duration(&self, pass: Pass) -> Duration {
+            self.pass.get(pass.idx()).map_or_else(Duration::default, PassTime::total)
+        }
+
+        /// Get the time spent in the child passes of `pass`.
+        pub fn child_duration(&self, pass: Pass) -> Duration {
+            from_nanos(self.pass.get(pass.idx()).map_or(0, |time| time.child_ns))
+        }
+
+        /// Get the sample standard deviation of the single run times of `pass`, including child
+        /// passes.
+        ///
+        /// Returns zero if the pass ran less than twice, or if the timings were loaded with
+        /// `PassTimes::decode()`, which doesn't keep the sums needed.
+        pub fn stddev(&self, pass: Pass) -> Duration {
+            self.pass.get(pass.idx()).map_or_else(Duration::default, PassTime::stddev)
+        }
+
+        /// Get the time spent in `pass`, including its child passes, in nanoseconds.
+        pub fn total_nanos(&self, pass: Pass) -> u128 {
+            self.pass.get(pass.idx()).map_or(0, |time| u128::from(time.total_ns))
+        }
+
+        /// Have no passes been timed?
+        pub fn is_empty(&self) -> bool {
+            self.times().all(|time| time.count == 0)
+        }
+
+        /// Get the time spent in top-level passes in nanoseconds, which is the sum of the self
+        /// times of all passes.
+        fn top_level_ns(&self) -> u64 {
+            self.times()
+                .map(|time| time.total_ns.saturating_sub(time.child_ns))
+                .sum()
+        }
+
+        /// Iterate over the passes that have run, in definition order.
+        ///
+        /// Each item is a `(pass, total, self_time)` tuple, where `total` includes the time spent
+        /// in child passes and `self_time` excludes it. Use `rows()` for more details.
+        pub fn iter<'a>(&'a self) -> impl Iterator<Item = (Pass, Duration, Duration)> + 'a {
+            self.rows().map(|row| (row.pass, row.total, row.self_time))
+        }
+
+        /// Get a deterministic report of which passes have run and how many times.
+        ///
+        /// Unlike the `Display` report, this doesn't contain any durations, so the same pipeline
+        /// always produces identical output. This makes it suitable for golden tests. There is one
+        /// line per pass that has run, in definition order, e.g. `regalloc (x1)`.
+        pub fn structure_report(&self) -> String {
+            self.rows()
+                .map(|row| format!("{} (x{})\n", row.pass.name(), row.count))
+                .collect()
+        }
+
+        /// Get a report of the passes that have run, sorted by how many times they ran.
+        ///
+        /// Each pass is listed with its run count, total time, and average time per run in
+        /// microseconds. This highlights passes that are cheap individually but run so often
+        /// that they are candidates for caching.
+        pub fn report_by_count(&self) -> String {
+            ByCount(self).to_string()
+        }
+
+        /// Get a report of the passes that have run, with child passes indented under the
+        /// passes they ran in.
+        ///
+        /// Each top-level pass is listed with the time it spent outside of any other pass, and
+        /// each child pass with the time it spent in its parent. A pass that ran in several
+        /// parents is listed under each of them. Passes are listed in definition order at each
+        /// level.
+        pub fn display_tree(&self) -> String {
+            Tree(self).to_string()
+        }
+
+        /// Get the timing report with all times shown in `unit`.
+        ///
+        /// The `Display` report picks a unit for each time so it fits in the column. Use this to
+        /// line up the times of all the passes in the same unit instead.
+        pub fn display_with_unit(&self, unit: TimeUnit) -> String {
+            WithUnit(self, unit).to_string()
+        }
+
+        /// Get the timing report with the passes sorted by decreasing self time.
+        ///
+        /// This is the same as the `Display` report, except the most expensive passes are
+        /// listed first instead of in definition order. Passes with the same self time are
+        /// listed in definition order.
+        pub fn display_sorted(&self) -> String {
+            Sorted(self).to_string()
+        }
+
+        /// Get the timing report with an extra column holding the standard deviation of the
+        /// single run times of each pass, see `stddev()`.
+        pub fn display_with_stats(&self) -> String {
+            WithStats(self).to_string()
+        }
+
+        /// Get the fraction of the total self time spent in each pass that has run.
+        ///
+        /// The passes are sorted by decreasing fraction, and the fractions sum to 1.0, modulo
+        /// floating point rounding errors. Returns an empty vector if no time was recorded.
+        pub fn fractions(&self) -> Vec<(Pass, f64)> {
+            let total = self.rows().map(|row| secs(row.self_time)).sum::<f64>();
+            if total == 0.0 {
+                return Vec::new();
+            }
+            let mut fracs: Vec<_> = self.rows()
+                .map(|row| (row.pass, secs(row.self_time) / total))
+                .collect();
+            // Stable sort keeps definition order for ties.
+            fracs.sort_by(|a, b| b.1.partial_cmp(&a.1).expect("NaN fraction"));
+            fracs
+        }
+
+        /// Get a summary of the time spent in each phase of compilation.
+        ///
+        /// There is one row per phase, listing the total and self time of the predefined passes
+        /// in that phase. The total time of a phase doesn't count passes nested in another pass
+        /// of the same phase twice, e.g. the `ra_*` passes are included in the `regalloc` pass.
+        /// Use the `Display` report for the per-pass details.
+        pub fn phase_summary(&self) -> String {
+            PhaseSummary(self).to_string()
+        }
+
+        /// Get a breakdown of the time spent in `root` among its immediate child passes.
+        ///
+        /// Each child is listed with its share of the time spent in `root` and a cumulative
+        /// running total. The time `root` spent outside of any child pass is listed last, so the
+        /// cumulative total ends at the total time spent in `root`.
+        ///
+        /// Returns an empty string if `root` hasn't run.
+        pub fn waterfall(&self, root: Pass) -> String {
+            Waterfall(self, root).to_string()
+        }
+    }
+
+    /// Display adapter for `PassTimes::phase_summary()`.
+    struct PhaseSummary<'a>(&'a PassTimes);
+
+    impl<'a> fmt::Display for PhaseSummary<'a> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            let times = self.0;
+            writeln!(f, "======== ========  ==================================")?;
+            writeln!(f, "   Total     Self  Phase")?;
+            writeln!(f, "-------- --------  ----------------------------------")?;
+            for &phase in &Phase::ALL {
+                let (mut total_ns, mut self_ns) = (0, 0);
+                for (&pass, time) in PASSES.iter().zip(&times.pass) {
+                    if pass.phase() == Some(phase) {
+                        total_ns += time.total_ns;
+                        self_ns += time.total_ns.saturating_sub(time.child_ns);
+                    }
+                }
+                // Don't count passes nested in the same phase twice.
+                let nested_ns: u64 = times
+                    .edges
+                    .iter()
+                    .filter(|&(&(parent, child), _)| {
+                        parent.phase() == Some(phase) && child.phase() == Some(phase)
+                    })
+                    .map(|(_, &ns)| ns)
+                    .sum();
+                writeln!(
+                    f,
+                    "{} {}  {}",
+                    DisplayDur(from_nanos(total_ns.saturating_sub(nested_ns)), None),
+                    DisplayDur(from_nanos(self_ns), None),
+                    phase
+                )?;
+            }
+            writeln!(f, "======== ========  ==================================")
+        }
+    }
+
+    /// Display adapter for `PassTimes::waterfall()`.
+    struct Waterfall<'a>(&'a PassTimes, Pass);
+
+    impl<'a> fmt::Display for Waterfall<'a> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            let Waterfall(times, root) = *self;
+            let total = match times.pass.get(root.idx()) {
+                Some(time) if time.total_ns != 0 => time.total(),
+                _ => return Ok(()),
+            };
+
+            writeln!(f, "======== ========  ==================================")?;
+            writeln!(f, "   Share    Cumul  {}", root)?;
+            writeln!(f, "-------- --------  ----------------------------------")?;
+            let mut cumul = Duration::default();
+            for (&pass, desc) in PASSES.iter().zip(&DESCRIPTIONS) {
+                if let Some(&ns) = times.edges.get(&(root, pass)) {
+                    let dur = from_nanos(ns);
+                    cumul += dur;
+                    writeln!(f, "{} {}  {}", DisplayDur(dur, None), DisplayDur(cumul, None), desc)?;
+                }
+            }
+            if let Some(rest) = total.checked_sub(cumul) {
+                writeln!(f, "{} {}  (self)", DisplayDur(rest, None), DisplayDur(total, None))?;
+            }
+            writeln!(f, "======== ========  ==================================")
+        }
+    }
+
+    /// Display adapter for `PassTimes::display_tree()`.
+    struct Tree<'a>(&'a PassTimes);
+
+    impl<'a> Tree<'a> {
+        /// Write the children of `parent` indented by `depth`, followed by their own children.
+        ///
+        /// `path` holds the passes from the root to `parent`. Passes already on it are skipped,
+        /// so a pass nested inside itself doesn't recurse forever.
+        fn write_children(
+            &self,
+            f: &mut fmt::Formatter,
+            parent: Pass,
+            depth: usize,
+            path: &mut Vec<Pass>,
+        ) -> fmt::Result {
+            path.push(parent);
+            for (&pass, desc) in PASSES.iter().zip(&DESCRIPTIONS) {
+                if path.contains(&pass) {
+                    continue;
+                }
+                if let Some(&ns) = self.0.edges.get(&(parent, pass)) {
+                    writeln!(
+                        f,
+                        "{}  {:indent$}{}",
+                        DisplayDur(from_nanos(ns), None),
+                        "",
+                        desc,
+                        indent = 2 * depth
+                    )?;
+                    self.write_children(f, pass, depth + 1, path)?;
+                }
+            }
+            path.pop();
+            Ok(())
+        }
+    }
+
+    impl<'a> fmt::Display for Tree<'a> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            writeln!(f, "========  ==================================")?;
+            writeln!(f, "   Total  Pass")?;
+            writeln!(f, "--------  ----------------------------------")?;
+            for ((&pass, desc), time) in PASSES.iter().zip(&DESCRIPTIONS).zip(&self.0.pass) {
+                // The time spent in this pass when it wasn't nested in another pass.
+                let nested_ns: u64 = self.0
+                    .edges
+                    .iter()
+                    .filter(|&(&(_, child), _)| child == pass)
+                    .map(|(_, &ns)| ns)
+                    .sum();
+                let top_ns = time.total_ns.saturating_sub(nested_ns);
+                if top_ns == 0 {
+                    continue;
+                }
+                writeln!(f, "{}  {}", DisplayDur(from_nanos(top_ns), None), desc)?;
+                self.write_children(f, pass, 1, &mut Vec::new())?;
+            }
+            writeln!(f, "========  ==================================")
+        }
+    }
+
+    /// Display adapter for `PassTimes::report_by_count()`.
+    struct ByCount<'a>(&'a PassTimes);
+
+    impl<'a> fmt::Display for ByCount<'a> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            let mut rows: Vec<_> = self.0.rows().collect();
+            // Stable sort keeps definition order for ties.
+            rows.sort_by_key(|row| Reverse(row.count));
+
+            writeln!(f, "======== ======== ========  ==================================")?;
+            writeln!(f, "   Count    Total  Avg(us)  Pass")?;
+            writeln!(f, "-------- -------- --------  ----------------------------------")?;
+            for row in rows {
+                let avg = secs(row.total) * 1e6 / row.count as f64;
+                writeln!(
+                    f,
+                    "{:8} {} {:8.1}  {}",
+                    row.count,
+                    DisplayDur(row.total, None),
+                    avg,
+                    DESCRIPTIONS[row.pass.idx()]
+                )?;
+            }
+            writeln!(f, "======== ======== ========  ==================================")
+        }
+    }
+
+    /// Combine two optional effects.
+    fn add_effects(a: Option<u64>, b: Option<u64>) -> Option<u64> {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(a + b),
+            (a, None) => a,
+            (None, b) => b,
+        }
+    }
+
+    /// A unit of time used to display durations in timing reports.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum TimeUnit {
+        /// Nanoseconds.
+        Nanos,
+        /// Microseconds.
+        Micros,
+        /// Milliseconds.
+        Millis,
+        /// Seconds.
+        Secs,
+    }
+
+    impl TimeUnit {
+        /// Get the number of nanoseconds in this unit.
+        fn nanos(self) -> u64 {
+            match self {
+                TimeUnit::Nanos => 1,
+                TimeUnit::Micros => 1_000,
+                TimeUnit::Millis => 1_000_000,
+                TimeUnit::Secs => 1_000_000_000,
+            }
+        }
+
+        /// Get the unit suffix, padded to two columns.
+        fn suffix(self) -> &'static str {
+            match self {
+                TimeUnit::Nanos => "ns",
+                TimeUnit::Micros => "us",
+                TimeUnit::Millis => "ms",
+                TimeUnit::Secs => "s ",
+            }
+        }
+
+        /// Get the largest unit that `ns` is at least one of.
+        fn auto(ns: u64) -> TimeUnit {
+            [TimeUnit::Secs, TimeUnit::Millis, TimeUnit::Micros]
+                .iter()
+                .cloned()
+                .find(|unit| ns >= unit.nanos())
+                .unwrap_or(TimeUnit::Nanos)
+        }
+    }
+
+    /// Display a duration with two decimals and a unit suffix, right-aligned in 8 columns.
+    ///
+    /// The unit is picked to fit the duration when none is given.
+    struct DisplayDur(Duration, Option<TimeUnit>);
+
+    impl fmt::Display for DisplayDur {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            let ns = nanos(self.0);
+            let unit = self.1.unwrap_or_else(|| TimeUnit::auto(ns));
+            if unit == TimeUnit::Nanos {
+                return write!(f, "{:6}{}", ns, unit.suffix());
+            }
+            // Round to the nearest hundredth of the unit.
+            let hundredth = unit.nanos() / 100;
+            let hundredths = (ns + hundredth / 2) / hundredth;
+            write!(f, "{:3}.{:02}{}", hundredths / 100, hundredths % 100, unit.suffix())
+        }
+    }
+
+    /// The timing report lists the passes that have run in a table. It is empty when no pass
+    /// has been timed, e.g. when timing is disabled, so it doesn't clutter the output.
+    impl fmt::Display for PassTimes {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            self.write_report(f, false, None, false)
+        }
+    }
+
+    /// Display adapter for `PassTimes::display_with_unit()`.
+    struct WithUnit<'a>(&'a PassTimes, TimeUnit);
+
+    impl<'a> fmt::Display for WithUnit<'a> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            self.0.write_report(f, false, Some(self.1), false)
+        }
+    }
+
+    /// Display adapter for `PassTimes::display_sorted()`.
+    struct Sorted<'a>(&'a PassTimes);
+
+    impl<'a> fmt::Display for Sorted<'a> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            self.0.write_report(f, true, None, false)
+        }
+    }
+
+    /// Display adapter for `PassTimes::display_with_stats()`.
+    struct WithStats<'a>(&'a PassTimes);
+
+    impl<'a> fmt::Display for WithStats<'a> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            self.0.write_report(f, false, None, true)
+        }
+    }
+
+    impl PassTimes {
+        /// Write the timing report to `f`, with the passes in definition order or sorted by
+        /// decreasing self time, times in `unit` or auto-scaled per row, and optionally the
+        /// standard deviation of the single run times.
+        ///
+        /// Nothing is written if no pass has been timed.
+        fn write_report(
+            &self,
+            f: &mut fmt::Formatter,
+            sorted: bool,
+            unit: Option<TimeUnit>,
+            stats: bool,
+        ) -> fmt::Result {
+            if self.is_empty() {
+                return Ok(());
+            }
+            let mut rule = String::from("======== ======== ======== ========");
+            let mut title = String::from("   Total     Self   %Total    Count");
+            let mut dash = String::from("-------- -------- -------- --------");
+            if stats {
+                rule += " ========";
+                title += "   StdDev";
+                dash += " --------";
+            }
+            // The effect column is only shown when some pass has recorded an effect.
+            let effects = self.times().any(|time| time.effect.is_some());
+            if effects {
+                rule += " ========";
+                title += "   Effect";
+                dash += " --------";
+            }
+
+            // Percentages are relative to the time spent in top-level passes.
+            let grand_ns = self.top_level_ns();
+
+            if !self.isas.is_empty() {
+                writeln!(f, "Target ISA: {}", self.isas.join(", "))?;
+            }
+            writeln!(f, "{}  ==================================", rule)?;
+            writeln!(f, "{}  Pass", title)?;
+            writeln!(f, "{}  ----------------------------------", dash)?;
+
+            // Omit passes that haven't run.
+            let mut order: Vec<(usize, &PassTime)> = self.times()
+                .enumerate()
+                .filter(|&(_, time)| time.total_ns > 0)
+                .collect();
+            if sorted {
+                // Stable sort keeps definition order for ties.
+                order.sort_by_key(|&(_, time)| Reverse(time.self_time()));
+            }
+            for (slot, time) in order {
+                let desc = slot_desc(slot);
+
+                let percent = if grand_ns > 0 {
+                    time.total_ns as f64 * 100.0 / grand_ns as f64
+                } else {
+                    0.0
+                };
+                write!(
+                    f,
+                    "{} {} {:7.1}% {:8}",
+                    DisplayDur(time.total(), unit),
+                    DisplayDur(time.self_time(), unit),
+                    percent,
+                    time.count
+                )?;
+                if stats {
+                    write!(f, " {}", DisplayDur(time.stddev(), unit))?;
+                }
+                if effects {
+                    match time.effect {
+                        Some(n) => write!(f, " {:8}", n)?,
+                        None => write!(f, "         ")?,
+                    }
+                }
+                writeln!(f, "  {}", desc)?;
+            }
+            writeln!(f, "{}  ==================================", rule)
+        }
+    }
+
+    /// Is timing enabled? When disabled, `start_pass` returns inert tokens.
+    pub(super) static ENABLED: AtomicBool = AtomicBool::new(true);
+
+    /// Are timings being recorded? See `set_recording()`.
+    static RECORDING: AtomicBool = AtomicBool::new(true);
+
+    /// Bit set of the predefined passes that are timed, indexed by `Pass::idx()`. See
+    /// `set_enabled_passes()`.
+    static PASS_MASK: AtomicU64 = AtomicU64::new(!0);
+
+    // The pass mask must have a bit for each predefined pass.
+    const _: () = assert!(NUM_PASSES <= 64);
+
+    /// Are timings accumulated globally? See `set_global()`.
+    static GLOBAL_MODE: AtomicBool = AtomicBool::new(false);
+
+    /// Timings accumulated by all threads in global mode.
+    static GLOBAL: GlobalTimes = GlobalTimes::new();
+
+    /// Start and limit of the time budget shared by all threads. See `set_budget()`.
+    static BUDGET: Mutex<Option<(Timestamp, Duration)>> = Mutex::new(None);
+
+    /// Get the description of the pass in `slot`. See `PassTimes::time()`.
+    fn slot_desc(slot: usize) -> &'static str {
+        DESCRIPTIONS
+            .get(slot)
+            .cloned()
+            .unwrap_or_else(|| custom::name(slot - NUM_PASSES))
+    }
+
+    /// Information about passes in a single thread.
+    ///
+    /// `RUNNING` holds the slots of all the running passes, while `STACK` only holds the
+    /// predefined ones. Registered passes are transparent to the nesting of predefined passes
+    /// recorded in `PassTimes::edges` and `PassTimes::stacks`.
+    thread_local!{
+        static RUNNING: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+        static ACTIVE_DEPTH: RefCell<[u32; NUM_PASSES]> = const { RefCell::new([0; NUM_PASSES]) };
+        static STACK: RefCell<Vec<Pass>> = const { RefCell::new(Vec::new()) };
+        static PASS_TIME: RefCell<PassTimes> = RefCell::new(Default::default());
+        static SKIP: Cell<u32> = const { Cell::new(0) };
+    }
+
+    /// Start timing `pass` as a child of the currently running pass, if any.
+    ///
+    /// This function is called by the publicly exposed pass functions.
+    pub(super) fn start_pass(pass: Pass) -> TimingToken {
+        start_slot(pass, pass.idx())
+    }
+
+    /// Start timing the registered pass `pass` as a child of the currently running pass, if any.
+    pub(super) fn start_custom(pass: DynPass) -> TimingToken {
+        start_slot(Pass::None, NUM_PASSES + pass.idx())
+    }
+
+    /// Start timing the pass in `slot`, where `pass` is the corresponding predefined pass or
+    /// `Pass::None` for registered passes.
+    fn start_slot(pass: Pass, slot: usize) -> TimingToken {
+        let active = if ENABLED.load(Ordering::Relaxed) && RECORDING.load(Ordering::Relaxed) &&
+            (pass == Pass::None || PASS_MASK.load(Ordering::Relaxed) & (1 << pass.idx()) != 0)
+        {
+            Some(ActivePass::start(slot, false))
+        } else {
+            None
+        };
+        TimingToken::new(pass, active)
+    }
+
+    /// Accumulate timings collected elsewhere, e.g. by independent compilations, like
+    /// `PassTimes::add()`.
+    impl<'a> ops::AddAssign<&'a PassTimes> for PassTimes {
+        fn add_assign(&mut self, other: &'a PassTimes) {
+            PassTimes::add(self, other)
+        }
+    }
+
+    impl<'a> ops::Add<&'a PassTimes> for PassTimes {
+        type Output = PassTimes;
+
+        fn add(mut self, other: &'a PassTimes) -> PassTimes {
+            self += other;
+            self
+        }
+    }
+
+    impl ops::Add for PassTimes {
+        type Output = PassTimes;
+
+        fn add(self, other: PassTimes) -> PassTimes {
+            self + &other
+        }
+    }
+
+    impl TimingToken {
+        /// Create the token of `pass`, timing it with `active` if any.
+        fn new(pass: Pass, active: Option<ActivePass>) -> Self {
+            ACTIVE_DEPTH.with(|rc| if let Some(depth) = rc.borrow_mut().get_mut(pass.idx()) {
+                *depth += 1;
+            });
+            TimingToken { pass, active }
+        }
+
+        /// Stop the clock of this pass until `resume()` is called.
+        ///
+        /// The time spent while paused isn't accounted to the pass. Passes started while it is
+        /// paused still count as its children, so they shouldn't be run in the paused interval.
+        /// Pausing a paused token does nothing.
+        pub fn pause(&mut self) {
+            if let Some(ref mut active) = self.active {
+                if let Some(start) = active.start.take() {
+                    active.elapsed_ns += nanos(start.elapsed());
+                }
+            }
+        }
+
+        /// Restart the clock of this pass after `pause()`. Resuming a running token does
+        /// nothing.
+        pub fn resume(&mut self) {
+            if let Some(ref mut active) = self.active {
+                if active.start.is_none() {
+                    active.start = Some(clock::now());
+                }
+            }
+        }
+    }
+
+    impl Drop for TimingToken {
+        fn drop(&mut self) {
+            ACTIVE_DEPTH.with(|rc| if let Some(depth) = rc.borrow_mut().get_mut(self.pass.idx()) {
+                *depth -= 1;
+            });
+        }
+    }
+
+    /// Is `pass` running on the current thread?
+    ///
+    /// This returns true if a timing token for `pass` is alive anywhere in the current thread's
+    /// stack of passes, not just at the top. Passes can use it to detect re-entrancy, e.g. to
+    /// avoid recomputing a dominator tree while already computing one.
+    pub fn is_active(pass: Pass) -> bool {
+        ACTIVE_DEPTH.with(|rc| rc.borrow().get(pass.idx()).cloned().unwrap_or(0) > 0)
+    }
+
+    impl ActivePass {
+        fn start(slot: usize, calibrating: bool) -> Self {
+            let pass = PASSES.get(slot).cloned();
+            let prev = RUNNING.with(|rc| {
+                let mut running = rc.borrow_mut();
+                let prev = running.last().cloned();
+                running.push(slot);
+                prev
+            });
+            if let Some(pass) = pass {
+                STACK.with(|rc| rc.borrow_mut().push(pass));
+            }
+            dbg!(
+                "timing: Starting {}, (during {})",
+                slot_desc(slot),
+                prev.map_or("<no pass>", slot_desc)
+            );
+            let traced = !calibrating && pass.is_some() && trace::enabled();
+            if let (true, Some(pass)) = (traced, pass) {
+                trace::record(pass, true);
+            }
+            ActivePass {
+                start: Some(clock::now()),
+                elapsed_ns: 0,
+                slot,
+                traced,
+                calibrating,
+                #[cfg(feature = "otel")]
+                _span: pass.filter(|_| !calibrating).map(otel::PassSpan::enter),
+                #[cfg(feature = "tracing")]
+                _tracing_span: pass.filter(|_| !calibrating).map(tracing::PassSpan::enter),
+            }
+        }
+
+        /// Account for this run of the pass taking `duration` nanoseconds, like `accumulate()`.
+        fn accumulate(&self, parent: Option<usize>, path: &[Pass], duration: u64) {
+            if self.calibrating {
+                PASS_TIME.with(|rc| rc.borrow_mut().end_pass(self.slot, parent, path, duration))
+            } else {
+                accumulate(self.slot, parent, path, duration)
+            }
+        }
+    }
+
+    /// Dropping a timing token indicated the end of the pass.
+    ///
+    /// Tokens should be dropped in LIFO order. If one is dropped while a pass started after it is
+    /// still running, its pass is removed from the middle of the stack of running passes and the
+    /// innermost remaining pass becomes current again, so the accumulated timings stay
+    /// consistent.
+    impl Drop for ActivePass {
+        fn drop(&mut self) {
+            let duration = self.elapsed_ns + self.start.map_or(0, |start| nanos(start.elapsed()));
+            let pass = PASSES.get(self.slot).cloned();
+            if let (true, Some(pass)) = (self.traced, pass) {
+                trace::record(pass, false);
+            }
+            dbg!("timing: Ending {}", slot_desc(self.slot));
+            let parent = RUNNING.with(|rc| {
+                let mut running = rc.borrow_mut();
+                let pos = match running.iter().rposition(|&slot| slot == self.slot) {
+                    Some(pos) => pos,
+                    None => {
+                        dbg!(
+                            "timing: {} is not running, timing it as a top-level pass",
+                            slot_desc(self.slot)
+                        );
+                        return None;
+                    }
+                };
+                if pos + 1 != running.len() {
+                    dbg!(
+                        "timing: Warning: {} ended out of order during {}",
+                        slot_desc(self.slot),
+                        slot_desc(running[running.len() - 1])
+                    );
+                }
+                running.remove(pos);
+                pos.checked_sub(1).map(|pos| running[pos])
+            });
+            let pass = match pass {
+                Some(pass) => pass,
+                None => return self.accumulate(parent, &[], duration),
+            };
+            STACK.with(|rc| {
+                let mut stack = rc.borrow_mut();
+                match stack.iter().rposition(|&p| p == pass) {
+                    Some(pos) => {
+                        self.accumulate(parent, &stack[..pos + 1], duration);
+                        stack.remove(pos);
+                    }
+                    None => self.accumulate(parent, &[pass], duration),
+                }
+            })
+        }
+    }
+
+    impl PassTimes {
+        /// Account for a run of the pass in `slot` taking `duration` nanoseconds.
+        ///
+        /// The pass ran directly inside the pass in the `parent` slot, if any. If it is a
+        /// predefined pass, `path` holds the running predefined passes from the outermost one to
+        /// it. Otherwise, `path` is empty.
+        pub(super) fn end_pass(
+            &mut self,
+            slot: usize,
+            parent: Option<usize>,
+            path: &[Pass],
+            duration: u64,
+        ) {
+            {
+                let time = self.time_mut(slot);
+                time.total_ns += duration;
+                time.add_runs(1, duration, duration);
+                time.sq_ns += duration as f64 * duration as f64;
+            }
+            if histogram::enabled() {
+                self.histograms.entry(slot).or_default().record(duration);
+            }
+            if let Some(parent) = parent {
+                self.time_mut(parent).child_ns += duration;
+            }
+            if path.is_empty() {
+                return;
+            }
+            if path.len() >= 2 {
+                let edge = (path[path.len() - 2], path[path.len() - 1]);
+                *self.edges.entry(edge).or_insert(0) += duration;
+            }
+            match self.stacks.get_mut(path) {
+                Some(ns) => *ns += duration,
+                None => {
+                    self.stacks.insert(path.to_vec(), duration);
+                }
+            }
+        }
+    }
+
+    /// Check that none of the timing tokens on the current thread are still alive.
+    ///
+    /// This is meant to be called by test harnesses after a compilation has finished. Tokens
+    /// dropped out of order are recovered from when they are dropped, so this only reports
+    /// tokens that are still alive.
+    pub fn check_discipline() -> Result<(), String> {
+        match RUNNING.with(|rc| rc.borrow().last().cloned()) {
+            None => Ok(()),
+            Some(slot) => Err(format!("timing token still active for pass: {}", slot_desc(slot))),
+        }
+    }
+
+    /// Get a copy of the current accumulated pass timings for the current thread, without
+    /// resetting them.
+    ///
+    /// Passes that are still running aren't included until they end. Unlike `take_current()`,
+    /// this isn't affected by `skip_next()`.
+    pub fn snapshot() -> PassTimes {
+        PASS_TIME.with(|rc| rc.borrow().clone())
+    }
+
+    /// Take the current accumulated pass timings and reset the timings for the current thread.
+    ///
+    /// If cycles are being skipped with `skip_next()`, the timings are discarded and empty
+    /// timings are returned instead.
+    pub fn take_current() -> PassTimes {
+        let times = PASS_TIME.with(|rc| mem::take(&mut *rc.borrow_mut()));
+        overhead::check(&times);
+        let skip = SKIP.with(Cell::get);
+        if skip > 0 {
+            SKIP.with(|s| s.set(skip - 1));
+            dbg!("timing: Discarding warm-up timings, {} more to skip", skip - 1);
+            return PassTimes::default();
+        }
+        times
+    }
+
+    /// Replace the accumulated pass timings for the current thread with `times`, and return the
+    /// previous ones.
+    ///
+    /// This sets the timings of the current thread aside while it does unrelated work. Unlike
+    /// `take_current()`, it doesn't end an accumulation cycle: it isn't affected by `skip_next()`,
+    /// and the timings aren't checked against the overhead limit.
+    pub fn swap_current(times: PassTimes) -> PassTimes {
+        PASS_TIME.with(|rc| mem::replace(&mut *rc.borrow_mut(), times))
+    }
+
+    /// Discard the timings of the next `n` compiles on the current thread.
+    ///
+    /// A compile here is one accumulation cycle: everything timed on the current thread up to
+    /// and including the next call to `take_current()`. The next `n` calls to `take_current()`
+    /// discard the accumulated timings and return empty timings. This excludes warm-up compiles,
+    /// which are skewed by cold caches and lazy initialization, from benchmark results.
+    ///
+    /// Unlike `set_recording()`, passes are still timed while skipping, and the setting only
+    /// affects the current thread. Calling this again replaces the remaining count.
+    pub fn skip_next(n: u32) {
+        SKIP.with(|s| s.set(n))
+    }
+
+    /// Record that the passes timed on the current thread compile for the target ISA `name`.
+    ///
+    /// The ISA names are kept with the accumulated timings, shown in the header of the timing
+    /// report, and saved with them. Timings that are added together keep the names of all their
+    /// ISAs.
+    pub fn set_isa(name: &str) {
+        if GLOBAL_MODE.load(Ordering::Relaxed) {
+            GLOBAL.add_isa(name)
+        } else {
+            PASS_TIME.with(|rc| rc.borrow_mut().add_isa(name))
+        }
+    }
+
+    /// Enable or disable global accumulation of pass timings.
+    ///
+    /// By default, each thread accumulates its own timings, which must be taken with
+    /// `take_current()` on that thread. In global mode, the timings of all threads are
+    /// accumulated into a single shared total instead, which can be taken from any thread with
+    /// `take_global()`. This suits long-lived worker pools whose threads never finish.
+    ///
+    /// The shared total is updated without taking a lock, so only the counters of the
+    /// predefined passes and their effects are accumulated in global mode: The timings of
+    /// registered passes, the nesting of the passes, and the histograms of their run times
+    /// aren't recorded.
+    ///
+    /// Passes are accumulated according to the mode when they end.
+    pub fn set_global(enable: bool) {
+        GLOBAL_MODE.store(enable, Ordering::Relaxed);
+    }
+
+    /// Are pass timings accumulated globally? See `set_global()`.
+    pub fn is_global() -> bool {
+        GLOBAL_MODE.load(Ordering::Relaxed)
+    }
+
+    /// Take the pass timings accumulated by all threads in global mode, and reset them.
+    pub fn take_global() -> PassTimes {
+        GLOBAL.take()
+    }
+
+    /// Account for a run of the pass in `slot`, see `PassTimes::end_pass()`. The run is added to
+    /// the shared total in global mode, or to the current thread's timings otherwise.
+    fn accumulate(slot: usize, parent: Option<usize>, path: &[Pass], duration: u64) {
+        if GLOBAL_MODE.load(Ordering::Relaxed) {
+            GLOBAL.end_pass(slot, parent, duration)
+        } else {
+            PASS_TIME.with(|rc| rc.borrow_mut().end_pass(slot, parent, path, duration))
+        }
+    }
+
+    /// Enable or disable timing in all threads.
+    ///
+    /// Timing is enabled by default. While it is disabled, the pass functions return inert
+    /// tokens that don't read the clock or touch the accumulated timings, so instrumented code
+    /// runs with next to no overhead. This is meant to be set once at startup, e.g. from a
+    /// command line flag, so the same binary can be used for both production compiles and
+    /// profiling. Use `set_recording()` to pause measurements temporarily instead.
+    ///
+    /// Timing can also be disabled automatically by `set_overhead_limit()`. Enabling timing
+    /// again rearms the limit.
+    pub fn set_enabled(enable: bool) {
+        if enable {
+            overhead::rearm();
+        }
+        ENABLED.store(enable, Ordering::Relaxed);
+    }
+
+    /// Is timing enabled? See `set_enabled()`.
+    pub fn is_enabled() -> bool {
+        ENABLED.load(Ordering::Relaxed)
+    }
+
+    /// Get the bit set of `passes` for the pass mask.
+    pub(super) fn pass_mask(passes: &[Pass]) -> u64 {
+        passes
+            .iter()
+            .filter(|&&pass| pass != Pass::None)
+            .fold(0, |mask, pass| mask | 1 << pass.idx())
+    }
+
+    /// Only time `passes` in all threads.
+    ///
+    /// The pass functions of the other predefined passes return inert tokens, so the overhead of
+    /// timing the many fine-grained passes can be avoided when only a few passes are of
+    /// interest. The time spent in the other passes is accounted to the timed pass they run in,
+    /// if any. Passes registered with `register_pass()` are always timed.
+    ///
+    /// All passes are timed by default. Call this with `Pass::all()` to time them all again.
+    pub fn set_enabled_passes(passes: &[Pass]) {
+        PASS_MASK.store(pass_mask(passes), Ordering::Relaxed);
+    }
+
+    /// Is `pass` timed? See `set_enabled_passes()`.
+    pub fn is_pass_enabled(pass: Pass) -> bool {
+        pass != Pass::None && PASS_MASK.load(Ordering::Relaxed) & pass_mask(&[pass]) != 0
+    }
+
+    /// Pause or resume the recording of pass timings in all threads.
+    ///
+    /// While recording is paused, the pass functions return inert tokens and nothing is
+    /// accumulated, so compilations can run without being measured, e.g. to warm up caches.
+    /// Passes that were started before recording was paused are still accounted for when they
+    /// end.
+    pub fn set_recording(recording: bool) {
+        RECORDING.store(recording, Ordering::Relaxed);
+    }
+
+    /// Are pass timings being recorded? See `set_recording()`.
+    pub fn is_recording() -> bool {
+        RECORDING.load(Ordering::Relaxed)
+    }
+
+    /// Measure the average cost of timing a single pass on the current thread.
+    ///
+    /// This repeatedly starts timing a pass and drops its token, including the bookkeeping of
+    /// the passes running on the current thread. The runs are accumulated in scratch timings of
+    /// the current thread, even in global mode, and they aren't traced or reported as spans, so
+    /// the accumulated timings, the global timings, traces and spans are unaffected. The
+    /// measurement is taken even if timing is disabled.
+    pub fn calibrate() -> Duration {
+        const ROUNDS: u32 = 10_000;
+        let pass = Pass::process_file;
+        let saved = swap_current(PassTimes::default());
+        let start = clock::now();
+        for _ in 0..ROUNDS {
+            drop(TimingToken::new(pass, Some(ActivePass::start(pass.idx(), true))));
+        }
+        let cost = start.elapsed() / ROUNDS;
+        swap_current(saved);
+        cost
+    }
+
+    /// Record the effect of running `pass` on the current thread.
+    ///
+    /// Passes can use this to report a benefit metric to put their cost in context. For example,
+    /// dead code elimination reports the number of instructions removed, and the register
+    /// allocator's spilling pass reports the number of values spilled. The effects reported for
+    /// a pass are summed, and they are shown in an extra column of the timing report.
+    pub fn record_effect(pass: Pass, amount: u64) {
+        if !RECORDING.load(Ordering::Relaxed) {
+            return;
+        }
+        if GLOBAL_MODE.load(Ordering::Relaxed) {
+            return GLOBAL.record_effect(pass.idx(), amount);
+        }
+        PASS_TIME.with(|rc| if let Some(time) = rc.borrow_mut().pass.get_mut(pass.idx()) {
+            time.effect = add_effects(time.effect, Some(amount));
+        })
+    }
+
+    /// Set a time budget for the compilation work of the process, starting now.
+    ///
+    /// The budget is shared by all threads: It runs out after `limit` of wall-clock time, however
+    /// many threads are compiling meanwhile. It is purely advisory. Passes that can do a variable
+    /// amount of work can consult `budget_remaining()` to decide whether another round of
+    /// optimization is affordable.
+    ///
+    /// The start of the budget is read from the clock of the current thread. If a clock was
+    /// installed with `clock::set_clock()`, only threads with a clock installed see the budget
+    /// run out.
+    pub fn set_budget(limit: Duration) {
+        *BUDGET.lock().unwrap() = Some((clock::now(), limit));
+    }
+
+    /// Remove the time budget of the process.
+    pub fn clear_budget() {
+        *BUDGET.lock().unwrap() = None;
+    }
+
+    /// Get the time left of the budget set by `set_budget()` on any thread.
+    ///
+    /// Returns `None` if no budget has been set, and a zero duration once the budget is exhausted.
+    pub fn budget_remaining() -> Option<Duration> {
+        BUDGET.lock().unwrap().map(|(start, limit)| {
+            limit.checked_sub(start.elapsed()).unwrap_or_default()
+        })
+    }
+
+    /// Sum pass timings collected from multiple threads into a new `PassTimes`.
+    ///
+    /// This doesn't touch the accumulated timings of the current thread.
+    pub fn collect_from<I>(threads: I) -> PassTimes
+    where
+        I: IntoIterator<Item = PassTimes>,
+    {
+        threads.into_iter().fold(PassTimes::default(), |mut sum, times| {
+            sum.add(&times);
+            sum
+        })
+    }
+
+    /// Add `timings` to the accumulated timings for the current thread.
+    pub fn add_to_current(times: &PassTimes) {
+        PASS_TIME.with(|rc| rc.borrow_mut().add(times))
+    }
+
+    /// Add `timings` to the accumulated timings for the current thread, and record them as
+    /// contributed by `label`. See `PassTimes::add_labeled()`.
+    pub fn add_to_current_labeled(label: &str, times: &PassTimes) {
+        PASS_TIME.with(|rc| rc.borrow_mut().add_labeled(label, times))
+    }
+}
+
+/// Dummy implementation details for the `no-timing` feature.
+///
+/// Nothing is measured or accumulated, and the pass functions compile down to nothing.
+#[cfg(feature = "no-timing")]
+mod details {
+    use super::{Pass, PassRow};
+    use std::fmt;
+    use std::iter;
+
+    /// A timing token is responsible for timing the currently running pass. Timing is compiled
+    /// out, so this is an empty token.
+    pub struct TimingToken;
+
+    impl TimingToken {
+        /// Stop the clock of this pass. This does nothing.
+        #[inline(always)]
+        pub fn pause(&mut self) {}
+
+        /// Restart the clock of this pass. This does nothing.
+        #[inline(always)]
+        pub fn resume(&mut self) {}
+    }
+
+    /// Accumulated timing for all passes. Timing is compiled out, so this is always empty.
+    #[derive(Clone, Default)]
+    pub struct PassTimes;
+
+    impl PassTimes {
+        /// Add the timings in `other` to `self`. This does nothing.
+        pub fn add(&mut self, _other: &PassTimes) {}
+
+        /// Add the timings in `other` to `self`, contributed by `label`. This does nothing.
+        pub fn add_labeled(&mut self, _label: &str, _other: &PassTimes) {}
+
+        /// Iterate over the passes that have run, of which there are none.
+        pub fn rows(&self) -> impl Iterator<Item = PassRow> {
+            iter::empty()
+        }
+
+        /// Get the fraction of the total self time spent in each pass, which is always empty.
+        pub fn fractions(&self) -> Vec<(Pass, f64)> {
+            Vec::new()
+        }
+
+        /// Encode these timings in the timing file format, with no passes.
+        pub fn encode(&self) -> String {
+            "cretonne-timing 1\n".to_string()
+        }
+
+        /// Decode timings in the timing file format. The timings are discarded.
+        pub fn load_lenient(_data: &str) -> Result<(PassTimes, Vec<String>), String> {
+            Ok((PassTimes, Vec::new()))
+        }
+
+        /// Encode these timings as an empty JSON object.
+        pub fn to_json(&self) -> String {
+            "{}".to_string()
+        }
+    }
+
+    /// The timing report is empty.
+    impl fmt::Display for PassTimes {
+        fn fmt(&self, _f: &mut fmt::Formatter) -> fmt::Result {
+            Ok(())
+        }
+    }
+
+    /// Statistics of the timings of repeated runs. Timing is compiled out, so there are none.
+    #[derive(Clone, Default)]
+    pub struct PassTimesStats;
+
+    impl PassTimesStats {
+        /// Add the timings of one run. This does nothing.
+        pub fn add_sample(&mut self, _times: &PassTimes) {}
+    }
+
+    /// Start timing `pass`. This does nothing.
+    #[inline(always)]
+    pub(super) fn start_pass(_pass: Pass) -> TimingToken {
+        TimingToken
+    }
+
+    /// Take the current accumulated pass timings, which are always empty.
+    pub fn take_current() -> PassTimes {
+        PassTimes
+    }
+
+    /// Get the current accumulated pass timings, which are always empty.
+    pub fn snapshot() -> PassTimes {
+        PassTimes
+    }
+
+    /// Replace the accumulated pass timings for the current thread, which are always empty.
+    pub fn swap_current(_times: PassTimes) -> PassTimes {
+        PassTimes
+    }
+
+    /// Add `timings` to the accumulated timings for the current thread. This does nothing.
+    pub fn add_to_current(_times: &PassTimes) {}
+
+    /// Is timing enabled? Timing is compiled out, so it never is.
+    pub fn is_enabled() -> bool {
+        false
+    }
+
+    /// Check that no timing tokens are alive. Tokens are empty, so this always succeeds.
+    pub fn check_discipline() -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Record the target ISA of the passes timed on the current thread. This does nothing.
+    pub fn set_isa(_name: &str) {}
+
+    /// Record the effect of running `pass` on the current thread. This does nothing.
+    #[inline(always)]
+    pub fn record_effect(_pass: Pass, _amount: u64) {}
+
+    /// A compilation pass registered at runtime. Timing is compiled out, so all registered
+    /// passes are the same.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub struct DynPass;
+
+    impl DynPass {
+        /// Start timing this pass. This does nothing.
+        #[inline(always)]
+        pub fn start(self) -> TimingToken {
+            TimingToken
+        }
+    }
+
+    /// Register a pass that can be timed. This does nothing.
+    pub fn register_pass(_name: &'static str) -> DynPass {
+        DynPass
+    }
+}
+
+#[cfg(all(test, not(feature = "no-timing")))]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn display() {
+        assert_eq!(Pass::None.to_string(), "<no pass>");
+        assert_eq!(Pass::regalloc.to_string(), "Register allocation");
+    }
+
+    #[test]
+    fn pass_mask() {
+        assert_eq!(details::pass_mask(&[]), 0);
+        assert_eq!(details::pass_mask(&[Pass::None]), 0);
+        assert_eq!(details::pass_mask(&[Pass::parse_text, Pass::None]), 2);
+        assert_eq!(details::pass_mask(Pass::all()).count_ones() as usize, NUM_PASSES);
+        assert!(is_pass_enabled(Pass::regalloc));
+        assert!(!is_pass_enabled(Pass::None));
+    }
+
+    #[test]
+    fn all_passes() {
+        let all = Pass::all();
+        assert_eq!(all.len(), NUM_PASSES);
+        assert!(!all.contains(&Pass::None));
+        for (idx, &pass) in all.iter().enumerate() {
+            assert_eq!(pass.idx(), idx);
+            assert_eq!(Pass::from_name(pass.name()), Some(pass));
+            assert_eq!(pass.description(), pass.to_string());
+        }
+        assert_eq!(Pass::ra_coloring.description(), "RA coloring");
+        assert_eq!(Pass::from_name("none"), None);
+    }
+
+    #[test]
+    fn budget() {
+        assert_eq!(budget_remaining(), None);
+        set_budget(Duration::from_secs(3600));
+        let left = budget_remaining().unwrap();
+        assert!(left <= Duration::from_secs(3600));
+        assert!(left > Duration::from_secs(3500));
+
+        // The budget is shared with the other threads.
+        let left = ::std::thread::spawn(budget_remaining).join().unwrap().unwrap();
+        assert!(left > Duration::from_secs(3500));
+        set_budget(Duration::default());
+        assert_eq!(budget_remaining(), Some(Duration::default()));
+        clear_budget();
+        assert_eq!(budget_remaining(), None);
+    }
+
+    #[test]
+    fn discipline() {
+        assert_eq!(check_discipline(), Ok(()));
+        {
+            let _tt = regalloc();
+            assert_eq!(
+                check_discipline(),
+                Err("timing token still active for pass: Register allocation".to_string())
+            );
+            let _inner = ra_coloring();
+            assert!(check_discipline().is_err());
+        }
+        assert_eq!(check_discipline(), Ok(()));
+    }
+
+    #[test]
+    fn out_of_order() {
+        take_current();
+        let clock = set_clock_for_testing();
+        let ms = Duration::from_millis;
+        {
+            let file = process_file();
+            clock.advance(ms(1));
+            let compile = compile();
+            clock.advance(ms(2));
+            drop(file);
+            assert_eq!(
+                check_discipline(),
+                Err("timing token still active for pass: Compilation passes".to_string())
+            );
+            clock.advance(ms(4));
+            {
+                let _ = verifier();
+            }
+            drop(compile);
+        }
+        set_clock(None);
+        assert_eq!(check_discipline(), Ok(()));
+
+        // The compile pass outlived its parent, so it is accounted for as a top-level pass.
+        let times = take_current();
+        assert_eq!(
+            times.iter().collect::<Vec<_>>(),
+            [
+                (Pass::process_file, ms(3), ms(3)),
+                (Pass::verifier, ms(0), ms(0)),
+                (Pass::compile, ms(6), ms(6)),
+            ]
+        );
+        assert_eq!(times.display_tree().lines().count(), 7);
+    }
+
+    #[test]
+    fn pause() {
+        take_current();
+        let clock = set_clock_for_testing();
+        let ms = Duration::from_millis;
+        {
+            let mut emit = binemit();
+            clock.advance(ms(1));
+            emit.pause();
+            clock.advance(ms(10));
+            emit.pause();
+            clock.advance(ms(10));
+            emit.resume();
+            clock.advance(ms(2));
+            emit.resume();
+            clock.advance(ms(4));
+        }
+        set_clock(None);
+        let times = take_current();
+        assert_eq!(times.iter().collect::<Vec<_>>(), [(Pass::binemit, ms(7), ms(7))]);
+        assert_eq!(times.stats(Pass::binemit).count, 1);
+    }
+
+    #[test]
+    fn stddev() {
+        take_current();
+        let clock = set_clock_for_testing();
+        let ms = Duration::from_millis;
+        for &t in &[1, 3] {
+            let _emit = binemit();
+            clock.advance(ms(t));
+        }
+        {
+            let _gvn = gvn();
+            clock.advance(ms(5));
+        }
+        set_clock(None);
+        let times = take_current();
+        // The sample standard deviation of 1 ms and 3 ms is sqrt(2) ms.
+        assert_eq!(times.stddev(Pass::binemit), Duration::new(0, 1_414_214));
+        assert_eq!(times.stddev(Pass::gvn), Duration::default());
+        assert_eq!(times.stddev(Pass::licm), Duration::default());
+
+        // The sums of squares are saved with the timings, so merging keeps the variance.
+        let mut decoded = PassTimes::decode(&times.encode()).unwrap();
+        assert_eq!(decoded.stddev(Pass::binemit), times.stddev(Pass::binemit));
+        decoded.add(&times);
+        assert_eq!(decoded.stddev(Pass::binemit), Duration::new(0, 1_154_701));
+
+        let report = times.display_with_stats();
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert!(lines[1].starts_with("   Total     Self   %Total    Count   StdDev  Pass"));
+        assert!(lines.iter().any(|l| l.contains("  1.41ms  Binary machine code emission")));
+        assert!(!times.to_string().contains("StdDev"));
+    }
+
+    #[test]
+    fn phase_summary() {
+        assert_eq!(Pass::ra_coloring.phase(), Some(Phase::Regalloc));
+        assert_eq!(Pass::None.phase(), None);
+
+        let times = PassTimes::decode(
+            "cretonne-timing 1\npass compile 1000 800 1\npass regalloc 800 500 1\n\
+             pass ra_liveness 200 0 1\npass ra_coloring 300 0 1\n\
+             edge compile regalloc 800\nedge regalloc ra_liveness 200\n\
+             edge regalloc ra_coloring 300\n",
+        ).unwrap();
+        let report = times.phase_summary();
+        let lines: Vec<_> = report.lines().collect();
+        assert_eq!(lines.len(), 8);
+        assert_eq!(lines[3], "     0ns      0ns  Frontend");
+        assert_eq!(lines[4], "  1.00us    200ns  Optimization");
+        assert_eq!(lines[5], "   800ns    800ns  Register allocation");
+        assert_eq!(lines[6], "     0ns      0ns  Backend");
+    }
+
+    #[test]
+    fn waterfall() {
+        take_current();
+        {
+            let _file = process_file();
+            let _ = parse_text();
+            let _ = compile();
+        }
+        let times = take_current();
+        let report = times.waterfall(Pass::process_file);
+        let lines: Vec<_> = report.lines().collect();
+        assert_eq!(lines.len(), 7);
+        assert!(lines[1].ends_with("  Processing test file"));
+        assert!(lines[3].ends_with("  Parsing textual Cretonne IR"));
+        assert!(lines[4].ends_with("  Compilation passes"));
+        assert!(lines[5].ends_with("  (self)"));
+
+        // A pass that hasn't run has nothing to show.
+        assert_eq!(times.waterfall(Pass::regalloc), "");
+    }
+
+    #[test]
+    fn rows() {
+        take_current();
+        {
+            let _file = process_file();
+            for _ in 0..3 {
+                let _ = compile();
+            }
+        }
+        let times = take_current();
+        let rows: Vec<_> = times.rows().collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].pass, Pass::process_file);
+        assert_eq!(rows[0].count, 1);
+        assert_eq!(rows[1].pass, Pass::compile);
+        assert_eq!(rows[1].count, 3);
+        assert_eq!(rows[1].total, rows[1].self_time);
+        assert_eq!(rows[0].total, rows[0].self_time + rows[1].total);
+    }
+
+    #[test]
+    fn effects() {
+        take_current();
+        {
+            let _ = compile();
+            let _ = dce();
+            record_effect(Pass::dce, 3);
+            record_effect(Pass::dce, 4);
+        }
+        let times = take_current();
+        let rows: Vec<_> = times.rows().collect();
+        assert_eq!(rows[0].effect, None);
+        assert_eq!(rows[1].effect, Some(7));
+
+        let report = times.to_string();
+        let lines: Vec<_> = report.lines().collect();
+        assert_eq!(lines[1], "   Total     Self   %Total    Count   Effect  Pass");
+        assert!(lines[3].ends_with("        1           Compilation passes"));
+        assert!(lines[4].ends_with("        1        7  Dead code elimination"));
+    }
+
+    #[test]
+    fn percentages() {
+        let times = PassTimes::decode(
+            "cretonne-timing 1\npass process_file 1000000000 800000000 1\n\
+             pass compile 800000000 0 2\n",
+        ).unwrap();
+        let report = times.to_string();
+        let lines: Vec<_> = report.lines().collect();
+        assert_eq!(lines[1], "   Total     Self   %Total    Count  Pass");
+        assert_eq!(
+            lines[3],
+            "  1.00s  200.00ms   100.0%        1  Processing test file"
+        );
+        assert_eq!(
+            lines[4],
+            "800.00ms 800.00ms    80.0%        2  Compilation passes"
+        );
+    }
+
+    #[test]
+    fn units() {
+        let times = PassTimes::decode(
+            "cretonne-timing 1\npass process_file 1234567 1000 1\npass compile 1000 0 1\n",
+        ).unwrap();
+        // Get the total and self times of each row.
+        let times_of = |report: String| -> Vec<(String, String)> {
+            report
+                .lines()
+                .skip(3)
+                .take(2)
+                .map(|line| {
+                    let mut fields = line.split_whitespace().map(str::to_string);
+                    (fields.next().unwrap(), fields.next().unwrap())
+                })
+                .collect()
+        };
+        let pairs = |expected: [(&str, &str); 2]| -> Vec<(String, String)> {
+            expected
+                .iter()
+                .map(|&(a, b)| (a.to_string(), b.to_string()))
+                .collect()
+        };
+
+        assert_eq!(
+            times_of(times.to_string()),
+            pairs([("1.23ms", "1.23ms"), ("1.00us", "1.00us")])
+        );
+        assert_eq!(
+            times_of(times.display_with_unit(TimeUnit::Nanos)),
+            pairs([("1234567ns", "1233567ns"), ("1000ns", "1000ns")])
+        );
+        assert_eq!(
+            times_of(times.display_with_unit(TimeUnit::Micros)),
+            pairs([("1234.57us", "1233.57us"), ("1.00us", "1.00us")])
+        );
+        assert_eq!(
+            times_of(times.display_with_unit(TimeUnit::Secs)),
+            pairs([("0.00s", "0.00s"), ("0.00s", "0.00s")])
+        );
+    }
+
+    #[test]
+    fn sorted() {
+        let times = PassTimes::decode(
+            "cretonne-timing 1\npass process_file 1000000000 800000000 1\n\
+             pass parse_text 200000000 0 1\npass compile 600000000 0 1\n",
+        ).unwrap();
+        let report = times.display_sorted();
+        let passes: Vec<_> = report.lines().skip(3).take(3).map(|l| &l[37..]).collect();
+        assert_eq!(
+            passes,
+            ["Compilation passes", "Processing test file", "Parsing textual Cretonne IR"]
+        );
+        assert_eq!(report.lines().count(), times.to_string().lines().count());
+    }
+
+    #[test]
+    fn iter() {
+        let times = PassTimes::decode(
+            "cretonne-timing 1\npass process_file 1000 800 1\npass compile 800 0 2\n",
+        ).unwrap();
+        assert_eq!(
+            times.iter().collect::<Vec<_>>(),
+            [
+                (Pass::process_file, Duration::new(0, 1000), Duration::new(0, 200)),
+                (Pass::compile, Duration::new(0, 800), Duration::new(0, 800)),
+            ]
+        );
+        assert_eq!(PassTimes::default().iter().count(), 0);
+    }
+
+    #[test]
+    fn tree() {
+        take_current();
+        {
+            let _file = process_file();
+            let _ = parse_text();
+            let _compile = compile();
+            let _regalloc = regalloc();
+            let _ = ra_liveness();
+            let _ = ra_spilling();
+        }
+        let _ = verifier();
+        let report = take_current().display_tree();
+        let passes: Vec<_> = report.lines().skip(3).map(|l| &l[10..]).collect();
+        assert_eq!(
+            passes,
+            [
+                "Processing test file",
+                "  Parsing textual Cretonne IR",
+                "  Compilation passes",
+                "    Register allocation",
+                "      RA liveness analysis",
+                "      RA spilling",
+                "Verify Cretonne IR",
+                "==================================",
+            ]
+        );
+    }
+
+    #[test]
+    fn operators() {
+        let decode = |count| {
+            PassTimes::decode(&format!(
+                "cretonne-timing 1\npass compile 1000 0 {}\nrange compile 1000 1000\nisa intel\n",
+                count
+            )).unwrap()
+        };
+        let mut sum = decode(1) + decode(2);
+        sum += &decode(3);
+        let sum = sum + &PassTimes::default();
+        let stats = sum.stats(Pass::compile);
+        assert_eq!(stats.count, 6);
+        assert_eq!(stats.total, Duration::new(0, 3000));
+        assert_eq!(stats.max, Duration::new(0, 1000));
+        assert_eq!(sum.isas(), ["intel"]);
+    }
+
+    #[test]
+    fn test_clock() {
+        take_current();
+        let clock = set_clock_for_testing();
+        let ms = Duration::from_millis;
+        {
+            let _file = process_file();
+            clock.advance(ms(1));
+            for i in 1..3 {
+                let _compile = compile();
+                clock.advance(ms(5 * i));
+            }
+            clock.advance(ms(2));
+        }
+        set_clock(None);
+
+        let times = take_current();
+        assert_eq!(
+            times.iter().collect::<Vec<_>>(),
+            [
+                (Pass::process_file, ms(18), ms(3)),
+                (Pass::compile, ms(15), ms(15)),
+            ]
+        );
+        let stats = times.stats(Pass::compile);
+        assert_eq!((stats.min, stats.max), (ms(5), ms(10)));
+    }
+
+    #[test]
+    fn zero_length_runs() {
+        take_current();
+        let _clock = set_clock_for_testing();
+        for _ in 0..2 {
+            let _compile = compile();
+        }
+        set_clock(None);
+
+        // Runs that take no time at all still have a range.
+        let mut times = take_current();
+        let stats = times.stats(Pass::compile);
+        assert_eq!(stats.count, 2);
+        assert_eq!((stats.min, stats.max), (Duration::new(0, 0), Duration::new(0, 0)));
+
+        // The range survives saving and merging.
+        times.add(&PassTimes::decode(&times.encode()).unwrap());
+        let other = PassTimes::decode(
+            "cretonne-timing 1\npass compile 5000000 0 1\nrange compile 5000000 5000000\n",
+        ).unwrap();
+        times.add(&other);
+        let stats = times.stats(Pass::compile);
+        assert_eq!(stats.count, 5);
+        assert_eq!((stats.min, stats.max), (Duration::new(0, 0), Duration::from_millis(5)));
+    }
+
+    #[test]
+    fn snapshot() {
+        take_current();
+        let file = process_file();
+        let _ = compile();
+        assert_eq!(super::snapshot().structure_report(), "compile (x1)\n");
+        let _ = compile();
+        assert_eq!(super::snapshot().structure_report(), "compile (x2)\n");
+        drop(file);
+        assert_eq!(take_current().structure_report(), "process_file (x1)\ncompile (x2)\n");
+    }
+
+    #[test]
+    fn accessors() {
+        let times = PassTimes::decode(
+            "cretonne-timing 1\npass process_file 1000 800 1\npass compile 800 0 2\n\
+             pass verifier 300 0 1\n",
+        ).unwrap();
+        assert_eq!(times.total(), Duration::new(0, 1300));
+        assert_eq!(times.self_time(Pass::process_file), Duration::new(0, 200));
+        assert_eq!(times.self_time(Pass::compile), Duration::new(0, 800));
+        assert_eq!(times.self_time(Pass::dce), Duration::default());
+        assert_eq!(times.self_time(Pass::None), Duration::default());
+        assert_eq!(times.total_duration(Pass::process_file), Duration::new(0, 1000));
+        assert_eq!(times.child_duration(Pass::process_file), Duration::new(0, 800));
+        assert_eq!(times.total_nanos(Pass::compile), 800);
+        assert_eq!(times.total_duration(Pass::None), Duration::default());
+        assert_eq!(times.child_duration(Pass::dce), Duration::default());
+        assert_eq!(times.total_nanos(Pass::None), 0);
+        assert!(!times.is_empty());
+        assert!(PassTimes::default().is_empty());
+        assert_eq!(PassTimes::default().to_string(), "");
+        assert_eq!(PassTimes::default().display_sorted(), "");
+        assert_eq!(times.to_string().lines().count(), 7);
+        assert_eq!(PassTimes::default().total(), Duration::default());
+    }
+
+    #[test]
+    fn structure_report() {
+        take_current();
+        {
+            let _file = process_file();
+            let _ = parse_text();
+            let _ = compile();
+            let _ = compile();
+        }
+        assert_eq!(
+            take_current().structure_report(),
+            "process_file (x1)\nparse_text (x1)\ncompile (x2)\n"
+        );
+    }
+
+    #[test]
+    fn collect() {
+        let before = take_current();
+        let _ = compile();
+        let times = take_current();
+        add_to_current(&before);
+
+        let sum = collect_from(vec![take_current(), times, PassTimes::default()]);
+        assert_eq!(sum.structure_report(), "compile (x1)\n");
+        assert_eq!(take_current().structure_report(), "");
+    }
+
+    #[test]
+    fn calibration() {
+        take_current();
+        {
+            // Calibrating inside a pass doesn't add children to it.
+            let _compile = compile();
+            assert!(calibrate() > Duration::default());
+            assert!(!is_active(Pass::process_file));
+        }
+        let times = take_current();
+        let rows: Vec<_> = times.rows().collect();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].pass, Pass::compile);
+        assert_eq!(times.structure_report(), "compile (x1)\n");
+    }
+
+    #[test]
+    fn fractions() {
+        take_current();
+        assert_eq!(take_current().fractions(), Vec::new());
+        {
+            let _file = process_file();
+            let _ = compile();
+        }
+        let fracs = take_current().fractions();
+        assert_eq!(fracs.len(), 2);
+        assert!(fracs[0].1 >= fracs[1].1);
+        assert!((fracs[0].1 + fracs[1].1 - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn compact_representation() {
+        use std::mem::size_of;
+        // Five u64 counters, the sum of squares and the optional effect.
+        assert_eq!(size_of::<details::PassTime>(), 6 * 8 + size_of::<Option<u64>>());
+        assert_eq!(details::from_nanos(details::nanos(Duration::new(3, 5))), Duration::new(3, 5));
+    }
+
+    #[test]
+    fn active() {
+        assert!(!is_active(Pass::verifier));
+        {
+            let _outer = verifier();
+            assert!(is_active(Pass::verifier));
+            assert!(!is_active(Pass::domtree));
+            {
+                let _inner = domtree();
+                assert!(is_active(Pass::verifier));
+                assert!(is_active(Pass::domtree));
+                {
+                    let _again = verifier();
+                    assert!(is_active(Pass::verifier));
+                }
+                assert!(is_active(Pass::verifier));
+            }
+            assert!(!is_active(Pass::domtree));
+            assert!(is_active(Pass::verifier));
+        }
+        assert!(!is_active(Pass::verifier));
+        assert!(!is_active(Pass::None));
+    }
+
+    #[test]
+    fn isa() {
+        take_current();
+        set_isa("riscv");
+        {
+            let _tt = verifier();
+        }
+        set_isa("riscv");
+        let mut times = take_current();
+        assert_eq!(times.isas(), ["riscv"]);
+        assert!(times.to_string().starts_with("Target ISA: riscv\n"));
+
+        set_isa("intel");
+        times.add(&take_current());
+        assert_eq!(times.isas(), ["riscv", "intel"]);
+        assert!(take_current().isas().is_empty());
+    }
+
+    #[test]
+    fn report_by_count() {
+        take_current();
+        {
+            let _file = process_file();
+            for _ in 0..3 {
+                let _ = domtree();
+            }
+        }
+        let report = take_current().report_by_count();
+        let lines: Vec<_> = report.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[1], "   Count    Total  Avg(us)  Pass");
+        assert!(lines[3].starts_with("       3 "));
+        assert!(lines[3].ends_with("  Dominator tree"));
+        assert!(lines[4].starts_with("       1 "));
+        assert!(lines[4].ends_with("  Processing test file"));
+    }
+
+    #[test]
+    fn skip() {
+        take_current();
+        skip_next(2);
+        for _ in 0..2 {
+            let _ = verifier();
+            assert_eq!(take_current().rows().count(), 0);
+        }
+        let _ = verifier();
+        assert_eq!(take_current().rows().count(), 1);
+    }
+
+    #[test]
+    fn swap() {
+        take_current();
+        skip_next(1);
+        let _ = verifier();
+        let saved = swap_current(PassTimes::default());
+        assert_eq!(saved.rows().count(), 1);
+        assert_eq!(swap_current(saved).rows().count(), 0);
+
+        // Swapping doesn't end the cycle being skipped.
+        assert_eq!(take_current().rows().count(), 0);
+        let _ = verifier();
+        assert_eq!(take_current().rows().count(), 1);
+    }
+
+    #[test]
+    fn stats() {
+        take_current();
+        {
+            let _ = compile();
+        }
+        {
+            let _compile = compile();
+            ::std::thread::sleep(Duration::from_millis(2));
+        }
+        let mut times = take_current();
+        let stats = times.stats(Pass::compile);
+        assert_eq!(stats.count, 2);
+        assert!(stats.max >= Duration::from_millis(2));
+        assert!(stats.min < Duration::from_millis(2));
+        assert_eq!(stats.min + stats.max, stats.total);
+        assert!(stats.min <= stats.average() && stats.average() <= stats.max);
+        assert_eq!(times.stats(Pass::dce), PassStats::default());
+        assert_eq!(times.stats(Pass::dce).average(), Duration::default());
+
+        // Adding timings widens the range.
+        let other = PassTimes::decode(
+            "cretonne-timing 1\npass compile 1000000000 0 1\nrange compile 1000000000 1000000000\n",
+        ).unwrap();
+        times.add(&other);
+        let combined = times.stats(Pass::compile);
+        assert_eq!(combined.count, 3);
+        assert_eq!(combined.min, stats.min);
+        assert_eq!(combined.max, Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,51 @@
+//! OpenTelemetry integration.
+//!
+//! When the `otel` feature is enabled, every timed pass is also reported as an OpenTelemetry span
+//! named after the pass, e.g. `regalloc` or `ra_coloring`. Nested passes become child spans via
+//! the OpenTelemetry context, so compilation passes appear in traces underneath whatever span was
+//! active when Cretonne was invoked.
+//!
+//! The spans are created with the tracer named `cretonne` from the global tracer provider. The
+//! embedder is responsible for setting up OpenTelemetry:
+//!
+//! - Install a tracer provider with `opentelemetry::global::set_tracer_provider()`, typically an
+//!   SDK provider configured with an exporter. Without a provider, the spans are no-ops.
+//! - Since the spans are attached to the thread-local OpenTelemetry context, compile on a thread
+//!   where the parent request span is current if the passes should be nested under it.
+//!
+//! The `PassTimes` accounting is independent of this integration and works the same with or
+//! without the feature.
+
+use super::Pass;
+use opentelemetry::global;
+use opentelemetry::trace::{TraceContextExt, Tracer};
+use opentelemetry::{Context, ContextGuard};
+
+/// An OpenTelemetry span covering the execution of a pass.
+///
+/// The span is the current span while this object is alive, and it is ended when it is dropped.
+pub struct PassSpan {
+    cx: Context,
+    _guard: ContextGuard,
+}
+
+impl PassSpan {
+    /// Start a new span for `pass` as a child of the current span, and make it current.
+    pub fn enter(pass: Pass) -> Self {
+        // The `Debug` representation of a pass is its snake_case name.
+        let span = global::tracer("cretonne").start(format!("{:?}", pass));
+        let cx = Context::current_with_span(span);
+        Self {
+            _guard: cx.clone().attach(),
+            cx,
+        }
+    }
+}
+
+impl Drop for PassSpan {
+    fn drop(&mut self) {
+        // End our own span rather than the current one: when passes are dropped out of order, the
+        // current span may belong to a pass that is still running.
+        self.cx.span().end();
+    }
+}
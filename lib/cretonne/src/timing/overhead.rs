@@ -0,0 +1,79 @@
+//! Adaptive limit on the overhead of timing passes.
+//!
+//! Timing a pass has a small fixed cost. When many tiny passes are timed, this cost can become a
+//! significant fraction of the compile time. With `set_overhead_limit()`, timing disables itself
+//! when its estimated overhead exceeds a given fraction of the measured compile time.
+//!
+//! The overhead is sampled each time `take_current()` is called, so it is checked once per
+//! compilation for embedders that collect timings after each compilation. The estimate is the
+//! number of passes timed since the last `take_current()` call multiplied by the cost of timing a
+//! pass as measured by `calibrate()`. The cost is measured when the limit is set, and measured
+//! again every `RECALIBRATE_INTERVAL` checks to follow changes in the load of the machine.
+
+use super::details::{self, calibrate, nanos, ENABLED};
+use super::PassTimes;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Number of overhead checks between measurements of the cost of timing a pass.
+const RECALIBRATE_INTERVAL: u64 = 1000;
+
+/// Maximum overhead as a fraction of compile time, stored as `f64` bits. Zero means no limit.
+static LIMIT: AtomicU64 = AtomicU64::new(0);
+
+/// Cost of timing a single pass in nanoseconds, as measured by `calibrate()`.
+static COST: AtomicU64 = AtomicU64::new(0);
+
+/// Set when timing has been disabled because of excessive overhead.
+static TRIPPED: AtomicBool = AtomicBool::new(false);
+
+/// Number of overhead checks since the limit was set.
+static CHECKS: AtomicU64 = AtomicU64::new(0);
+
+/// Disable timing automatically if its overhead exceeds `limit` as a fraction of compile time.
+///
+/// This calibrates the cost of timing a pass on the current thread. Setting a new limit, or
+/// removing the limit with `None`, re-enables timing if it was disabled by a previous limit.
+pub fn set_overhead_limit(limit: Option<f64>) {
+    match limit {
+        Some(limit) => {
+            COST.store(nanos(calibrate()), Ordering::Relaxed);
+            CHECKS.store(0, Ordering::Relaxed);
+            LIMIT.store(limit.to_bits(), Ordering::Relaxed);
+        }
+        None => LIMIT.store(0, Ordering::Relaxed),
+    }
+    if TRIPPED.swap(false, Ordering::Relaxed) {
+        ENABLED.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Forget that the limit disabled timing, so it can disable timing again. This is called when
+/// timing is enabled with `set_enabled()`.
+pub fn rearm() {
+    TRIPPED.store(false, Ordering::Relaxed);
+}
+
+/// Check the estimated overhead of timing the passes in `times`, and disable timing if it exceeds
+/// the limit.
+pub fn check(times: &PassTimes) {
+    let limit = f64::from_bits(LIMIT.load(Ordering::Relaxed));
+    if limit == 0.0 {
+        return;
+    }
+    if CHECKS.fetch_add(1, Ordering::Relaxed) % RECALIBRATE_INTERVAL == RECALIBRATE_INTERVAL - 1 {
+        COST.store(nanos(calibrate()), Ordering::Relaxed);
+    }
+
+    // The self times of all the passes add up to the time spent in top-level passes.
+    let (count, secs) = times.rows().fold((0, 0.0), |(count, secs), row| {
+        (count + row.count, secs + details::secs(row.self_time))
+    });
+    let overhead = count as f64 * COST.load(Ordering::Relaxed) as f64 * 1e-9;
+    if secs > 0.0 && overhead > limit * secs && !TRIPPED.swap(true, Ordering::Relaxed) {
+        ENABLED.store(false, Ordering::Relaxed);
+        dbg!(
+            "timing: Overhead is {:.1}% of compile time, disabling timing",
+            overhead / secs * 100.0
+        );
+    }
+}
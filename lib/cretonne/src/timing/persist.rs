@@ -0,0 +1,387 @@
+//! Saving and loading pass timings.
+//!
+//! Pass timings can be saved to a file and loaded back, so timings can be accumulated across
+//! separate process invocations. For example, each compiler invocation in a build can fold its
+//! timings into a shared file with `PassTimes::accumulate_to_file()`.
+//!
+//! The file format is line-based text. The first line identifies the format, and the following
+//! lines record the timings of each pass that has run and the time spent in child passes, with
+//! passes identified by their snake_case name:
+//!
+//! ```text
+//! cretonne-timing 1
+//! pass <name> <total_ns> <child_ns> <count>
+//! range <name> <min_ns> <max_ns>
+//! sumsq <name> <sum of squared run times in ns^2>
+//! effect <name> <amount>
+//! edge <parent> <child> <ns>
+//! stack <outermost>;...;<innermost> <ns>
+//! isa <name>
+//! ```
+
+use super::details::PassTime;
+use super::{Pass, PassTimes};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// First line of a timing file.
+const HEADER: &str = "cretonne-timing 1";
+
+/// How long to wait for another process to release the lock on a timing file.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Parse a number of nanoseconds.
+fn parse_nanos(s: &str) -> Result<u64, String> {
+    s.parse::<u64>().map_err(
+        |e| format!("bad duration '{}': {}", s, e),
+    )
+}
+
+/// Look up a pass by name.
+fn parse_pass(s: &str) -> Result<Pass, String> {
+    Pass::from_name(s).ok_or_else(|| format!("unknown pass '{}'", s))
+}
+
+/// Convert a parse error into an I/O error.
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl PassTimes {
+    /// Encode these timings in the timing file format.
+    pub fn encode(&self) -> String {
+        let mut s = format!("{}\n", HEADER);
+        for row in self.rows() {
+            let time = &self.pass[row.pass.idx()];
+            s += &format!(
+                "pass {} {} {} {}\n",
+                row.pass.name(),
+                time.total_ns,
+                time.child_ns,
+                time.count
+            );
+            if time.count > 0 {
+                s += &format!("range {} {} {}\n", row.pass.name(), time.min_ns, time.max_ns);
+                s += &format!("sumsq {} {}\n", row.pass.name(), time.sq_ns);
+            }
+            if let Some(effect) = time.effect {
+                s += &format!("effect {} {}\n", row.pass.name(), effect);
+            }
+        }
+        // Sort the edges so the output is deterministic.
+        let mut edges: Vec<_> = self.edges.iter().collect();
+        edges.sort_by_key(|&(&(parent, child), _)| (parent.idx(), child.idx()));
+        for (&(parent, child), &ns) in edges {
+            s += &format!("edge {} {} {}\n", parent.name(), child.name(), ns);
+        }
+        let mut stacks: Vec<_> = self.stacks.iter().collect();
+        stacks.sort_by_key(|&(stack, _)| stack.iter().map(|pass| pass.idx()).collect::<Vec<_>>());
+        for (stack, &ns) in stacks {
+            let names: Vec<_> = stack.iter().map(|pass| pass.name()).collect();
+            s += &format!("stack {} {}\n", names.join(";"), ns);
+        }
+        for isa in &self.isas {
+            s += &format!("isa {}\n", isa);
+        }
+        s
+    }
+
+    /// Decode timings in the timing file format.
+    ///
+    /// This fails if the data refers to a pass that doesn't exist. Use `load_lenient()` to
+    /// decode timings saved by a version of Cretonne with different passes.
+    pub fn decode(data: &str) -> Result<PassTimes, String> {
+        PassTimes::decode_with(data, parse_pass)
+    }
+
+    /// Decode timings in the timing file format, ignoring passes that don't exist.
+    ///
+    /// Passes can be removed or renamed between Cretonne versions, so timings saved by an older
+    /// version may refer to passes that are gone. Their timings are dropped. Returns the decoded
+    /// timings along with the names of the ignored passes, in the order they first appear.
+    pub fn load_lenient(data: &str) -> Result<(PassTimes, Vec<String>), String> {
+        let mut ignored = Vec::new();
+        let times = PassTimes::decode_with(data, |name| {
+            Ok(Pass::from_name(name).unwrap_or_else(|| {
+                if !ignored.iter().any(|n| n == name) {
+                    ignored.push(name.to_string());
+                }
+                Pass::None
+            }))
+        })?;
+        if !ignored.is_empty() {
+            dbg!("timing: ignored {} unknown passes", ignored.len());
+        }
+        Ok((times, ignored))
+    }
+
+    /// Decode timings, looking up pass names with `lookup`.
+    ///
+    /// Timings for passes that `lookup` maps to `Pass::None` are dropped.
+    fn decode_with<F>(data: &str, mut lookup: F) -> Result<PassTimes, String>
+    where
+        F: FnMut(&str) -> Result<Pass, String>,
+    {
+        let mut lines = data.lines();
+        if lines.next() != Some(HEADER) {
+            return Err("not a cretonne timing file".to_string());
+        }
+
+        // Scratch entry receiving the timings of dropped passes.
+        let mut dropped = PassTime::default();
+        let mut times = PassTimes::default();
+        for line in lines {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                [] => {}
+                ["pass", name, total, child, count] => {
+                    let time = times.pass.get_mut(lookup(name)?.idx()).unwrap_or(&mut dropped);
+                    time.total_ns += parse_nanos(total)?;
+                    time.child_ns += parse_nanos(child)?;
+                    time.count += count.parse::<u64>().map_err(
+                        |e| format!("bad count '{}': {}", count, e),
+                    )?;
+                }
+                ["range", name, min, max] => {
+                    // The range follows the `pass` line, which has already accounted for the runs.
+                    let time = times.pass.get_mut(lookup(name)?.idx()).unwrap_or(&mut dropped);
+                    time.min_ns = parse_nanos(min)?;
+                    time.max_ns = parse_nanos(max)?;
+                }
+                ["sumsq", name, sq] => {
+                    let time = times.pass.get_mut(lookup(name)?.idx()).unwrap_or(&mut dropped);
+                    time.sq_ns += sq.parse::<f64>().map_err(
+                        |e| format!("bad sum of squares '{}': {}", sq, e),
+                    )?;
+                }
+                ["effect", name, amount] => {
+                    let time = times.pass.get_mut(lookup(name)?.idx()).unwrap_or(&mut dropped);
+                    let amount = amount.parse::<u64>().map_err(
+                        |e| format!("bad effect '{}': {}", amount, e),
+                    )?;
+                    time.effect = Some(time.effect.unwrap_or(0) + amount);
+                }
+                ["edge", parent, child, dur] => {
+                    let edge = (lookup(parent)?, lookup(child)?);
+                    let ns = parse_nanos(dur)?;
+                    if edge.0 != Pass::None && edge.1 != Pass::None {
+                        *times.edges.entry(edge).or_insert(0) += ns;
+                    }
+                }
+                ["stack", names, dur] => {
+                    let stack = names
+                        .split(';')
+                        .map(&mut lookup)
+                        .collect::<Result<Vec<_>, _>>()?;
+                    let ns = parse_nanos(dur)?;
+                    if !stack.contains(&Pass::None) {
+                        *times.stacks.entry(stack).or_insert(0) += ns;
+                    }
+                }
+                ["isa", name] => times.add_isa(name),
+                _ => return Err(format!("malformed line: {}", line)),
+            }
+        }
+        Ok(times)
+    }
+
+    /// Save these timings to the file at `path`.
+    ///
+    /// The file is replaced atomically, so concurrent readers never see a partially written file.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        // Write a temporary file in the same directory, then rename it over `path`.
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(format!(".tmp{}", process::id()));
+        let tmp = PathBuf::from(tmp);
+        fs::File::create(&tmp)
+            .and_then(|mut file| file.write_all(self.encode().as_bytes()))
+            .and_then(|()| fs::rename(&tmp, path))
+            .inspect_err(|_| {
+                // Don't leave a partially written file behind.
+                fs::remove_file(&tmp).ok();
+            })
+    }
+
+    /// Load timings from the file at `path`.
+    pub fn load(path: &Path) -> io::Result<PassTimes> {
+        PassTimes::decode(&fs::read_to_string(path)?).map_err(invalid_data)
+    }
+
+    /// Load timings from the file at `path`, or return empty timings if it doesn't exist.
+    pub fn load_or_default(path: &Path) -> io::Result<PassTimes> {
+        match PassTimes::load(path) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(PassTimes::default()),
+            result => result,
+        }
+    }
+
+    /// Add these timings to the totals saved in the file at `path`.
+    ///
+    /// The file is created if it doesn't exist. Timings in the file for passes that no longer
+    /// exist are dropped, as with `load_lenient()`, and the names of these passes are returned.
+    ///
+    /// Concurrent updates from multiple processes are serialized with a lock file named after
+    /// `path` with a `.lock` suffix. If the lock can't be acquired within 10 seconds, this fails
+    /// with a `TimedOut` error. A lock file left behind by a crashed process must be removed
+    /// manually.
+    pub fn accumulate_to_file(&self, path: &Path) -> io::Result<Vec<String>> {
+        let _lock = LockFile::acquire(path)?;
+        let (mut totals, ignored) = match fs::read_to_string(path) {
+            Ok(data) => PassTimes::load_lenient(&data).map_err(invalid_data)?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (PassTimes::default(), Vec::new()),
+            Err(e) => return Err(e),
+        };
+        totals.add(self);
+        totals.save(path)?;
+        Ok(ignored)
+    }
+}
+
+/// A lock file which is removed when dropped.
+struct LockFile(PathBuf);
+
+impl LockFile {
+    /// Acquire the lock for `path`, waiting for other processes to release it.
+    fn acquire(path: &Path) -> io::Result<LockFile> {
+        let mut lock = path.as_os_str().to_owned();
+        lock.push(".lock");
+        let lock = PathBuf::from(lock);
+        let started = Instant::now();
+        loop {
+            match fs::OpenOptions::new().write(true).create_new(true).open(&lock) {
+                Ok(_) => return Ok(LockFile(lock)),
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    if started.elapsed() > LOCK_TIMEOUT {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            format!("timed out waiting for {}", lock.display()),
+                        ));
+                    }
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        fs::remove_file(&self.0).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use timing::{compile, process_file, set_isa, take_current, Pass, PassTimes};
+
+    fn sample() -> PassTimes {
+        take_current();
+        set_isa("intel");
+        {
+            let _file = process_file();
+            let _ = compile();
+        }
+        take_current()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let times = sample();
+        let encoded = times.encode();
+        assert!(encoded.starts_with("cretonne-timing 1\npass process_file "));
+        let decoded = PassTimes::decode(&encoded).unwrap();
+        assert_eq!(decoded.encode(), encoded);
+        assert_eq!(decoded.isas(), ["intel"]);
+        assert_eq!(decoded.stats(Pass::compile), times.stats(Pass::compile));
+        assert_eq!(
+            decoded.rows().collect::<Vec<_>>(),
+            times.rows().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            decoded.edges.get(&(Pass::process_file, Pass::compile)),
+            times.edges.get(&(Pass::process_file, Pass::compile))
+        );
+        let stack = vec![Pass::process_file, Pass::compile];
+        assert!(times.stacks.contains_key(&stack));
+        assert_eq!(decoded.stacks.get(&stack), times.stacks.get(&stack));
+
+        assert!(PassTimes::decode("").is_err());
+        assert!(PassTimes::decode("cretonne-timing 1\npass nopass 1 0 1").is_err());
+        assert!(PassTimes::decode("cretonne-timing 1\npass compile 1 0").is_err());
+    }
+
+    #[test]
+    fn lenient() {
+        let data = "cretonne-timing 1\n\
+                    pass process_file 30 20 1\n\
+                    pass oldpass 20 0 2\n\
+                    effect oldpass 5\n\
+                    pass compile 10 0 1\n\
+                    edge process_file oldpass 20\n\
+                    edge process_file compile 10\n\
+                    edge oldpass gonepass 1\n\
+                    stack process_file;oldpass 20\n\
+                    stack process_file;compile 10\n";
+        assert!(PassTimes::decode(data).is_err());
+
+        let (times, ignored) = PassTimes::load_lenient(data).unwrap();
+        assert_eq!(ignored, vec!["oldpass".to_string(), "gonepass".to_string()]);
+        let rows: Vec<_> = times.rows().collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].pass, Pass::process_file);
+        assert_eq!(rows[1].pass, Pass::compile);
+        assert_eq!(rows[1].effect, None);
+        assert_eq!(times.edges.len(), 1);
+        assert_eq!(times.stacks.len(), 1);
+
+        assert!(PassTimes::load_lenient("").is_err());
+        assert!(PassTimes::load_lenient("cretonne-timing 1\npass compile 1 0").is_err());
+    }
+
+    #[test]
+    fn accumulate() {
+        let path = env::temp_dir().join(format!("cretonne-timing-test-{}", ::std::process::id()));
+        fs::remove_file(&path).ok();
+        assert_eq!(PassTimes::load_or_default(&path).unwrap().rows().count(), 0);
+
+        let times = sample();
+        assert!(times.accumulate_to_file(&path).unwrap().is_empty());
+        assert!(times.accumulate_to_file(&path).unwrap().is_empty());
+        let totals = PassTimes::load(&path).unwrap();
+
+        // Passes that no longer exist are reported and dropped from the file.
+        let mut data = fs::read_to_string(&path).unwrap();
+        data += "pass old_pass 1 0 1\n";
+        fs::write(&path, data).unwrap();
+        assert_eq!(times.accumulate_to_file(&path).unwrap(), ["old_pass"]);
+        assert!(!fs::read_to_string(&path).unwrap().contains("old_pass"));
+        fs::remove_file(&path).unwrap();
+
+        let rows: Vec<_> = totals.rows().collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1].pass, Pass::compile);
+        assert_eq!(rows[1].count, 2);
+        assert_eq!(rows[1].total, times.rows().nth(1).unwrap().total * 2);
+    }
+
+    #[test]
+    fn save_failure() {
+        // The timings can't replace a non-empty directory.
+        let dir = env::temp_dir().join(format!("cretonne-timing-save-{}", ::std::process::id()));
+        let path = dir.join("times");
+        fs::create_dir_all(path.join("entry")).unwrap();
+        assert!(sample().save(&path).is_err());
+
+        // The temporary file was removed.
+        let entries: Vec<_> = fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name()).collect();
+        assert_eq!(entries, ["times"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,130 @@
+//! Tracking which threads contributed to aggregated pass timings.
+//!
+//! Summing the timings of many threads loses track of where the time was spent: a pass may be
+//! evenly spread over the threads, or dominated by one overloaded thread. Timings added with
+//! `PassTimes::add_labeled()` or `add_to_current_labeled()` additionally keep the total time of
+//! each pass per label, e.g. one label per worker thread, and `PassTimes::contributions()` breaks
+//! the time of a pass down by label.
+//!
+//! Plain `PassTimes::add()` keeps the contributions already recorded in both operands, but doesn't
+//! record new ones.
+
+use super::details::from_nanos;
+use super::{Pass, PassTimes};
+use std::cmp::Reverse;
+use std::time::Duration;
+
+impl PassTimes {
+    /// Add the timings in `other` to `self`, and record them as contributed by `label`.
+    ///
+    /// Contributions recorded in `other` itself are kept under their own labels, so only the
+    /// timings `other` was given without a label are attributed to `label`. Adding timings with
+    /// the same label again adds to its contribution.
+    pub fn add_labeled(&mut self, label: &str, other: &PassTimes) {
+        self.add(other);
+        let mut own: Vec<u64> = other.pass.iter().map(|time| time.total_ns).collect();
+        for totals in other.contributors.values() {
+            for (own, &ns) in own.iter_mut().zip(totals) {
+                *own = own.saturating_sub(ns);
+            }
+        }
+        if own.iter().all(|&ns| ns == 0) {
+            return;
+        }
+        let totals = self.contributors.entry(label.to_string()).or_insert_with(
+            || vec![0; own.len()],
+        );
+        for (total, ns) in totals.iter_mut().zip(own) {
+            *total += ns;
+        }
+    }
+
+    /// Merge the contributions recorded in `other`. Used by `add()`.
+    pub(super) fn add_contributors(&mut self, other: &PassTimes) {
+        for (label, other_totals) in &other.contributors {
+            let totals = self.contributors.entry(label.clone()).or_insert_with(
+                || vec![0; other_totals.len()],
+            );
+            for (total, &ns) in totals.iter_mut().zip(other_totals) {
+                *total += ns;
+            }
+        }
+    }
+
+    /// Break the total time of `pass` down by the labels that contributed it.
+    ///
+    /// Each item is a `(label, time, fraction)` tuple, where `fraction` is the share of the
+    /// time of `pass` contributed by `label`. The items are sorted by decreasing time. Time
+    /// added without a label isn't listed, so the fractions only sum to 1 when all the timings
+    /// were added with `add_labeled()`.
+    pub fn contributions(&self, pass: Pass) -> Vec<(&str, Duration, f64)> {
+        let total = self.pass.get(pass.idx()).map_or(0, |time| time.total_ns);
+        let mut contributions: Vec<_> = self.contributors
+            .iter()
+            .filter_map(|(label, totals)| {
+                totals.get(pass.idx()).cloned().filter(|&ns| ns > 0).map(|ns| {
+                    (label.as_str(), from_nanos(ns), ns as f64 / total as f64)
+                })
+            })
+            .collect();
+        // Stable sort keeps the labels in order for ties.
+        contributions.sort_by_key(|&(_, time, _)| Reverse(time));
+        contributions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::add_to_current_labeled;
+    use super::*;
+
+    fn sample(regalloc_ns: u64) -> PassTimes {
+        PassTimes::decode(&format!(
+            "cretonne-timing 1\npass regalloc {} 0 1\npass dce 10 0 1\n",
+            regalloc_ns
+        )).unwrap()
+    }
+
+    #[test]
+    fn contributions() {
+        let mut times = PassTimes::default();
+        times.add_labeled("worker #0", &sample(100));
+        times.add_labeled("worker #1", &sample(300));
+        times.add(&sample(600));
+        assert_eq!(times.total_nanos(Pass::regalloc), 1000);
+        assert_eq!(
+            times.contributions(Pass::regalloc),
+            [
+                ("worker #1", Duration::new(0, 300), 0.3),
+                ("worker #0", Duration::new(0, 100), 0.1),
+            ]
+        );
+        assert_eq!(times.contributions(Pass::dce).len(), 2);
+        assert_eq!(times.contributions(Pass::licm), []);
+
+        // Nested contributions keep their labels.
+        let mut outer = PassTimes::default();
+        outer.add_labeled("main", &times);
+        outer.add_labeled("worker #1", &sample(250));
+        assert_eq!(
+            outer.contributions(Pass::regalloc),
+            [
+                ("main", Duration::new(0, 600), 0.48),
+                ("worker #1", Duration::new(0, 550), 0.44),
+                ("worker #0", Duration::new(0, 100), 0.08),
+            ]
+        );
+    }
+
+    #[test]
+    fn current() {
+        use super::super::take_current;
+        take_current();
+        add_to_current_labeled("thread", &sample(100));
+        assert_eq!(
+            take_current().contributions(Pass::regalloc),
+            [("thread", Duration::new(0, 100), 1.0)]
+        );
+        assert_eq!(take_current().contributions(Pass::regalloc), []);
+    }
+}
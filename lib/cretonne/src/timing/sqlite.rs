@@ -0,0 +1,85 @@
+//! SQLite export of pass timings.
+//!
+//! When the `sqlite` feature is enabled, `PassTimes::write_sqlite()` inserts the accumulated
+//! timings into a `pass_times` table, creating it if necessary:
+//!
+//! ```sql
+//! CREATE TABLE pass_times (
+//!     run_id    TEXT NOT NULL,
+//!     pass_name TEXT NOT NULL,
+//!     total_ns  INTEGER NOT NULL,
+//!     self_ns   INTEGER NOT NULL,
+//!     count     INTEGER NOT NULL
+//! );
+//! ```
+//!
+//! There is one row per pass that has run. The `pass_name` column is the snake_case pass name,
+//! and the `run_id` column is chosen by the caller to identify the run, e.g. a CI build number.
+
+use super::details::nanos;
+use super::PassTimes;
+use rusqlite::{Connection, Result};
+
+/// SQL statement creating the `pass_times` table.
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS pass_times (
+    run_id    TEXT NOT NULL,
+    pass_name TEXT NOT NULL,
+    total_ns  INTEGER NOT NULL,
+    self_ns   INTEGER NOT NULL,
+    count     INTEGER NOT NULL
+)";
+
+impl PassTimes {
+    /// Insert one row per pass that has run into the `pass_times` table of `conn`.
+    ///
+    /// The table is created if it doesn't exist. All the rows are inserted in a single
+    /// transaction.
+    pub fn write_sqlite(&self, conn: &Connection, run_id: &str) -> Result<()> {
+        conn.execute_batch(CREATE_TABLE)?;
+        let tx = conn.unchecked_transaction()?;
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO pass_times (run_id, pass_name, total_ns, self_ns, count)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for row in self.rows() {
+                insert.execute((
+                    run_id,
+                    row.pass.name(),
+                    nanos(row.total) as i64,
+                    nanos(row.self_time) as i64,
+                    row.count as i64,
+                ))?;
+            }
+        }
+        tx.commit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use timing::{compile, take_current};
+    use rusqlite::Connection;
+
+    #[test]
+    fn write_sqlite() {
+        take_current();
+        drop(compile());
+        drop(compile());
+        let times = take_current();
+
+        let conn = Connection::open_in_memory().unwrap();
+        times.write_sqlite(&conn, "run1").unwrap();
+        times.write_sqlite(&conn, "run2").unwrap();
+
+        let count: i64 = conn.query_row(
+            "SELECT count FROM pass_times WHERE run_id = 'run2' AND pass_name = 'compile'",
+            [],
+            |row| row.get(0),
+        ).unwrap();
+        assert_eq!(count, 2);
+        let rows: i64 = conn.query_row("SELECT COUNT(*) FROM pass_times", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(rows, 2);
+    }
+}
@@ -0,0 +1,139 @@
+//! Statistical baselines of pass timings.
+//!
+//! A `PassTimesStats` baseline summarizes the timings of many compilations by the mean and
+//! standard deviation of the total time spent in each pass. The timings of a single compilation
+//! can then be compared against the baseline with `PassTimes::flag_anomalies()` to find the
+//! passes that were unusually slow.
+
+use super::details::secs;
+use super::{Pass, PassTimes, NUM_PASSES, PASSES};
+
+/// Running mean and variance of a single pass, using Welford's algorithm.
+#[derive(Clone, Copy, Debug, Default)]
+struct Moments {
+    mean: f64,
+    /// Sum of squared differences from the mean.
+    m2: f64,
+}
+
+/// Mean and standard deviation of the time spent in each pass over a number of samples.
+#[derive(Clone, Debug, Default)]
+pub struct PassTimesStats {
+    samples: u64,
+    pass: [Moments; NUM_PASSES],
+}
+
+impl PassTimesStats {
+    /// Create a baseline from a sequence of samples.
+    ///
+    /// Each sample should cover comparable work, typically a single compilation. A pass that
+    /// didn't run in a sample counts as taking no time in that sample.
+    pub fn from_samples<'a, I>(samples: I) -> PassTimesStats
+    where
+        I: IntoIterator<Item = &'a PassTimes>,
+    {
+        let mut stats = PassTimesStats::default();
+        for sample in samples {
+            stats.add_sample(sample);
+        }
+        stats
+    }
+
+    /// Add a sample to the baseline.
+    pub fn add_sample(&mut self, sample: &PassTimes) {
+        self.samples += 1;
+        let n = self.samples as f64;
+        for (moments, time) in self.pass.iter_mut().zip(sample.pass.iter()) {
+            let x = secs(time.total());
+            let delta = x - moments.mean;
+            moments.mean += delta / n;
+            moments.m2 += delta * (x - moments.mean);
+        }
+    }
+
+    /// Get the number of samples in the baseline.
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    /// Get the mean time spent in `pass`, in seconds.
+    pub fn mean(&self, pass: Pass) -> f64 {
+        self.pass.get(pass.idx()).map_or(0.0, |m| m.mean)
+    }
+
+    /// Get the sample standard deviation of the time spent in `pass`, in seconds.
+    ///
+    /// Returns 0.0 if there are fewer than two samples.
+    pub fn stddev(&self, pass: Pass) -> f64 {
+        if self.samples < 2 {
+            return 0.0;
+        }
+        self.pass
+            .get(pass.idx())
+            .map_or(0.0, |m| (m.m2 / (self.samples - 1) as f64).sqrt())
+    }
+}
+
+impl PassTimes {
+    /// Find the passes that took more than `sigma` standard deviations longer than their mean
+    /// time in `baseline`.
+    ///
+    /// Each flagged pass is returned with its distance from the baseline mean, measured in
+    /// standard deviations, and the passes are sorted by decreasing distance. Passes whose time
+    /// doesn't vary in the baseline are never flagged, since their distance is undefined.
+    pub fn flag_anomalies(&self, baseline: &PassTimesStats, sigma: f64) -> Vec<(Pass, f64)> {
+        let mut flagged: Vec<_> = PASSES
+            .iter()
+            .filter_map(|&pass| {
+                let stddev = baseline.stddev(pass);
+                if stddev == 0.0 {
+                    return None;
+                }
+                let z = (secs(self.pass[pass.idx()].total()) - baseline.mean(pass)) / stddev;
+                if z > sigma { Some((pass, z)) } else { None }
+            })
+            .collect();
+        flagged.sort_by(|a, b| b.1.partial_cmp(&a.1).expect("NaN distance"));
+        flagged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(regalloc_ns: u64, dce_ns: u64) -> PassTimes {
+        PassTimes::decode(&format!(
+            "cretonne-timing 1\npass regalloc {} 0 1\npass dce {} 0 1\n",
+            regalloc_ns,
+            dce_ns
+        )).unwrap()
+    }
+
+    #[test]
+    fn baseline() {
+        let samples = [sample(1000, 500), sample(3000, 500)];
+        let stats = PassTimesStats::from_samples(&samples);
+        assert_eq!(stats.samples(), 2);
+        assert!((stats.mean(Pass::regalloc) - 2e-6).abs() < 1e-12);
+        assert!((stats.stddev(Pass::regalloc) - 2f64.sqrt() * 1e-6).abs() < 1e-12);
+        assert_eq!(stats.stddev(Pass::dce), 0.0);
+        assert_eq!(stats.mean(Pass::licm), 0.0);
+    }
+
+    #[test]
+    fn anomalies() {
+        let samples = [
+            sample(1000, 500),
+            sample(2000, 500),
+            sample(3000, 500),
+        ];
+        let stats = PassTimesStats::from_samples(&samples);
+        // Regalloc has mean 2µs and stddev 1µs, so 8µs is 6 sigma slow.
+        let flagged = sample(8000, 5000).flag_anomalies(&stats, 5.0);
+        assert_eq!(flagged.len(), 1);
+        assert_eq!(flagged[0].0, Pass::regalloc);
+        assert!((flagged[0].1 - 6.0).abs() < 1e-6);
+        assert!(sample(8000, 500).flag_anomalies(&stats, 6.5).is_empty());
+    }
+}
@@ -0,0 +1,101 @@
+//! Rendering pass timings as SVG.
+//!
+//! This draws a horizontal bar chart of the self time of each pass, which can be opened directly
+//! in a web browser for a quick look at where the time goes.
+
+use super::details::millis;
+use super::PassTimes;
+use std::cmp::Reverse;
+use std::io::{self, Write};
+
+/// Width of the column of pass names, in pixels.
+const LABEL_WIDTH: u32 = 160;
+
+/// Width of the longest bar, in pixels.
+const BAR_WIDTH: u32 = 480;
+
+/// Height of a bar, and the spacing between them, in pixels.
+const BAR_HEIGHT: u32 = 18;
+const BAR_SPACING: u32 = 4;
+
+/// Room to the right of the longest bar for its duration label, in pixels.
+const DURATION_WIDTH: u32 = 100;
+
+impl PassTimes {
+    /// Write an SVG bar chart of the self time of each pass that has run to `w`.
+    ///
+    /// The passes are sorted by decreasing self time, and each bar is labeled with the pass name
+    /// and its self time in milliseconds. Bars are scaled so the longest one has a fixed width.
+    pub fn write_svg<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut rows: Vec<_> = self.rows().collect();
+        rows.sort_by_key(|row| Reverse(row.self_time));
+        let max = rows.first().map_or(0.0, |row| millis(row.self_time));
+
+        let width = LABEL_WIDTH + BAR_WIDTH + DURATION_WIDTH;
+        let height = rows.len() as u32 * (BAR_HEIGHT + BAR_SPACING) + BAR_SPACING;
+        writeln!(
+            w,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-family="sans-serif" font-size="12">"#,
+            width,
+            height
+        )?;
+        for (i, row) in rows.iter().enumerate() {
+            let ms = millis(row.self_time);
+            let bar = if max > 0.0 {
+                (ms / max * f64::from(BAR_WIDTH)).round() as u32
+            } else {
+                0
+            };
+            let y = BAR_SPACING + i as u32 * (BAR_HEIGHT + BAR_SPACING);
+            let text_y = y + BAR_HEIGHT - 5;
+            writeln!(
+                w,
+                r#"<text x="{}" y="{}" text-anchor="end">{}</text>"#,
+                LABEL_WIDTH - 6,
+                text_y,
+                row.pass.name()
+            )?;
+            writeln!(
+                w,
+                r#"<rect x="{}" y="{}" width="{}" height="{}" fill="steelblue"/>"#,
+                LABEL_WIDTH,
+                y,
+                bar,
+                BAR_HEIGHT
+            )?;
+            writeln!(
+                w,
+                r#"<text x="{}" y="{}">{:.3} ms</text>"#,
+                LABEL_WIDTH + bar + 6,
+                text_y,
+                ms
+            )?;
+        }
+        writeln!(w, "</svg>")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use timing::PassTimes;
+
+    #[test]
+    fn bars() {
+        let times = PassTimes::decode(
+            "cretonne-timing 1\npass regalloc 2000000 0 1\npass dce 1000000 0 1\n",
+        ).unwrap();
+        let mut svg = Vec::new();
+        times.write_svg(&mut svg).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.ends_with("</svg>\n"));
+
+        let lines: Vec<_> = svg.lines().collect();
+        assert_eq!(lines.len(), 8);
+        assert!(lines[1].ends_with(">regalloc</text>"));
+        assert!(lines[2].contains(r#" width="480" "#));
+        assert!(lines[3].ends_with(">2.000 ms</text>"));
+        assert!(lines[4].ends_with(">dce</text>"));
+        assert!(lines[5].contains(r#" width="240" "#));
+    }
+}
@@ -0,0 +1,157 @@
+//! Recording pass timings as a Chrome trace.
+//!
+//! When tracing is enabled with `set_tracing(true)`, the start and end of every timed pass is
+//! recorded as a begin/end event. The events are written by `write_trace()` in the Chrome trace
+//! event format, which can be loaded in `chrome://tracing` or Perfetto to view the nested passes
+//! of each thread as a flame chart.
+//!
+//! Each thread records events into its own buffer, so recording only takes an uncontended lock.
+
+use super::clock::{self, Timestamp};
+use super::details::secs;
+use super::Pass;
+use std::io::{self, Write};
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+/// Is tracing enabled?
+static TRACING: AtomicBool = AtomicBool::new(false);
+
+/// Time origin of the trace event timestamps.
+static EPOCH: OnceLock<Timestamp> = OnceLock::new();
+
+/// The trace events recorded by a thread.
+type Buffer = Arc<Mutex<Vec<Event>>>;
+
+/// The event buffers of the threads that have recorded events, with their thread ids.
+///
+/// The buffer of a thread that has exited is removed once its events have been written.
+static BUFFERS: Mutex<Vec<(usize, Buffer)>> = Mutex::new(Vec::new());
+
+/// The thread id of the next thread to record events.
+static NEXT_TID: AtomicUsize = AtomicUsize::new(1);
+
+thread_local! {
+    /// The event buffer of the current thread.
+    static BUFFER: Buffer = {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let tid = NEXT_TID.fetch_add(1, Ordering::Relaxed);
+        BUFFERS.lock().unwrap().push((tid, buffer.clone()));
+        buffer
+    };
+}
+
+/// A begin or end event of a pass.
+struct Event {
+    pass: Pass,
+    begin: bool,
+    /// Time since `EPOCH`.
+    time: Duration,
+}
+
+/// Enable or disable the recording of trace events in all threads.
+///
+/// Only passes that are timed are traced, so nothing is recorded while timing is disabled or
+/// recording is paused.
+pub fn set_tracing(enable: bool) {
+    EPOCH.get_or_init(clock::now);
+    TRACING.store(enable, Ordering::Relaxed);
+}
+
+/// Is tracing enabled?
+pub(super) fn enabled() -> bool {
+    TRACING.load(Ordering::Relaxed)
+}
+
+/// Record the beginning or end of `pass` on the current thread.
+pub(super) fn record(pass: Pass, begin: bool) {
+    let time = EPOCH.get_or_init(clock::now).elapsed();
+    BUFFER.with(|buffer| buffer.lock().unwrap().push(Event { pass, begin, time }))
+}
+
+/// Write the trace events recorded so far in all threads to `w`, and clear them.
+///
+/// The events are written as a JSON object in the Chrome trace event format, with pass names as
+/// event names and timestamps in microseconds. Passes that are still running when this is
+/// called only have their begin event written.
+pub fn write_trace<W: Write>(w: &mut W) -> io::Result<()> {
+    let buffers = BUFFERS.lock().unwrap().clone();
+    writeln!(w, "{{\"traceEvents\": [")?;
+    let mut first = true;
+    for (tid, buffer) in buffers {
+        let events = mem::take(&mut *buffer.lock().unwrap());
+        for event in events {
+            if !first {
+                writeln!(w, ",")?;
+            }
+            first = false;
+            let micros = secs(event.time) * 1e6;
+            write!(
+                w,
+                "{{\"name\": \"{}\", \"ph\": \"{}\", \"pid\": 1, \"tid\": {}, \"ts\": {:.3}}}",
+                event.pass.name(),
+                if event.begin { "B" } else { "E" },
+                tid,
+                micros
+            )?;
+        }
+    }
+    // Forget the threads that have exited, now that their events are written. The only reference
+    // left to their buffer is ours.
+    BUFFERS.lock().unwrap().retain(|(_, buffer)| {
+        Arc::strong_count(buffer) > 1 || !buffer.lock().unwrap().is_empty()
+    });
+    writeln!(w, "\n]}}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use timing::{gvn, licm};
+
+    #[test]
+    fn nested_events() {
+        set_tracing(true);
+        {
+            let _outer = gvn();
+            let _inner = licm();
+        }
+        set_tracing(false);
+
+        let mut out = Vec::new();
+        write_trace(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("{\"traceEvents\": [\n"));
+        assert!(out.ends_with("\n]}\n"));
+
+        // Other tests may be recording events concurrently, so only look at ours.
+        let ours: Vec<_> = out.lines()
+            .filter(|line| line.contains("\"gvn\"") || line.contains("\"licm\""))
+            .map(|line| &line[..line.find(", \"pid\"").unwrap()])
+            .collect();
+        assert_eq!(
+            ours,
+            [
+                "{\"name\": \"gvn\", \"ph\": \"B\"",
+                "{\"name\": \"licm\", \"ph\": \"B\"",
+                "{\"name\": \"licm\", \"ph\": \"E\"",
+                "{\"name\": \"gvn\", \"ph\": \"E\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn exited_threads() {
+        let buffer = ::std::thread::spawn(|| {
+            record(Pass::gvn, true);
+            BUFFER.with(Arc::downgrade)
+        }).join()
+            .unwrap();
+
+        // The buffer of the exited thread is dropped once its events have been written.
+        write_trace(&mut io::sink()).unwrap();
+        assert!(buffer.upgrade().is_none());
+    }
+}
@@ -0,0 +1,30 @@
+//! `tracing` integration.
+//!
+//! When the `tracing` feature is enabled, every timed pass is also entered as a `tracing` span at
+//! the `INFO` level, named after the pass, e.g. `regalloc` or `ra_coloring`. Timing tokens are
+//! dropped in LIFO order, so nested passes become child spans, and the passes appear underneath
+//! whatever span was entered when Cretonne was invoked.
+//!
+//! The embedder is responsible for installing a `tracing` subscriber, which may in turn forward
+//! the spans to Jaeger, OpenTelemetry, or any other backend. Without a subscriber, the spans are
+//! disabled and cost next to nothing.
+//!
+//! The `PassTimes` accounting is independent of this integration and works the same with or
+//! without the feature.
+
+use super::{pass_span, Pass};
+use tracing::span::EnteredSpan;
+
+/// A `tracing` span covering the execution of a pass.
+///
+/// The span is entered while this object is alive, and it is exited when it is dropped.
+pub struct PassSpan {
+    _guard: EnteredSpan,
+}
+
+impl PassSpan {
+    /// Create a new span for `pass` as a child of the current span, and enter it.
+    pub fn enter(pass: Pass) -> Self {
+        Self { _guard: pass_span(pass).entered() }
+    }
+}
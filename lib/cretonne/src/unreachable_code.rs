@@ -18,6 +18,7 @@ pub fn eliminate_unreachable_code(
     domtree: &DominatorTree,
 ) {
     let _tt = timing::unreachable_code();
+    let mut removed = 0;
     let mut pos = FuncCursor::new(func);
     while let Some(ebb) = pos.next_ebb() {
         if domtree.is_reachable(ebb) {
@@ -33,6 +34,7 @@ pub fn eliminate_unreachable_code(
         while let Some(inst) = pos.func.layout.first_inst(ebb) {
             dbg!(" - {}", pos.func.dfg.display_inst(inst, None));
             pos.func.layout.remove_inst(inst);
+            removed += 1;
         }
 
         // Once the EBB is completely empty, we can update the CFG which removes it from any
@@ -42,4 +44,5 @@ pub fn eliminate_unreachable_code(
         // Finally, remove the EBB from the layout.
         pos.func.layout.remove_ebb(ebb);
     }
+    timing::record_effect(timing::Pass::unreachable_code, removed);
 }
@@ -0,0 +1,121 @@
+//! Tests of the process-wide timing switches.
+//!
+//! The switches affect every thread, so these tests live in their own test binary where they
+//! can't disturb the timing unit tests, and they are serialized with `TOGGLES`.
+
+#![cfg(not(feature = "no-timing"))]
+
+extern crate cretonne;
+
+use cretonne::timing::{self, Pass};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+static TOGGLES: Mutex<()> = Mutex::new(());
+
+/// Take exclusive use of the switches, and clear the timings of the current thread.
+fn toggles() -> MutexGuard<'static, ()> {
+    let guard = TOGGLES.lock().unwrap_or_else(|e| e.into_inner());
+    timing::take_current();
+    guard
+}
+
+#[test]
+fn enabled() {
+    let _toggles = toggles();
+    assert!(timing::is_enabled());
+    timing::set_enabled(false);
+    assert!(!timing::is_enabled());
+    let _ = timing::compile();
+    timing::set_enabled(true);
+    assert!(timing::take_current().is_empty());
+
+    let _ = timing::compile();
+    assert_eq!(timing::take_current().stats(Pass::compile).count, 1);
+}
+
+#[test]
+fn recording() {
+    let _toggles = toggles();
+    {
+        let _compile = timing::compile();
+        timing::set_recording(false);
+        assert!(!timing::is_recording());
+        let _ = timing::gvn();
+        timing::record_effect(Pass::compile, 3);
+    }
+    timing::set_recording(true);
+    assert!(timing::is_recording());
+
+    // The pass started before pausing is still accounted for.
+    let times = timing::take_current();
+    assert_eq!(times.stats(Pass::compile).count, 1);
+    assert_eq!(times.stats(Pass::gvn).count, 0);
+    assert_eq!(times.rows().next().unwrap().effect, None);
+}
+
+#[test]
+fn global() {
+    let _toggles = toggles();
+    timing::take_global();
+    timing::set_global(true);
+    assert!(timing::is_global());
+    thread::spawn(|| { let _ = timing::compile(); }).join().unwrap();
+    let _ = timing::compile();
+    timing::set_global(false);
+    assert!(!timing::is_global());
+
+    assert!(timing::take_current().is_empty());
+    assert_eq!(timing::take_global().stats(Pass::compile).count, 2);
+    assert!(timing::take_global().is_empty());
+}
+
+#[test]
+fn enabled_passes() {
+    let _toggles = toggles();
+    timing::set_enabled_passes(&[Pass::compile]);
+    assert!(timing::is_pass_enabled(Pass::compile));
+    assert!(!timing::is_pass_enabled(Pass::gvn));
+    {
+        let _compile = timing::compile();
+        let _ = timing::gvn();
+    }
+    timing::set_enabled_passes(Pass::all());
+    assert!(timing::is_pass_enabled(Pass::gvn));
+
+    // The masked pass records nothing, and its time is part of the pass it ran in.
+    let times = timing::take_current();
+    assert_eq!(times.stats(Pass::compile).count, 1);
+    assert_eq!(times.stats(Pass::gvn), Default::default());
+    assert_eq!(times.rows().count(), 1);
+}
+
+#[test]
+fn overhead_limit() {
+    let _toggles = toggles();
+    // Calibrate with the real clock before installing the test clock.
+    timing::set_overhead_limit(Some(0.01));
+    let clock = timing::set_clock_for_testing();
+
+    // Passes that each take a second are far above the cost of timing them.
+    for _ in 0..10 {
+        let _compile = timing::compile();
+        clock.advance(Duration::from_secs(1));
+    }
+    timing::take_current();
+    assert!(timing::is_enabled());
+
+    // Passes that take no time at all are all overhead.
+    for _ in 0..10 {
+        let _compile = timing::compile();
+        clock.advance(Duration::new(0, 1));
+    }
+    timing::take_current();
+    assert!(!timing::is_enabled());
+
+    // Removing the limit enables timing again.
+    timing::set_overhead_limit(None);
+    timing::set_clock(None);
+    assert!(timing::is_enabled());
+}
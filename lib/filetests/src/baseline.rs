@@ -0,0 +1,251 @@
+//! Saving and comparing test suite timing baselines.
+//!
+//! A `RunSummary` can be saved to a file as a baseline and loaded back by a later run, which can
+//! then fail if any test file got slower than in the baseline with `compare()`. This is the test
+//! suite analog of `PassTimes::regressions()`, and the aggregated pass timings of the two runs
+//! can be compared with that.
+//!
+//! The file format is line-based text. The first line identifies the format, and the following
+//! lines record the wall time and skipped count of the run and the duration of each test that
+//! passed. The aggregated pass timings follow a `times` line in the `PassTimes` file format:
+//!
+//! ```text
+//! cretonne-filetests-baseline 1
+//! wall <ns>
+//! skipped <count>
+//! file <ns> <path>
+//! times
+//! cretonne-timing 1
+//! ...
+//! ```
+//!
+//! Failed and timed out tests are not saved since their durations can't be compared.
+
+use cretonne::timing::PassTimes;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use summary::{FileResult, RunSummary};
+use {nanos, secs};
+
+/// First line of a baseline file.
+const HEADER: &str = "cretonne-filetests-baseline 1";
+
+/// Parse a number of nanoseconds.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let ns = s.parse::<u64>().map_err(
+        |e| format!("bad duration '{}': {}", s, e),
+    )?;
+    Ok(Duration::new(ns / 1_000_000_000, (ns % 1_000_000_000) as u32))
+}
+
+impl RunSummary {
+    /// Encode this summary in the baseline file format.
+    pub fn encode(&self) -> String {
+        let mut s = format!("{}\n", HEADER);
+        s += &format!("wall {}\n", nanos(self.wall_time));
+        s += &format!("skipped {}\n", self.skipped);
+        for f in self.files.iter().filter(|f| f.passed()) {
+            s += &format!("file {} {}\n", nanos(f.duration), f.path.display());
+        }
+        s += "times\n";
+        s += &self.times.encode();
+        s
+    }
+
+    /// Decode a summary in the baseline file format.
+    ///
+    /// The tests are numbered in the order they appear, starting from 0. Pass timings for passes
+    /// that no longer exist are dropped, as with `PassTimes::load_lenient()`, and their names are
+    /// available from `RunSummary::ignored_passes()`.
+    pub fn decode(data: &str) -> Result<RunSummary, String> {
+        let mut lines = data.lines();
+        if lines.next() != Some(HEADER) {
+            return Err("not a cretonne baseline file".to_string());
+        }
+
+        let mut summary = RunSummary::new();
+        for line in lines.by_ref() {
+            let mut fields = line.splitn(3, ' ');
+            match (fields.next(), fields.next(), fields.next()) {
+                (Some(""), None, None) => {}
+                (Some("wall"), Some(dur), None) => summary.wall_time = parse_duration(dur)?,
+                (Some("skipped"), Some(count), None) => {
+                    summary.skipped = count.parse::<usize>().map_err(|e| {
+                        format!("bad count '{}': {}", count, e)
+                    })?
+                }
+                (Some("file"), Some(dur), Some(path)) => {
+                    let jobid = summary.files.len();
+                    summary.files.push(FileResult {
+                        jobid,
+                        path: PathBuf::from(path),
+                        duration: parse_duration(dur)?,
+                        error: None,
+                    });
+                }
+                (Some("times"), None, None) => break,
+                _ => return Err(format!("malformed line: {}", line)),
+            }
+        }
+        let times: Vec<&str> = lines.collect();
+        let (times, ignored) = PassTimes::load_lenient(&times.join("\n"))?;
+        summary.times = times;
+        summary.ignored_passes = ignored;
+        Ok(summary)
+    }
+
+    /// Save this summary as a baseline to the file at `path`.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.encode())
+    }
+
+    /// Load a baseline summary from the file at `path`.
+    pub fn load(path: &Path) -> io::Result<RunSummary> {
+        RunSummary::decode(&fs::read_to_string(path)?).map_err(|msg| {
+            io::Error::new(io::ErrorKind::InvalidData, msg)
+        })
+    }
+}
+
+/// A test file that got slower than in a baseline, as returned by `compare()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileRegression {
+    /// The path of the test file.
+    pub path: PathBuf,
+
+    /// Time spent running the test in the baseline.
+    pub baseline: Duration,
+
+    /// Time spent running the test now.
+    pub current: Duration,
+}
+
+impl fmt::Display for FileRegression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (old, new) = (secs(self.baseline), secs(self.current));
+        write!(
+            f,
+            "{}: {:.3}s -> {:.3}s ({:+.1}%)",
+            self.path.display(),
+            old,
+            new,
+            (new / old - 1.0) * 100.0
+        )
+    }
+}
+
+/// Get the fastest passing run of each test file in `summary`.
+fn fastest(summary: &RunSummary) -> BTreeMap<&Path, Duration> {
+    let mut files = BTreeMap::new();
+    for f in summary.files.iter().filter(|f| f.passed()) {
+        let dur = files.entry(f.path.as_path()).or_insert(f.duration);
+        *dur = (*dur).min(f.duration);
+    }
+    files
+}
+
+/// Find the test files that got slower than in `baseline` by more than `threshold`.
+///
+/// The threshold is relative, so 0.1 allows a test to be 10% slower than in the baseline. Only
+/// tests that passed in both runs are compared, and a test that ran more than once is represented
+/// by its fastest run. The regressions are sorted by path.
+pub fn compare(baseline: &RunSummary, current: &RunSummary, threshold: f64) -> Vec<FileRegression> {
+    let old = fastest(baseline);
+    fastest(current)
+        .into_iter()
+        .filter_map(|(path, new)| {
+            old.get(path).and_then(|&old| if secs(new) > secs(old) * (1.0 + threshold) {
+                Some(FileRegression {
+                    path: path.to_owned(),
+                    baseline: old,
+                    current: new,
+                })
+            } else {
+                None
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use concurrent::Reply;
+    use cretonne::timing;
+
+    #[test]
+    fn ignored_passes() {
+        let summary = RunSummary::decode(
+            "cretonne-filetests-baseline 1\nwall 5\nskipped 0\nfile 3 a.cton\ntimes\n\
+             cretonne-timing 1\npass old_pass 1 0 1\n",
+        ).unwrap();
+        assert_eq!(summary.files().len(), 1);
+        // Without timing, all the passes are dropped silently.
+        if timing::is_enabled() {
+            assert_eq!(summary.ignored_passes(), ["old_pass"]);
+        } else {
+            assert!(summary.ignored_passes().is_empty());
+        }
+        assert!(RunSummary::new().ignored_passes().is_empty());
+    }
+
+    #[test]
+    fn encode() {
+        let mut summary = RunSummary::new();
+        let ok = Ok(Duration::default());
+        let err = Err("boom".to_string());
+        summary.add_reply(Path::new("a.cton"), &Reply::done(0, ok, Duration::new(2, 5)));
+        summary.add_reply(Path::new("b.cton"), &Reply::done(1, err, Duration::new(1, 0)));
+        summary.add_reply(Path::new("c.cton"), &Reply::Skipped { jobid: 2 });
+        summary.wall_time = Duration::from_millis(1200);
+
+        // The failed test isn't saved.
+        let encoded = summary.encode();
+        assert_eq!(
+            encoded,
+            format!(
+                "cretonne-filetests-baseline 1\nwall 1200000000\nskipped 1\n\
+                 file 2000000005 a.cton\ntimes\n{}",
+                PassTimes::default().encode()
+            )
+        );
+        let decoded = RunSummary::decode(&encoded).unwrap();
+        assert_eq!(decoded.wall_time(), summary.wall_time());
+        assert_eq!(decoded.skipped(), 1);
+        assert_eq!(decoded.files().len(), 1);
+        assert_eq!(decoded.files()[0].path, Path::new("a.cton"));
+        assert_eq!(decoded.files()[0].duration, Duration::new(2, 5));
+        assert_eq!(decoded.encode(), encoded);
+    }
+
+    #[test]
+    fn regressions() {
+        let baseline = RunSummary::decode(
+            "cretonne-filetests-baseline 1\nfile 3000000000 a.cton\nfile 2000000000 a.cton\n\
+             file 1000000000 b.cton\ntimes\ncretonne-timing 1\n",
+        ).unwrap();
+        let current = RunSummary::decode(
+            "cretonne-filetests-baseline 1\nfile 2100000000 a.cton\nfile 1500000000 b.cton\n\
+             file 9000000000 c.cton\ntimes\ncretonne-timing 1\n",
+        ).unwrap();
+
+        // a.cton is compared with its fastest run, and c.cton isn't in the baseline.
+        let regressions = compare(&baseline, &current, 0.1);
+        assert_eq!(
+            regressions,
+            [
+                FileRegression {
+                    path: PathBuf::from("b.cton"),
+                    baseline: Duration::from_secs(1),
+                    current: Duration::from_millis(1500),
+                },
+            ]
+        );
+        assert_eq!(regressions[0].to_string(), "b.cton: 1.000s -> 1.500s (+50.0%)");
+        assert!(compare(&baseline, &current, 0.5).is_empty());
+    }
+}
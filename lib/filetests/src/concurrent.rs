@@ -5,150 +5,2972 @@
 
 use cretonne::timing;
 use num_cpus;
-use std::panic::catch_unwind;
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet, VecDeque};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::iter;
+use std::mem;
+use std::ops::Range;
+use std::panic::{self, catch_unwind, resume_unwind, AssertUnwindSafe};
 use std::path::{Path, PathBuf};
-use std::sync::mpsc::{channel, Receiver, Sender};
-use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, SendError, Sender, TryRecvError};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Once, Weak};
 use std::thread;
-use std::time::Duration;
-use {runone, TestResult};
+use std::time::{Duration, Instant};
+use report::{ReportEntry, RunReport};
+use {interrupt, millis, output, runone, TestResult};
 
-/// Request sent to worker threads contains jobid and path.
-struct Request(usize, PathBuf);
+/// A job run by the worker threads on each submitted file.
+type JobFn = Arc<dyn Fn(&Path) -> TestResult + Send + Sync>;
+
+/// A filter selecting the files to run, see `ConcurrentRunner::set_filter()`.
+type FilterFn = Box<dyn Fn(&Path) -> bool + Send>;
+
+/// A hook called with each reply, see `ConcurrentRunner::on_reply()`.
+type ObserverFn = Box<dyn FnMut(&Reply) + Send>;
+
+/// Request sent to worker threads contains jobid, path, and the time the job was submitted.
+struct Request(usize, PathBuf, Instant);
+
+/// A request waiting in the job queue.
+struct Queued {
+    priority: i32,
+    /// Sequence number of the request, keeping requests of equal priority in FIFO order.
+    seq: u64,
+    request: Request,
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == cmp::Ordering::Equal
+    }
+}
+
+impl Eq for Queued {}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Queued {
+    /// The greatest request is the one to run next: It has the highest priority, and was
+    /// submitted first among those.
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        self.priority.cmp(&other.priority).then(
+            other.seq.cmp(&self.seq),
+        )
+    }
+}
+
+/// Bounded queue of requests shared between the runner and the worker threads.
+///
+/// Workers pull the request with the highest priority first.
+struct JobQueue {
+    state: Mutex<QueueState>,
+
+    /// Maximum number of queued requests.
+    capacity: usize,
+
+    /// Signaled when a request is queued or the queue is closed.
+    not_empty: Condvar,
+
+    /// Signaled when a request is pulled.
+    not_full: Condvar,
+}
+
+struct QueueState {
+    requests: BinaryHeap<Queued>,
+
+    /// Sequence number of the next request.
+    next_seq: u64,
+
+    /// No more requests can be queued, and workers exit when the queue is empty.
+    closed: bool,
+
+    /// Time after which the queued requests are no longer pulled, if any.
+    deadline: Option<Instant>,
+}
+
+impl QueueState {
+    fn past_deadline(&self) -> bool {
+        self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+impl JobQueue {
+    fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                requests: BinaryHeap::new(),
+                next_seq: 0,
+                closed: false,
+                deadline: None,
+            }),
+            capacity,
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+        }
+    }
+
+    /// Queue `request`, blocking while the queue is full.
+    ///
+    /// Hands the request back if the deadline passes while waiting.
+    fn push(&self, request: Request, priority: i32) -> Result<(), Request> {
+        let mut state = self.state.lock().unwrap();
+        while state.requests.len() >= self.capacity {
+            if state.past_deadline() {
+                return Err(request);
+            }
+            state = Self::wait(&self.not_full, state);
+        }
+        Self::insert(&mut state, request, priority);
+        self.not_empty.notify_one();
+        Ok(())
+    }
+
+    /// Queue `request` if the queue isn't full, or hand it back.
+    fn try_push(&self, request: Request, priority: i32) -> Result<(), Request> {
+        let mut state = self.state.lock().unwrap();
+        if state.requests.len() >= self.capacity {
+            return Err(request);
+        }
+        Self::insert(&mut state, request, priority);
+        self.not_empty.notify_one();
+        Ok(())
+    }
+
+    fn insert(state: &mut QueueState, request: Request, priority: i32) {
+        assert!(!state.closed, "cannot push after shutdown");
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.requests.push(Queued {
+            priority,
+            seq,
+            request,
+        });
+    }
+
+    /// Pull the next request, blocking while the queue is empty.
+    ///
+    /// Returns `None` when the queue is empty and closed, or when the deadline has passed.
+    fn pop(&self) -> Option<Request> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.past_deadline() {
+                return None;
+            }
+            if let Some(queued) = state.requests.pop() {
+                self.not_full.notify_one();
+                return Some(queued.request);
+            }
+            if state.closed {
+                return None;
+            }
+            state = Self::wait(&self.not_empty, state);
+        }
+    }
+
+    /// Wait for `condvar` to be signaled, or for the deadline to pass.
+    fn wait<'a>(
+        condvar: &Condvar,
+        state: MutexGuard<'a, QueueState>,
+    ) -> MutexGuard<'a, QueueState> {
+        match state.deadline {
+            Some(deadline) => {
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                condvar.wait_timeout(state, timeout).unwrap().0
+            }
+            None => condvar.wait(state).unwrap(),
+        }
+    }
+
+    /// Set the time after which the queued requests are no longer pulled.
+    fn set_deadline(&self, deadline: Option<Instant>) {
+        self.state.lock().unwrap().deadline = deadline;
+        // Let the waiting threads see the new deadline.
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+    }
+
+    fn past_deadline(&self) -> bool {
+        self.state.lock().unwrap().past_deadline()
+    }
+
+    /// Close the queue, so the workers exit once they have run the queued requests.
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.not_empty.notify_all();
+    }
+
+    fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().closed
+    }
+
+    /// Remove the queued request for job `jobid`, if any.
+    fn remove(&self, jobid: usize) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let len = state.requests.len();
+        state.requests.retain(|queued| queued.request.0 != jobid);
+        self.removed(len - state.requests.len())
+    }
+
+    /// Remove all the queued requests, and return their jobids.
+    fn clear(&self) -> Vec<usize> {
+        let mut state = self.state.lock().unwrap();
+        let jobids: Vec<usize> = state.requests.drain().map(|queued| queued.request.0).collect();
+        self.removed(jobids.len());
+        jobids
+    }
+
+    /// Wake up the threads blocked on a full queue after `count` requests were removed.
+    fn removed(&self, count: usize) -> bool {
+        for _ in 0..count {
+            self.not_full.notify_one();
+        }
+        count > 0
+    }
+}
 
 /// Reply from worker thread,
 pub enum Reply {
-    Starting { jobid: usize, thread_num: usize },
-    Done { jobid: usize, result: TestResult },
+    /// A worker is starting a job. `queue_latency` is the time the job spent waiting in the queue
+    /// since it was submitted.
+    Starting {
+        jobid: usize,
+        thread_num: usize,
+        queue_latency: Duration,
+    },
+    /// A job is done. `times` holds the pass timings of this job alone, and `duration` is the
+    /// wall-clock time the worker spent on it, including work outside of timed passes.
+    ///
+    /// `attempts` is the number of times the job was run. It is more than 1 if the job failed
+    /// and was retried, see `ConcurrentRunner::set_max_retries()`. The timings and duration
+    /// cover all the attempts.
+    ///
+    /// `output` is what the job wrote to `TestOutput` during its last run.
+    ///
+    /// With `ConcurrentRunner::set_repetitions()`, each attempt runs the job several times, and
+    /// `stats` holds the mean and standard deviation of the time spent in each pass over the
+    /// runs of the last attempt. Otherwise, it holds the single run of the last attempt.
+    Done {
+        jobid: usize,
+        result: TestResult,
+        times: Box<timing::PassTimes>,
+        duration: Duration,
+        attempts: u32,
+        output: String,
+        stats: Box<timing::PassTimesStats>,
+    },
+    /// A job has been running for longer than the timeout set with `set_job_timeout()`. It is
+    /// treated as failed, and no `Done` reply will be sent for it.
+    Timeout { jobid: usize },
+    /// A job was submitted for a file rejected by the filter set with `set_filter()`. It isn't
+    /// run, and no `Done` reply will be sent for it.
+    Skipped { jobid: usize },
+    /// Heartbeat delivered once per tick interval, see `ConcurrentRunner::set_tick_interval()`.
+    /// This is a good time to poll `ConcurrentRunner::pending()` and
+    /// `ConcurrentRunner::in_flight()` to report progress.
+    ///
+    /// Ticks aren't sent by the workers. They are synthesized by the runner while it is waiting
+    /// for replies, and jobs are checked for timeouts on every tick.
     Tick,
 }
 
+impl Reply {
+    /// Get the jobid of the job completed by this reply, if it is a `Done`, `Timeout`, or
+    /// `Skipped` reply.
+    fn completed_jobid(&self) -> Option<usize> {
+        match *self {
+            Reply::Done { jobid, .. } |
+            Reply::Timeout { jobid } |
+            Reply::Skipped { jobid } => Some(jobid),
+            _ => None,
+        }
+    }
+
+    /// Create the `Reply::Done` of job `jobid`, which ran once for `duration` without timing
+    /// any pass.
+    #[cfg(test)]
+    pub(crate) fn done(jobid: usize, result: TestResult, duration: Duration) -> Reply {
+        Reply::Done {
+            jobid,
+            result,
+            times: Box::default(),
+            duration,
+            attempts: 1,
+            output: String::new(),
+            stats: Box::default(),
+        }
+    }
+}
+
+/// What worker threads do when a test panics.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PanicPolicy {
+    /// Catch the panic and report it as a test failure. This is the default.
+    Catch,
+    /// Let the panic unwind the worker thread. It is propagated to the caller of `join()`.
+    Abort,
+}
+
+/// Why `ConcurrentRunner::join_timeout()` failed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum JoinError {
+    /// These worker threads, by thread number, were still running at the deadline.
+    Running(Vec<usize>),
+    /// All the worker threads were joined, but these jobs were submitted and never completed.
+    Incomplete(Vec<usize>),
+}
+
+/// The jobs currently being run by the worker threads.
+///
+/// This is shared between the workers and the runner, which checks for jobs that have timed out
+/// on every tick.
+#[derive(Default)]
+struct Running {
+    /// Maximum time a job may run for, if any.
+    timeout: Option<Duration>,
+
+    /// Start time, worker thread number, and path of each running job, by jobid.
+    jobs: HashMap<usize, (Instant, usize, PathBuf)>,
+
+    /// Worker threads that are still running a job that has timed out.
+    stuck: HashSet<usize>,
+
+    /// Jobs cancelled after a worker pulled them from the queue, but before it started them.
+    cancelled: HashSet<usize>,
+}
+
+/// Number of jobs at each stage, shared with the worker threads.
+#[derive(Default)]
+struct Progress {
+    /// Jobs submitted but not yet picked up by a worker.
+    queued: AtomicUsize,
+
+    /// Jobs picked up by a worker that haven't finished running.
+    running: AtomicUsize,
+}
+
+/// Schedule of the `Reply::Tick` heartbeats.
+struct Ticker {
+    /// Time between ticks.
+    interval: Duration,
+
+    /// Time of the next tick.
+    next: Instant,
+}
+
+impl Ticker {
+    fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            next: Instant::now() + interval,
+        }
+    }
+
+    /// Is a tick due? If so, schedule the next one.
+    fn due(&mut self) -> bool {
+        let now = Instant::now();
+        if now < self.next {
+            return false;
+        }
+        self.next = now + self.interval;
+        true
+    }
+
+    /// Get the time left until the next tick is due.
+    fn remaining(&self) -> Duration {
+        self.next.saturating_duration_since(Instant::now())
+    }
+}
+
+/// The replies received from the worker threads that haven't been delivered yet.
+///
+/// This is shared with the forwarding thread after `ConcurrentRunner::forward_to()`, so
+/// forwarded replies are delivered the same way as the replies returned by `get()`.
+struct Inbox {
+    /// Replies ready for delivery.
+    pending: VecDeque<Reply>,
+
+    /// Deliver the completion replies in jobid order?
+    ordered: bool,
+
+    /// In ordered mode, completion replies held back until the jobs before them complete.
+    held: BTreeMap<usize, Reply>,
+
+    /// In ordered mode, the jobids of the submitted jobs whose completion hasn't been delivered.
+    undelivered: BTreeSet<usize>,
+
+    /// Jobs that were never run because the deadline passed before a worker could pick them up.
+    not_run: BTreeSet<usize>,
+
+    /// When to deliver the next `Reply::Tick`.
+    ticker: Ticker,
+
+    /// Hook called with each reply as it is received.
+    observer: Option<ObserverFn>,
+
+    /// Queue of requests of the runner, cleared once the deadline has passed.
+    requests: Arc<JobQueue>,
+
+    /// Results of the jobs completed so far, where timed out jobs are recorded.
+    completed: Arc<Mutex<HashMap<usize, ReportEntry>>>,
+
+    /// The jobs currently being run by the workers, checked for timeouts.
+    running: Arc<Mutex<Running>>,
+
+    /// Number of jobs queued and running.
+    progress: Arc<Progress>,
+}
+
+impl Inbox {
+    /// Get the next reply ready for delivery, or a `Reply::Tick` if one is due.
+    fn next_ready(&mut self) -> Option<Reply> {
+        loop {
+            if let Some(reply) = self.pending.pop_front() {
+                return Some(reply);
+            }
+            if !self.ticker.due() {
+                return None;
+            }
+            self.tick();
+        }
+    }
+
+    /// Queue a `Reply::Tick` for delivery, followed by the timeouts of the jobs that have
+    /// expired. Also drop the queued jobs if the deadline has passed.
+    fn tick(&mut self) {
+        self.accept(Reply::Tick);
+        self.expire_jobs();
+        self.drop_expired();
+    }
+
+    /// Report the jobs that have exceeded the job timeout as failed, and queue their
+    /// `Reply::Timeout` for delivery.
+    fn expire_jobs(&mut self) {
+        for reply in timeout_replies(&self.completed, &self.running) {
+            self.accept(reply);
+        }
+    }
+
+    /// Once the deadline has passed, remove the queued jobs and record them as never run.
+    fn drop_expired(&mut self) {
+        if !self.requests.past_deadline() {
+            return;
+        }
+        for jobid in self.requests.clear() {
+            self.progress.queued.fetch_sub(1, Ordering::Relaxed);
+            self.not_run.insert(jobid);
+            self.undelivered.remove(&jobid);
+        }
+        self.release_ready();
+    }
+
+    /// Queue a reply received from the workers for delivery, after passing it to the observer.
+    ///
+    /// In ordered mode, completion replies are held back until they can be delivered in order.
+    fn accept(&mut self, reply: Reply) {
+        if let Some(ref mut observer) = self.observer {
+            observer(&reply);
+        }
+        match reply.completed_jobid() {
+            Some(jobid) if self.ordered => {
+                self.held.insert(jobid, reply);
+                self.release_ready();
+            }
+            _ => self.pending.push_back(reply),
+        }
+    }
+
+    /// Stop waiting for the completion of job `jobid`, which will never be delivered.
+    ///
+    /// In ordered mode, the job no longer holds back the jobs after it.
+    fn forget(&mut self, jobid: usize) {
+        if self.undelivered.remove(&jobid) {
+            self.release_ready();
+        }
+    }
+
+    /// Queue the held completion replies that are next in jobid order for delivery.
+    fn release_ready(&mut self) {
+        while let Some(&jobid) = self.undelivered.iter().next() {
+            match self.held.remove(&jobid) {
+                Some(reply) => {
+                    self.undelivered.remove(&jobid);
+                    self.pending.push_back(reply);
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Queue all the held completion replies for delivery, in jobid order, and get the next
+    /// reply.
+    ///
+    /// This is used when no more replies can arrive, so jobs that never completed don't hold
+    /// back the others forever.
+    fn release_held(&mut self) -> Option<Reply> {
+        for (jobid, reply) in mem::take(&mut self.held) {
+            self.undelivered.remove(&jobid);
+            self.pending.push_back(reply);
+        }
+        self.pending.pop_front()
+    }
+
+    /// Remove the completion reply of job `jobid` from the received replies, if it has been
+    /// received.
+    fn take_completion(&mut self, jobid: usize) -> Option<Reply> {
+        let reply = match self.held.remove(&jobid) {
+            Some(reply) => reply,
+            None => {
+                let idx = self.pending.iter().position(
+                    |reply| reply.completed_jobid() == Some(jobid),
+                )?;
+                self.pending.remove(idx)?
+            }
+        };
+        self.forget(jobid);
+        Some(reply)
+    }
+}
+
+/// Sending end of the reply channel.
+///
+/// With the `async` feature, a consumer awaiting a reply is woken up after each send, and when
+/// the sender is dropped.
+#[derive(Clone)]
+struct ReplySender {
+    tx: Sender<Reply>,
+    #[cfg(feature = "async")]
+    waker: Arc<async_io::WakerSlot>,
+}
+
+impl ReplySender {
+    fn send(&self, reply: Reply) -> Result<(), SendError<Reply>> {
+        let result = self.tx.send(reply);
+        #[cfg(feature = "async")]
+        self.waker.wake();
+        result
+    }
+}
+
+#[cfg(feature = "async")]
+impl Drop for ReplySender {
+    fn drop(&mut self) {
+        // The receiver may be waiting to see the channel disconnect.
+        self.waker.wake();
+    }
+}
+
 /// Manage threads that run test jobs concurrently.
 pub struct ConcurrentRunner {
-    /// Channel for sending requests to the worker threads.
-    /// The workers are sharing the receiver with an `Arc<Mutex<Receiver>>`.
-    /// This is `None` when shutting down.
-    request_tx: Option<Sender<Request>>,
+    /// Queue of requests shared with the worker threads.
+    /// The queue is bounded, so submitting jobs applies backpressure when the workers fall
+    /// behind.
+    /// It is closed when shutting down.
+    requests: Arc<JobQueue>,
 
     /// Channel for receiving replies from the workers.
     /// Workers have their own `Sender`.
     reply_rx: Receiver<Reply>,
 
-    handles: Vec<thread::JoinHandle<timing::PassTimes>>,
+    /// Sender for replies, cloned for new worker threads.
+    /// This is `None` when shutting down.
+    reply_tx: Option<ReplySender>,
+
+    /// Waker of a task awaiting the next reply.
+    #[cfg(feature = "async")]
+    waker: Arc<async_io::WakerSlot>,
+
+    /// The job run on each submitted file.
+    job: JobFn,
+
+    /// Maximum number of worker threads to spawn.
+    max_threads: usize,
+
+    /// Spawn worker threads one at a time as jobs are submitted?
+    lazy: bool,
+
+    /// What worker threads do when a test panics.
+    panic_policy: PanicPolicy,
+
+    /// Number of times a failing job is run again before reporting the failure.
+    max_retries: u32,
+
+    /// Number of times each job is run.
+    repetitions: u32,
+
+    /// Pin each worker thread to a CPU?
+    affinity: bool,
+
+    /// Base seed of the workers, if any.
+    seed: Option<u64>,
+
+    /// Label the pass timings of each worker when joining them?
+    track_threads: bool,
+
+    /// Directory receiving the log files of the workers, if any.
+    log_dir: Option<PathBuf>,
+
+    /// Log of the jobs run inline in serial mode.
+    serial_log: Option<Arc<WorkerLog>>,
+
+    /// Filter selecting the submitted files that are run, if any.
+    filter: Option<FilterFn>,
+
+    /// Jobs skipped because their file was rejected by the filter.
+    skipped: HashSet<usize>,
+
+    /// Replies received but not yet delivered, shared with the forwarding thread.
+    inbox: Arc<Mutex<Inbox>>,
+
+    /// Are the replies forwarded by `forward_to()`?
+    forwarded: bool,
+
+    /// The forwarding thread, until it is joined.
+    forwarder: Option<thread::JoinHandle<()>>,
+
+    /// Worker threads by thread number. A handle is taken when its thread has been joined by
+    /// `join_timeout()`, while the threads that are still running are joined later.
+    handles: Vec<Option<thread::JoinHandle<timing::PassTimes>>>,
+
+    /// Paths of the jobs submitted so far, indexed by jobid.
+    jobs: HashMap<usize, PathBuf>,
+
+    /// Jobs cancelled before they completed.
+    cancelled: HashSet<usize>,
+
+    /// Results and timing summaries of the jobs completed by the workers so far, by jobid.
+    completed: Arc<Mutex<HashMap<usize, ReportEntry>>>,
+
+    /// The jobs currently being run by the workers.
+    running: Arc<Mutex<Running>>,
+
+    /// Number of jobs queued and running.
+    progress: Arc<Progress>,
+
+    /// Next jobid to be allocated by `submit_dir()`.
+    next_jobid: usize,
+
+    /// Label identifying this run in timing reports.
+    label: String,
+
+    /// Run jobs inline on the calling thread instead of on worker threads?
+    serial: bool,
+
+    /// In serial mode, the pass timings of the jobs run so far.
+    serial_times: timing::PassTimes,
+
+    /// Messages of the worker threads that panicked and of the jobs that never completed, not
+    /// yet returned by `join()`.
+    errors: Vec<String>,
+}
+
+impl Default for ConcurrentRunner {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ConcurrentRunner {
-    /// Create a new `ConcurrentRunner` with threads spun up.
+    /// Create a new `ConcurrentRunner`.
+    ///
+    /// One worker thread per CPU is spun up when the first job is submitted. The number of
+    /// threads can be overridden with the `CRETONNE_TEST_THREADS` environment variable.
     pub fn new() -> Self {
-        let (request_tx, request_rx) = channel();
-        let request_mutex = Arc::new(Mutex::new(request_rx));
-        let (reply_tx, reply_rx) = channel();
+        Self::new_labeled("")
+    }
+
+    /// Create a new `ConcurrentRunner` with exactly `n` worker threads.
+    ///
+    /// Unlike `new()`, this ignores the `CRETONNE_TEST_THREADS` environment variable. Panics if
+    /// `n` is 0.
+    pub fn with_threads(n: usize) -> Self {
+        assert!(n > 0, "a ConcurrentRunner needs at least one worker thread");
+        Self::build("", n, Arc::new(runone::run), false)
+    }
 
-        heartbeat_thread(reply_tx.clone());
+    /// Create a `ConcurrentRunner` that runs each job inline on the calling thread.
+    ///
+    /// This is meant for debugging: No worker thread is spawned, and `put()` runs
+    /// the job before returning, so the replies are immediately available from `get()`. The panic
+    /// policy defaults to `PanicPolicy::Abort`, so a panicking test unwinds straight through
+    /// `put()` with its full backtrace. Job timeouts have no effect, since a job is never
+    /// observed while it runs.
+    pub fn serial() -> Self {
+        let mut runner = Self::build("", 1, Arc::new(runone::run), true);
+        runner.panic_policy = PanicPolicy::Abort;
+        runner
+    }
 
-        let handles = (0..num_cpus::get())
-            .map(|num| {
-                worker_thread(num, request_mutex.clone(), reply_tx.clone())
-            })
-            .collect();
+    /// Create a new `ConcurrentRunner` running `job` on each submitted file instead of running
+    /// it as a test.
+    ///
+    /// This makes the thread pool, job timeouts, and timing aggregation available for other
+    /// per-file work, e.g. compilation benchmarks. Panics in `job` are handled like panics in
+    /// tests, according to the panic policy.
+    pub fn with_job<F>(job: F) -> Self
+    where
+        F: Fn(&Path) -> TestResult + Send + Sync + 'static,
+    {
+        Self::build("", default_threads(), Arc::new(job), false)
+    }
+
+    /// Create a new `ConcurrentRunner` whose run is identified by `label`.
+    ///
+    /// The label is returned with the aggregated pass timings by `join_tagged()`, so timings from
+    /// multiple runners (e.g., one per target ISA) can be combined in a self-describing report.
+    /// It has no effect on how the jobs are run.
+    pub fn new_labeled(label: &str) -> Self {
+        Self::build(label, default_threads(), Arc::new(runone::run), false)
+    }
+
+    /// Create a new `ConcurrentRunner` labeled `label` with up to `max_threads` worker threads
+    /// running `job`.
+    ///
+    /// The request queue holds up to 4 jobs per worker thread. In `serial` mode, jobs are run
+    /// inline instead.
+    fn build(label: &str, max_threads: usize, job: JobFn, serial: bool) -> Self {
+        let (tx, reply_rx) = channel();
+        #[cfg(feature = "async")]
+        let waker = Arc::new(async_io::WakerSlot::default());
+        let reply_tx = ReplySender {
+            tx,
+            #[cfg(feature = "async")]
+            waker: waker.clone(),
+        };
+
+        let requests = Arc::new(JobQueue::new(4 * max_threads));
+        let completed = Arc::new(Mutex::new(HashMap::new()));
+        let running = Arc::new(Mutex::new(Running::default()));
+        let progress = Arc::new(Progress::default());
+        let inbox = Inbox {
+            pending: VecDeque::new(),
+            ordered: false,
+            held: BTreeMap::new(),
+            undelivered: BTreeSet::new(),
+            not_run: BTreeSet::new(),
+            ticker: Ticker::new(Duration::from_secs(1)),
+            observer: None,
+            requests: requests.clone(),
+            completed: completed.clone(),
+            running: running.clone(),
+            progress: progress.clone(),
+        };
 
         Self {
-            request_tx: Some(request_tx),
+            requests,
             reply_rx,
-            handles,
+            reply_tx: Some(reply_tx),
+            #[cfg(feature = "async")]
+            waker,
+            job,
+            max_threads,
+            lazy: false,
+            panic_policy: PanicPolicy::Catch,
+            max_retries: 0,
+            repetitions: 1,
+            affinity: false,
+            seed: None,
+            track_threads: false,
+            log_dir: None,
+            serial_log: None,
+            filter: None,
+            skipped: HashSet::new(),
+            inbox: Arc::new(Mutex::new(inbox)),
+            forwarded: false,
+            forwarder: None,
+            handles: Vec::new(),
+            jobs: HashMap::new(),
+            cancelled: HashSet::new(),
+            completed,
+            running,
+            progress,
+            next_jobid: 0,
+            label: label.to_string(),
+            serial,
+            serial_times: timing::PassTimes::default(),
+            errors: Vec::new(),
         }
     }
 
-    /// Shut down worker threads orderly. They will finish any queued jobs first.
-    pub fn shutdown(&mut self) {
-        self.request_tx = None;
+    /// Get the label identifying this run.
+    pub fn label(&self) -> &str {
+        &self.label
     }
 
-    /// Join all the worker threads.
-    /// Transfer pass timings from the worker threads to the current thread.
-    pub fn join(&mut self) {
-        assert!(self.request_tx.is_none(), "must shutdown before join");
-        for h in self.handles.drain(..) {
-            match h.join() {
-                Ok(t) => timing::add_to_current(&t),
-                Err(e) => println!("worker panicked: {:?}", e),
-            }
+    /// Spawn worker threads lazily, one per submitted job until the maximum number of threads is
+    /// reached.
+    ///
+    /// By default, all the worker threads are spawned when the first job is submitted. The lazy
+    /// mode avoids spinning up a thread per CPU when only a few jobs are run.
+    pub fn set_lazy(&mut self, lazy: bool) {
+        self.lazy = lazy;
+    }
+
+    /// Set what worker threads do when a test panics.
+    ///
+    /// By default, panics are caught and reported as test failures. With `PanicPolicy::Abort`,
+    /// the panic unwinds the worker thread instead, so it can be debugged in its original
+    /// context, and it is propagated by `join()`. Note that the job never completes in that case.
+    ///
+    /// The policy applies to worker threads spawned after this call, so it should be set before
+    /// submitting any jobs.
+    pub fn set_panic_policy(&mut self, policy: PanicPolicy) {
+        self.panic_policy = policy;
+    }
+
+    /// Set the number of times a failing job is run again before its failure is reported.
+    ///
+    /// This is 0 by default. Retrying works around flaky tests: a job that passes on a retry is
+    /// reported as passed, with the number of attempts in `Reply::Done`. A job that fails every
+    /// attempt is reported with the result of the last attempt.
+    ///
+    /// Like the panic policy, this applies to worker threads spawned after this call.
+    pub fn set_max_retries(&mut self, max_retries: u32) {
+        self.max_retries = max_retries;
+    }
+
+    /// Set the number of times each job is run, to measure the variance of its pass timings.
+    ///
+    /// This is 1 by default. With more repetitions, the runner works as a benchmark harness:
+    /// `Reply::Done` has the mean and standard deviation of each pass over the runs, while its
+    /// timings and duration cover all of them. A job stops at its first failing run, which is
+    /// reported like a failing job without repetitions. Panics if `repetitions` is 0.
+    ///
+    /// Like the panic policy, this applies to worker threads spawned after this call.
+    pub fn set_repetitions(&mut self, repetitions: u32) {
+        assert!(repetitions > 0, "jobs must run at least once");
+        self.repetitions = repetitions;
+    }
+
+    /// Pin worker thread #N to CPU N, wrapping around if there are more threads than CPUs.
+    ///
+    /// This is off by default. Pinning keeps the workers from migrating between the sockets of a
+    /// NUMA machine. It is only supported on Linux with the `affinity` feature: Otherwise, or if the
+    /// CPU is unavailable, a warning is printed and the worker runs unpinned.
+    ///
+    /// Like the panic policy, this applies to worker threads spawned after this call.
+    pub fn set_affinity(&mut self, affinity: bool) {
+        self.affinity = affinity;
+    }
+
+    /// Keep track of the pass timings contributed by each worker thread when they are joined.
+    ///
+    /// This is off by default. When on, the aggregated timings returned by `join_tagged()` or
+    /// added to the current thread by `join()` record the timings of worker #N under the label
+    /// `worker #N`, so `PassTimes::contributions()` can tell whether a pass was slow on all the
+    /// threads or only on one overloaded thread.
+    pub fn set_track_threads(&mut self, track: bool) {
+        self.track_threads = track;
+    }
+
+    /// Set the base seed for randomized tests, and print it.
+    ///
+    /// Worker thread #N runs its jobs with the seed `seed ^ N`, which tests get from
+    /// `worker_seed()`. Running a failing test again with the same base seed and a single worker
+    /// thread gives it the same seed. The seed is printed to stderr so it can be recovered from the
+    /// log of a failing run.
+    ///
+    /// Like the panic policy, this applies to worker threads spawned after this call.
+    pub fn set_seed(&mut self, seed: u64) {
+        eprintln!("worker seed: {:#x}", seed);
+        self.seed = Some(seed);
+    }
+
+    /// Make each worker log the jobs it runs to its own file in `dir`.
+    ///
+    /// Worker #N writes to `worker-N.log`, replacing any existing file. Each job is logged with
+    /// a line when it starts, and a line with its outcome and duration when it finishes,
+    /// followed by the output it captured. The log is written unbuffered, so after a crash it
+    /// ends with the file the worker was running.
+    ///
+    /// The directory is created if needed. Like the panic policy, this applies to worker threads
+    /// spawned after this call.
+    pub fn set_log_dir(&mut self, dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+        if self.serial {
+            self.serial_log = Some(Arc::new(WorkerLog::create(dir, 0)?));
         }
+        self.log_dir = Some(dir.to_owned());
+        Ok(())
     }
 
-    /// Add a new job to the queues.
-    pub fn put(&mut self, jobid: usize, path: &Path) {
-        self.request_tx
-            .as_ref()
-            .expect("cannot push after shutdown")
-            .send(Request(jobid, path.to_owned()))
-            .expect("all the worker threads are gone");
+    /// Deliver the `Reply::Done`, `Reply::Timeout`, and `Reply::Skipped` replies in ascending
+    /// jobid order.
+    ///
+    /// By default, replies are delivered as soon as they are received, in the nondeterministic
+    /// order the jobs complete. In ordered mode, a completed job is held back until all the jobs
+    /// submitted with a smaller jobid have completed, which makes the output of a run
+    /// reproducible at the cost of latency. Other replies are still delivered immediately, so a
+    /// consumer waiting for replies keeps receiving heartbeats while jobs are held back.
+    ///
+    /// This must be set before submitting any jobs.
+    pub fn set_ordered(&mut self, ordered: bool) {
+        self.inbox().ordered = ordered;
     }
 
-    /// Get a job reply without blocking.
-    pub fn try_get(&mut self) -> Option<Reply> {
-        self.reply_rx.try_recv().ok()
+    /// Set the maximum time a job may run for, or `None` to let jobs run forever, which is the
+    /// default.
+    ///
+    /// Jobs are checked on every `Reply::Tick`, and a job that has been running for longer than the
+    /// timeout is reported with a `Reply::Timeout` and recorded as a failure. Since a running test
+    /// can't be interrupted, the worker thread keeps running the job in the background, and its
+    /// result is discarded when it eventually completes. The runner doesn't wait for such a
+    /// worker when it is joined, so its pass timings are lost.
+    pub fn set_job_timeout(&mut self, timeout: Option<Duration>) {
+        self.running.lock().unwrap().timeout = timeout;
     }
 
-    /// Get a job reply, blocking until one is available.
-    pub fn get(&mut self) -> Option<Reply> {
-        self.reply_rx.recv().ok()
+    /// Set a deadline for the whole run, or `None` to run all the jobs, which is the default.
+    ///
+    /// Once the deadline has passed, worker threads no longer pick up queued jobs, and jobs
+    /// submitted afterwards aren't queued. The jobs already running are allowed to finish, so
+    /// `join()` returns promptly unless a job is stuck, see `set_job_timeout()`. The jobs that
+    /// never ran are returned by `not_run()`. They get no `Reply::Done`, and they don't count as
+    /// incomplete when the runner is joined.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.requests.set_deadline(deadline);
     }
-}
 
-/// Spawn a heartbeat thread which sends ticks down the reply channel every second.
-/// This lets us implement timeouts without the not yet stable `recv_timeout`.
-fn heartbeat_thread(replies: Sender<Reply>) -> thread::JoinHandle<()> {
-    thread::Builder::new()
-        .name("heartbeat".to_string())
-        .spawn(move || while replies.send(Reply::Tick).is_ok() {
-            thread::sleep(Duration::from_secs(1));
-        })
-        .unwrap()
-}
+    /// Stop running jobs when the process is interrupted with Ctrl-C.
+    ///
+    /// This installs the SIGINT handler with `handle_interrupts()`, and starts a thread that
+    /// watches for the interrupt. When it comes, the deadline is set to the current time: the
+    /// jobs in flight finish and are reported as usual, but the queued jobs are never run, and
+    /// jobs submitted afterwards are skipped. The harness can check `interrupted()` to report
+    /// the partial results after `join()`.
+    ///
+    /// Fails if the handler can't be installed, e.g. without the `ctrlc` feature.
+    pub fn stop_on_interrupt(&mut self) -> io::Result<()> {
+        interrupt::handle_interrupts()?;
+        let queue = Arc::downgrade(&self.requests);
+        thread::Builder::new()
+            .name("interrupt watcher".to_string())
+            .spawn(move || watch_interrupt(&queue))?;
+        Ok(())
+    }
 
-/// Spawn a worker thread running tests.
-fn worker_thread(
-    thread_num: usize,
-    requests: Arc<Mutex<Receiver<Request>>>,
-    replies: Sender<Reply>,
-) -> thread::JoinHandle<timing::PassTimes> {
-    thread::Builder::new()
-        .name(format!("worker #{}", thread_num))
-        .spawn(move || {
-            loop {
-                // Lock the mutex only long enough to extract a request.
-                let Request(jobid, path) = match requests.lock().unwrap().recv() {
-                    Err(..) => break, // TX end shut down. exit thread.
-                    Ok(req) => req,
-                };
+    /// Get the sorted jobids of the jobs that were never run because the deadline passed.
+    ///
+    /// The queued jobs are collected on every `Reply::Tick` and when the runner is joined, so
+    /// this is complete after `join()`.
+    pub fn not_run(&self) -> Vec<usize> {
+        self.inbox().not_run.iter().cloned().collect()
+    }
 
-                // Tell them we're starting this job.
-                // The receiver should always be present for this as long as we have jobs.
-                replies.send(Reply::Starting { jobid, thread_num }).unwrap();
-
-                let result = catch_unwind(|| runone::run(path.as_path())).unwrap_or_else(|e| {
-                    // The test panicked, leaving us a `Box<Any>`.
-                    // Panics are usually strings.
-                    if let Some(msg) = e.downcast_ref::<String>() {
-                        Err(format!("panicked in worker #{}: {}", thread_num, msg))
-                    } else if let Some(msg) = e.downcast_ref::<&'static str>() {
-                        Err(format!("panicked in worker #{}: {}", thread_num, msg))
-                    } else {
-                        Err(format!("panicked in worker #{}", thread_num))
-                    }
-                });
+    /// Set the time between two `Reply::Tick` heartbeats. This is one second by default.
+    ///
+    /// Since ticks are synthesized while waiting for replies, they are only delivered as long as
+    /// replies are requested, and they don't wake up a task awaiting a reply with the async
+    /// interface. Job timeouts are only detected on ticks, so this is also their resolution.
+    pub fn set_tick_interval(&mut self, interval: Duration) {
+        self.inbox().ticker = Ticker::new(interval);
+    }
 
-                if let Err(ref msg) = result {
-                    dbg!("FAIL: {}", msg);
-                }
+    /// Call `hook` with each reply as soon as it is received from the workers, before it is
+    /// queued for delivery.
+    ///
+    /// This lets a harness report progress or log results as they are produced, while the replies
+    /// are still delivered by `get()` and `try_get()`. The hook runs on the thread receiving the
+    /// replies: the caller of `get()`, `try_get()`, or `wait_for()`, or the forwarding thread
+    /// after `forward_to()`. Replies are received as the runner waits for them, so the hook is
+    /// only called while replies are requested. It also sees the `Reply::Tick` heartbeats,
+    /// except while waiting in `wait_for()`.
+    ///
+    /// This replaces any previously registered hook.
+    pub fn on_reply<F>(&mut self, hook: F)
+    where
+        F: FnMut(&Reply) + Send + 'static,
+    {
+        self.inbox().observer = Some(Box::new(hook));
+    }
 
-                replies.send(Reply::Done { jobid, result }).unwrap();
-            }
+    /// Only run the submitted files accepted by `filter`, like `cargo test <filter>`.
+    ///
+    /// A job submitted for a file rejected by the filter isn't queued, and it is reported with a
+    /// `Reply::Skipped` instead of a `Reply::Done`. The number of skipped jobs is returned by
+    /// `num_skipped()`. This replaces any previously set filter.
+    pub fn set_filter<F>(&mut self, filter: F)
+    where
+        F: Fn(&Path) -> bool + Send + 'static,
+    {
+        self.filter = Some(Box::new(filter));
+    }
 
-            // Timing is accumulated independently per thread.
-            // Timings from this worker thread will be aggregated by `ConcurrentRunner::join()`.
-            timing::take_current()
-        })
-        .unwrap()
+    /// Get the number of jobs skipped because their file was rejected by the filter.
+    pub fn num_skipped(&self) -> usize {
+        self.skipped.len()
+    }
+
+    /// Get the number of submitted jobs that no worker thread has started yet.
+    pub fn pending(&self) -> usize {
+        self.progress.queued.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of jobs currently being run by the worker threads.
+    ///
+    /// This includes jobs that have timed out, until their worker thread finishes them.
+    pub fn in_flight(&self) -> usize {
+        self.progress.running.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of worker threads spawned so far.
+    pub fn num_threads(&self) -> usize {
+        self.handles.len()
+    }
+
+    /// Get the settings of worker threads spawned now.
+    fn worker_config(&self) -> WorkerConfig {
+        WorkerConfig {
+            job: self.job.clone(),
+            panic_policy: self.panic_policy,
+            max_retries: self.max_retries,
+            repetitions: self.repetitions,
+            affinity: self.affinity,
+            seed: self.seed,
+        }
+    }
+
+    /// Spawn worker threads as needed for a newly submitted job.
+    fn spawn_workers(&mut self) {
+        let wanted = if self.lazy {
+            self.handles.len() + 1
+        } else {
+            self.max_threads
+        };
+        let reply_tx = self.reply_tx.as_ref().expect("cannot spawn after shutdown");
+        while self.handles.len() < wanted.min(self.max_threads) {
+            let num = self.handles.len();
+            let log = self.log_dir.as_ref().and_then(|dir| match WorkerLog::create(dir, num) {
+                Ok(log) => Some(Arc::new(log)),
+                Err(e) => {
+                    eprintln!("warning: cannot create log for worker #{}: {}", num, e);
+                    None
+                }
+            });
+            let worker = Worker {
+                thread_num: num,
+                log,
+                replies: reply_tx.clone(),
+                completed: self.completed.clone(),
+                running: self.running.clone(),
+                progress: self.progress.clone(),
+                config: self.worker_config(),
+            };
+            self.handles.push(Some(worker_thread(worker, self.requests.clone())));
+        }
+    }
+
+    /// Shut down worker threads orderly. They will finish any queued jobs first.
+    pub fn shutdown(&mut self) {
+        self.requests.close();
+        self.reply_tx = None;
+    }
+
+    /// Join all the worker threads and return their aggregated pass timings.
+    fn join_workers(&mut self) -> timing::PassTimes {
+        assert!(self.requests.is_closed(), "must shutdown before join");
+        let policy = self.panic_policy;
+        // Don't wait for workers stuck on a job that timed out.
+        let stuck = self.running.lock().unwrap().stuck.clone();
+        let handles: Vec<_> = self.handles
+            .drain(..)
+            .enumerate()
+            .filter(|&(num, _)| !stuck.contains(&num))
+            .filter_map(|(num, h)| h.map(|h| (num, h)))
+            .collect();
+        let mut times = timing::PassTimes::default();
+        for (num, h) in handles {
+            if let Some(t) = join_worker(num, h, policy, &mut self.errors) {
+                add_worker_times(&mut times, num, &t, self.track_threads);
+            }
+        }
+        let serial_times = mem::take(&mut self.serial_times);
+        add_worker_times(&mut times, 0, &serial_times, self.track_threads);
+        // The workers stuck on a job can still send replies, so the forwarding thread keeps
+        // running until they are gone.
+        if stuck.is_empty() {
+            self.join_forwarder();
+        }
+        self.inbox().drop_expired();
+        if let Err(missing) = self.verify_complete() {
+            self.errors.push(format!("jobs submitted but never completed: {:?}", missing));
+        }
+        times
+    }
+
+    /// Join all the worker threads that exit within `timeout`, and transfer their pass timings
+    /// to the current thread.
+    ///
+    /// Unlike `join()`, this doesn't wait forever for a worker stuck in a test. If some worker
+    /// threads are still running at the deadline, the timings of the other threads are
+    /// transferred anyway, and the thread numbers of the threads still running are returned as
+    /// `JoinError::Running`. They can't be interrupted, but they are kept so a later call can try
+    /// joining them again. If all the threads are joined but some jobs never completed, their
+    /// jobids are returned as `JoinError::Incomplete`.
+    pub fn join_timeout(&mut self, timeout: Duration) -> Result<(), JoinError> {
+        assert!(self.requests.is_closed(), "must shutdown before join");
+        let policy = self.panic_policy;
+        let deadline = Instant::now() + timeout;
+        let mut times = timing::PassTimes::default();
+        loop {
+            let errors = &mut self.errors;
+            for (num, handle) in self.handles.iter_mut().enumerate() {
+                if handle.as_ref().is_some_and(thread::JoinHandle::is_finished) {
+                    let t = handle.take().and_then(|h| join_worker(num, h, policy, errors));
+                    if let Some(t) = t {
+                        add_worker_times(&mut times, num, &t, self.track_threads);
+                    }
+                }
+            }
+            let running: Vec<usize> = self.handles
+                .iter()
+                .enumerate()
+                .filter(|&(_, h)| h.is_some())
+                .map(|(num, _)| num)
+                .collect();
+            if running.is_empty() {
+                break;
+            }
+            if Instant::now() >= deadline {
+                timing::add_to_current(&times);
+                return Err(JoinError::Running(running));
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        self.handles.clear();
+        self.join_forwarder();
+        let serial_times = mem::take(&mut self.serial_times);
+        add_worker_times(&mut times, 0, &serial_times, self.track_threads);
+        timing::add_to_current(&times);
+        self.inbox().drop_expired();
+        self.verify_complete().map_err(JoinError::Incomplete)
+    }
+
+    /// Build a report of the results and pass timings of the jobs completed so far.
+    ///
+    /// The report has one entry per completed job, ordered by jobid. Call this after `join()` to
+    /// get a report covering all the submitted jobs.
+    pub fn build_report(&self) -> RunReport {
+        let mut entries: Vec<_> = self.completed.lock().unwrap().values().cloned().collect();
+        entries.sort_by_key(|e| e.jobid);
+        RunReport { entries }
+    }
+
+    /// Cancel job `jobid` if it hasn't completed yet.
+    ///
+    /// A queued job is removed from the queue. A job already picked up by a worker thread can't
+    /// be interrupted, but no `Reply::Done` is sent for it, and the worker moves on to the next
+    /// job when it finishes. Cancelled jobs are left out of the run report, and don't count as
+    /// incomplete when the runner is joined.
+    ///
+    /// Returns `false` if there is no such job, or if it has already completed or been cancelled.
+    pub fn cancel(&mut self, jobid: usize) -> bool {
+        if !self.jobs.contains_key(&jobid) || self.cancelled.contains(&jobid) {
+            return false;
+        }
+        if self.requests.remove(jobid) {
+            self.progress.queued.fetch_sub(1, Ordering::Relaxed);
+        } else {
+            let mut running = self.running.lock().unwrap();
+            if running.jobs.remove(&jobid).is_none() {
+                // Workers record the completion of a job while holding the `running` lock.
+                if self.completed.lock().unwrap().contains_key(&jobid) {
+                    return false;
+                }
+                // A worker has pulled the job from the queue, but hasn't started it yet.
+                running.cancelled.insert(jobid);
+            }
+        }
+        self.cancelled.insert(jobid);
+        self.inbox().forget(jobid);
+        true
+    }
+
+    /// Cancel all the jobs that haven't completed yet, as with `cancel()`.
+    ///
+    /// Returns the number of cancelled jobs.
+    pub fn cancel_all(&mut self) -> usize {
+        let mut count = 0;
+        for jobid in self.requests.clear() {
+            self.progress.queued.fetch_sub(1, Ordering::Relaxed);
+            self.cancelled.insert(jobid);
+            self.inbox().undelivered.remove(&jobid);
+            count += 1;
+        }
+        let mut jobids: Vec<usize> = self.jobs.keys().cloned().collect();
+        jobids.sort();
+        for jobid in jobids {
+            if self.cancel(jobid) {
+                count += 1;
+            }
+        }
+        self.inbox().release_ready();
+        count
+    }
+
+    /// Check that every job submitted so far has completed.
+    ///
+    /// A job is completed when a worker has sent its `Reply::Done`, whether or not that reply
+    /// has been received yet. After `join()`, all the submitted jobs have completed unless a
+    /// job was lost, so this checks the consistency of the runner itself. `join()` returns an
+    /// error listing the missing jobs if this fails.
+    ///
+    /// Returns the sorted jobids of the jobs that haven't completed.
+    pub fn verify_complete(&self) -> Result<(), Vec<usize>> {
+        // The forwarding thread locks `completed` while holding the inbox, so release the inbox
+        // before locking `completed`.
+        let not_run = self.inbox().not_run.clone();
+        let completed = self.completed.lock().unwrap();
+        let mut missing: Vec<usize> = self.jobs
+            .keys()
+            .filter(|jobid| {
+                !completed.contains_key(jobid) && !self.cancelled.contains(jobid) &&
+                    !not_run.contains(jobid) && !self.skipped.contains(jobid)
+            })
+            .cloned()
+            .collect();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            missing.sort();
+            Err(missing)
+        }
+    }
+
+    /// Join all the worker threads.
+    /// Transfer pass timings from the worker threads to the current thread.
+    ///
+    /// With `PanicPolicy::Catch`, a worker thread can only panic outside of a job, e.g. in the
+    /// runner itself. The timings of the worker threads that panicked are lost, but the timings
+    /// of the other threads are still transferred, and the panic messages are returned as an
+    /// error. This includes the worker threads that panicked when joined by an earlier call to
+    /// `join_timeout()` or `join_tagged()`. Jobs that were submitted but never completed, as
+    /// checked by `verify_complete()`, are reported in the error as well.
+    pub fn join(&mut self) -> Result<(), Vec<String>> {
+        timing::add_to_current(&self.join_workers());
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(mem::take(&mut self.errors))
+        }
+    }
+
+    /// Join all the worker threads and return their aggregated pass timings, tagged with the
+    /// label of this runner.
+    ///
+    /// Unlike `join()`, this doesn't add the timings to the current thread. The messages of the
+    /// worker threads that panicked and of the jobs that never completed are kept, and returned
+    /// by a later call to `join()`.
+    pub fn join_tagged(&mut self) -> timing::TaggedPassTimes {
+        timing::TaggedPassTimes {
+            label: self.label.clone(),
+            times: self.join_workers(),
+        }
+    }
+
+    /// Add a new job to the queues.
+    ///
+    /// The queue is bounded, so this blocks until a worker thread has room for the job. Use
+    /// `try_put()` to avoid blocking.
+    pub fn put(&mut self, jobid: usize, path: &Path) {
+        self.put_with_priority(jobid, path, 0);
+    }
+
+    /// Add a new job to the queues with the given priority.
+    ///
+    /// Worker threads pick the queued job with the highest priority first, and jobs of equal
+    /// priority in the order they were submitted. Jobs added with `put()` have priority 0. This
+    /// only reorders the queued jobs: Since the queue holds a few jobs per worker, submitting
+    /// jobs by decreasing priority still matters for large runs.
+    pub fn put_with_priority(&mut self, jobid: usize, path: &Path, priority: i32) {
+        if self.filtered_out(jobid, path) {
+            return;
+        }
+        if self.requests.past_deadline() {
+            return self.skip(jobid, path);
+        }
+        if self.serial {
+            return self.run_inline(jobid, path);
+        }
+        self.spawn_workers();
+        // Count the job before a worker can pick it up.
+        self.progress.queued.fetch_add(1, Ordering::Relaxed);
+        let request = Request(jobid, path.to_owned(), Instant::now());
+        if self.requests.push(request, priority).is_err() {
+            // The deadline passed while waiting for room in the queue.
+            self.progress.queued.fetch_sub(1, Ordering::Relaxed);
+            return self.skip(jobid, path);
+        }
+        self.submitted(jobid, path);
+    }
+
+    /// Add a new job to the queues if there is room for it.
+    ///
+    /// Returns the path of the job back if the queue is full.
+    pub fn try_put(&mut self, jobid: usize, path: &Path) -> Result<(), PathBuf> {
+        if self.filtered_out(jobid, path) {
+            return Ok(());
+        }
+        if self.requests.past_deadline() {
+            self.skip(jobid, path);
+            return Ok(());
+        }
+        if self.serial {
+            self.run_inline(jobid, path);
+            return Ok(());
+        }
+        self.spawn_workers();
+        let request = Request(jobid, path.to_owned(), Instant::now());
+        self.progress.queued.fetch_add(1, Ordering::Relaxed);
+        if let Err(Request(_, path, _)) = self.requests.try_push(request, 0) {
+            self.progress.queued.fetch_sub(1, Ordering::Relaxed);
+            return Err(path);
+        }
+        self.submitted(jobid, path);
+        Ok(())
+    }
+
+    /// If `path` is rejected by the filter, record job `jobid` as skipped and return `true`.
+    fn filtered_out(&mut self, jobid: usize, path: &Path) -> bool {
+        if self.filter.as_ref().is_none_or(|filter| filter(path)) {
+            return false;
+        }
+        self.submitted(jobid, path);
+        self.skipped.insert(jobid);
+        self.inbox().accept(Reply::Skipped { jobid });
+        true
+    }
+
+    /// Record job `jobid` as submitted but never run, because the deadline has passed.
+    fn skip(&mut self, jobid: usize, path: &Path) {
+        self.submitted(jobid, path);
+        let mut inbox = self.inbox();
+        inbox.not_run.insert(jobid);
+        inbox.forget(jobid);
+    }
+
+    /// Run job `jobid` on the calling thread, in serial mode.
+    fn run_inline(&mut self, jobid: usize, path: &Path) {
+        let worker = Worker {
+            thread_num: 0,
+            log: self.serial_log.clone(),
+            replies: self.reply_tx.clone().expect("cannot push after shutdown"),
+            completed: self.completed.clone(),
+            running: self.running.clone(),
+            progress: self.progress.clone(),
+            config: self.worker_config(),
+        };
+        self.progress.queued.fetch_add(1, Ordering::Relaxed);
+        self.submitted(jobid, path);
+        // Keep the pass timings of the caller separate from the timings of the job, without
+        // ending an accumulation cycle of the caller.
+        let caller_times = timing::swap_current(timing::PassTimes::default());
+        worker.run(Request(jobid, path.to_owned(), Instant::now()), &mut self.serial_times);
+        timing::add_to_current(&caller_times);
+    }
+
+    /// Submit all the files in `paths`, in order, and return the range of jobids allocated to
+    /// them.
+    ///
+    /// Jobids are allocated sequentially like in `submit_dir()`. The files are submitted with
+    /// `put()` as `paths` is iterated, so a large batch blocks on the bounded queue instead of
+    /// being buffered, and `paths` can be a lazy iterator.
+    pub fn put_all<I>(&mut self, paths: I) -> Range<usize>
+    where
+        I: IntoIterator<Item = PathBuf>,
+    {
+        let first = self.next_jobid;
+        for path in paths {
+            let jobid = self.next_jobid;
+            self.put(jobid, &path);
+        }
+        first..self.next_jobid
+    }
+
+    /// Record that job `jobid` has been sent to the worker threads.
+    fn submitted(&mut self, jobid: usize, path: &Path) {
+        self.jobs.insert(jobid, path.to_owned());
+        self.next_jobid = self.next_jobid.max(jobid + 1);
+        let mut inbox = self.inbox();
+        if inbox.ordered {
+            inbox.undelivered.insert(jobid);
+        }
+    }
+
+    /// Get the path of the file submitted as job `jobid`.
+    pub fn path(&self, jobid: usize) -> Option<&Path> {
+        self.jobs.get(&jobid).map(PathBuf::as_path)
+    }
+
+    /// Recursively scan the directory `root` and submit all the files accepted by `filter`.
+    ///
+    /// Files are submitted as soon as they are found, so the workers can start running tests
+    /// while the rest of the tree is being scanned. The entries of each directory are sorted,
+    /// and its files are submitted before the files of its subdirectories, so the jobids don't
+    /// depend on the order the file system lists them in. Jobids are allocated sequentially,
+    /// following the largest jobid submitted so far. Use `path()` to map them back to file
+    /// names.
+    ///
+    /// Returns the number of submitted files, or the first I/O error encountered. Files found
+    /// before the error remain submitted.
+    pub fn submit_dir<F>(&mut self, root: &Path, filter: F) -> io::Result<usize>
+    where
+        F: Fn(&Path) -> bool,
+    {
+        let mut count = 0;
+        let mut dir_stack = vec![root.to_owned()];
+        while let Some(dir) = dir_stack.pop() {
+            let mut entries = dir.read_dir()?.collect::<io::Result<Vec<_>>>()?;
+            entries.sort_by_key(|entry| entry.file_name());
+            let mut subdirs = Vec::new();
+            for entry in entries {
+                let path = entry.path();
+                if entry.file_type()?.is_dir() {
+                    subdirs.push(path);
+                } else if filter(&path) {
+                    let jobid = self.next_jobid;
+                    self.put(jobid, &path);
+                    count += 1;
+                }
+            }
+            // Scan the subdirectories in order.
+            dir_stack.extend(subdirs.into_iter().rev());
+        }
+        Ok(count)
+    }
+
+    /// Get a job reply without blocking.
+    pub fn try_get(&mut self) -> Option<Reply> {
+        if self.forwarded {
+            return None;
+        }
+        let mut inbox = self.inbox();
+        loop {
+            if let Some(reply) = inbox.next_ready() {
+                return Some(reply);
+            }
+            match self.reply_rx.try_recv() {
+                Ok(reply) => inbox.accept(reply),
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => return inbox.release_held(),
+            }
+        }
+    }
+
+    /// Get a job reply, blocking until one is available.
+    ///
+    /// A `Reply::Tick` is returned when the tick interval has elapsed, so this blocks for at
+    /// most one tick interval. Returns `None` once all the worker threads are gone and all
+    /// their replies have been delivered.
+    pub fn get(&mut self) -> Option<Reply> {
+        if self.forwarded {
+            return None;
+        }
+        let mut inbox = self.inbox();
+        loop {
+            if let Some(reply) = inbox.next_ready() {
+                return Some(reply);
+            }
+            match self.reply_rx.recv_timeout(inbox.ticker.remaining()) {
+                Ok(reply) => inbox.accept(reply),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return inbox.release_held(),
+            }
+        }
+    }
+
+    /// Lock the replies received but not yet delivered.
+    fn inbox(&self) -> MutexGuard<'_, Inbox> {
+        self.inbox.lock().unwrap()
+    }
+
+    /// Forward all job replies to `sender` from now on.
+    ///
+    /// This spawns a thread moving replies from the workers into `sender`, so a driver can
+    /// receive them in its own event loop along with other events. Any buffered replies are
+    /// forwarded first. Replies are delivered as with `get()`: The forwarding thread also sends
+    /// the `Reply::Tick` heartbeats, checks for job timeouts and the deadline, calls the hook
+    /// registered with `on_reply()`, and keeps the replies in order in ordered mode.
+    ///
+    /// Afterwards, `get()` and `try_get()` return `None`, and `wait_for()` can't be used.
+    /// Forwarding stops when the receiving end of `sender` is dropped, or when all the worker
+    /// threads are gone. The forwarding thread is joined with the worker threads.
+    ///
+    /// Panics if the replies are already forwarded.
+    pub fn forward_to(&mut self, sender: Sender<Reply>) {
+        assert!(!self.forwarded, "replies are already forwarded");
+        let (_, disconnected) = channel();
+        let replies = mem::replace(&mut self.reply_rx, disconnected);
+        let inbox = self.inbox.clone();
+        let handle = thread::Builder::new()
+            .name("forwarder".to_string())
+            .spawn(move || forward(&inbox, &replies, &sender))
+            .unwrap();
+        self.forwarded = true;
+        self.forwarder = Some(handle);
+    }
+
+    /// Join the forwarding thread, if any.
+    ///
+    /// It only exits once all the worker threads are gone, so this must not be called while
+    /// some are still running.
+    fn join_forwarder(&mut self) {
+        if let Some(handle) = self.forwarder.take() {
+            if let Err(e) = handle.join() {
+                self.errors.push(match panic_message(&*e) {
+                    Some(msg) => format!("forwarder panicked: {}", msg),
+                    None => "forwarder panicked".to_string(),
+                });
+            }
+        }
+    }
+
+    /// Block until job `jobid` is done and return its result.
+    ///
+    /// Other replies received in the meantime are buffered and delivered by subsequent calls to
+    /// `get()` and `try_get()`. No `Reply::Tick` heartbeats are delivered for the time spent
+    /// waiting, but jobs are still checked for timeouts and the deadline once per tick interval.
+    ///
+    /// Returns an error right away if the job was never submitted, or if it won't complete
+    /// because it was cancelled, skipped by the filter, or dropped at the deadline. The
+    /// completion of the job must not have been delivered by `get()` or `try_get()` already.
+    pub fn wait_for(&mut self, jobid: usize) -> TestResult {
+        if self.forwarded {
+            return Err(format!("the reply of job {} is forwarded", jobid));
+        }
+        if !self.jobs.contains_key(&jobid) {
+            return Err(format!("job {} was never submitted", jobid));
+        }
+        if self.cancelled.contains(&jobid) {
+            return Err(format!("job {} was cancelled", jobid));
+        }
+        let mut inbox = self.inbox();
+        loop {
+            // The job may already have completed while waiting for another one.
+            match inbox.take_completion(jobid) {
+                Some(Reply::Done { result, .. }) => return result,
+                Some(Reply::Skipped { .. }) => return Err(format!("job {} was skipped", jobid)),
+                Some(_) => return Err(format!("job {} timed out", jobid)),
+                None => {}
+            }
+            if self.skipped.contains(&jobid) {
+                return Err(format!("job {} was skipped", jobid));
+            }
+            if inbox.not_run.contains(&jobid) {
+                return Err(format!("job {} was never run", jobid));
+            }
+            if inbox.ticker.due() {
+                inbox.expire_jobs();
+                inbox.drop_expired();
+                continue;
+            }
+            match self.reply_rx.recv_timeout(inbox.ticker.remaining()) {
+                Ok(reply) => inbox.accept(reply),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(format!("job {} never completed", jobid))
+                }
+            }
+        }
+    }
+}
+
+/// Dropping a runner shuts it down without joining it: The worker threads exit once they have
+/// run the queued jobs, and the forwarding thread once the workers are gone.
+impl Drop for ConcurrentRunner {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Move the replies received on `replies` through `inbox` into `sender`, until the worker
+/// threads are gone or the receiving end of `sender` is dropped.
+fn forward(inbox: &Mutex<Inbox>, replies: &Receiver<Reply>, sender: &Sender<Reply>) {
+    loop {
+        // Don't hold the lock while waiting, so the runner can submit jobs in the meantime.
+        let (ready, timeout) = {
+            let mut inbox = inbox.lock().unwrap();
+            let ready: Vec<Reply> = iter::from_fn(|| inbox.next_ready()).collect();
+            (ready, inbox.ticker.remaining())
+        };
+        for reply in ready {
+            if sender.send(reply).is_err() {
+                return;
+            }
+        }
+        match replies.recv_timeout(timeout) {
+            Ok(reply) => inbox.lock().unwrap().accept(reply),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                let mut inbox = inbox.lock().unwrap();
+                while let Some(reply) = inbox.release_held() {
+                    if sender.send(reply).is_err() {
+                        return;
+                    }
+                }
+                return;
+            }
+        }
+    }
+}
+
+/// Join the worker thread number `thread_num` and return its pass timings, handling a panic
+/// according to `panic_policy`.
+///
+/// With `PanicPolicy::Catch`, the panic message is added to `panics`.
+fn join_worker(
+    thread_num: usize,
+    handle: thread::JoinHandle<timing::PassTimes>,
+    panic_policy: PanicPolicy,
+    panics: &mut Vec<String>,
+) -> Option<timing::PassTimes> {
+    match handle.join() {
+        Ok(t) => Some(t),
+        Err(e) if panic_policy == PanicPolicy::Abort => resume_unwind(e),
+        Err(e) => {
+            panics.push(match panic_message(&*e) {
+                Some(msg) => format!("worker #{} panicked: {}", thread_num, msg),
+                None => format!("worker #{} panicked", thread_num),
+            });
+            None
+        }
+    }
+}
+
+/// Add the pass timings of worker #`thread_num` to `times`, labeled with the worker if `track`
+/// is set.
+fn add_worker_times(
+    times: &mut timing::PassTimes,
+    thread_num: usize,
+    worker_times: &timing::PassTimes,
+    track: bool,
+) {
+    if track {
+        times.add_labeled(&format!("worker #{}", thread_num), worker_times);
+    } else {
+        times.add(worker_times);
+    }
+}
+
+/// Get the message of a panic from its payload.
+///
+/// Panics are usually strings, but the payload can be any type.
+fn panic_message(payload: &(dyn Any + Send)) -> Option<&str> {
+    if let Some(msg) = payload.downcast_ref::<String>() {
+        Some(msg)
+    } else {
+        payload.downcast_ref::<&'static str>().cloned()
+    }
+}
+
+/// Get the default number of worker threads: the value of the `CRETONNE_TEST_THREADS` environment
+/// variable if it is set to a positive number, or the number of CPUs otherwise.
+fn default_threads() -> usize {
+    match env::var("CRETONNE_TEST_THREADS").ok().and_then(|s| s.parse().ok()) {
+        Some(n) if n > 0 => n,
+        _ => num_cpus::get(),
+    }
+}
+
+/// Report the running jobs that have exceeded the job timeout as failed in `completed`, and
+/// return their `Reply::Timeout` replies.
+fn timeout_replies(
+    completed: &Mutex<HashMap<usize, ReportEntry>>,
+    running: &Mutex<Running>,
+) -> Vec<Reply> {
+    let mut replies = Vec::new();
+    for (jobid, path) in expire_jobs(running) {
+        completed.lock().unwrap().insert(
+            jobid,
+            ReportEntry {
+                jobid,
+                path,
+                result: Err("timed out".to_string()),
+                compile_time: Duration::default(),
+                dominant_pass: None,
+            },
+        );
+        replies.push(Reply::Timeout { jobid });
+    }
+    replies
+}
+
+/// Remove the jobs that have exceeded the job timeout from `running`, and return their jobids
+/// and paths.
+fn expire_jobs(running: &Mutex<Running>) -> Vec<(usize, PathBuf)> {
+    let mut running = running.lock().unwrap();
+    let timeout = match running.timeout {
+        Some(timeout) => timeout,
+        None => return Vec::new(),
+    };
+    let expired: Vec<usize> = running
+        .jobs
+        .iter()
+        .filter(|&(_, &(started, _, _))| started.elapsed() > timeout)
+        .map(|(&jobid, _)| jobid)
+        .collect();
+    expired
+        .into_iter()
+        .map(|jobid| {
+            let (_, thread_num, path) = running.jobs.remove(&jobid).unwrap();
+            running.stuck.insert(thread_num);
+            (jobid, path)
+        })
+        .collect()
+}
+
+/// Settings of a worker thread, fixed when it is spawned.
+struct WorkerConfig {
+    /// The job to run on each file.
+    job: JobFn,
+
+    /// What to do when a job panics.
+    panic_policy: PanicPolicy,
+
+    /// Number of times a failing job is run again before reporting the failure.
+    max_retries: u32,
+
+    /// Number of times each job is run.
+    repetitions: u32,
+
+    /// Pin the worker thread to the CPU matching its thread number?
+    affinity: bool,
+
+    /// Base seed of the workers, if any.
+    seed: Option<u64>,
+}
+
+/// The log file of a worker, see `ConcurrentRunner::set_log_dir()`.
+struct WorkerLog {
+    file: fs::File,
+    opened: Instant,
+}
+
+impl WorkerLog {
+    /// Create the log file of worker `thread_num` in `dir`.
+    fn create(dir: &Path, thread_num: usize) -> io::Result<Self> {
+        Ok(Self {
+            file: fs::File::create(dir.join(format!("worker-{}.log", thread_num)))?,
+            opened: Instant::now(),
+        })
+    }
+
+    /// Write a line prefixed with the time since the log was opened. Errors are ignored, since a
+    /// broken log shouldn't fail the tests.
+    fn line(&self, args: fmt::Arguments) {
+        let t = self.opened.elapsed();
+        let line = format!("[{:4}.{:03}] {}\n", t.as_secs(), t.subsec_millis(), args);
+        (&self.file).write_all(line.as_bytes()).ok();
+    }
+
+    /// Log the outcome of the job on `path`, followed by its captured output.
+    fn finished(&self, path: &Path, result: &TestResult, duration: Duration, output: &str) {
+        let ms = millis(duration);
+        match *result {
+            Ok(_) => self.line(format_args!("pass {} in {:.3} ms", path.display(), ms)),
+            Err(ref msg) => {
+                self.line(format_args!("FAIL {} in {:.3} ms: {}", path.display(), ms, msg))
+            }
+        }
+        for l in output.lines() {
+            (&self.file).write_all(format!("    {}\n", l).as_bytes()).ok();
+        }
+    }
+}
+
+/// A worker running jobs, and its handles on the state shared with the runner.
+struct Worker {
+    thread_num: usize,
+    log: Option<Arc<WorkerLog>>,
+    replies: ReplySender,
+    completed: Arc<Mutex<HashMap<usize, ReportEntry>>>,
+    running: Arc<Mutex<Running>>,
+    progress: Arc<Progress>,
+    config: WorkerConfig,
+}
+
+impl Worker {
+    /// Run the job of `request` and report it, adding its pass timings to `thread_times`.
+    fn run(&self, request: Request, thread_times: &mut timing::PassTimes) {
+        let Request(jobid, path, submitted) = request;
+        let thread_num = self.thread_num;
+        let pulled = Instant::now();
+        self.progress.queued.fetch_sub(1, Ordering::Relaxed);
+        if let Some(seed) = self.config.seed {
+            SEED.with(|s| s.set(Some(seed ^ thread_num as u64)));
+        }
+        {
+            let mut running = self.running.lock().unwrap();
+            if running.cancelled.remove(&jobid) {
+                return;
+            }
+            running.jobs.insert(jobid, (Instant::now(), thread_num, path.clone()));
+        }
+        self.progress.running.fetch_add(1, Ordering::Relaxed);
+        if let Some(ref log) = self.log {
+            log.line(format_args!("start {}", path.display()));
+        }
+
+        // Tell them we're starting this job.
+        // The receiver should always be present for this as long as we have jobs.
+        self.replies
+            .send(Reply::Starting {
+                jobid,
+                thread_num,
+                queue_latency: submitted.elapsed(),
+            })
+            .unwrap();
+
+        // The timings of this job, reported separately from the running total for the thread.
+        let mut times = timing::PassTimes::default();
+        let mut attempts = 0;
+        let (result, output, stats) = loop {
+            attempts += 1;
+            let (result, output, stats) = self.run_repeated(&path, &mut times);
+            match result {
+                Err(ref msg) if attempts <= self.config.max_retries => {
+                    dbg!("RETRY {}/{}: {}", attempts, self.config.max_retries, msg);
+                }
+                _ => break (result, output, stats),
+            }
+        };
+
+        self.progress.running.fetch_sub(1, Ordering::Relaxed);
+        if let Err(ref msg) = result {
+            dbg!("FAIL: {}", msg);
+        }
+        let duration = pulled.elapsed();
+        if let Some(ref log) = self.log {
+            log.finished(&path, &result, duration, &output);
+        }
+
+        thread_times.add(&times);
+        let times = Box::new(times);
+
+        // If the job timed out, it has already been reported as failed. If it was cancelled, it
+        // isn't reported at all.
+        {
+            let mut running = self.running.lock().unwrap();
+            if running.jobs.remove(&jobid).is_none() {
+                running.stuck.remove(&thread_num);
+                return;
+            }
+            // Record the completion before releasing the lock, so `cancel()` sees the job
+            // either running or completed.
+            self.completed.lock().unwrap().insert(
+                jobid,
+                ReportEntry {
+                    jobid,
+                    path,
+                    result: result.clone(),
+                    compile_time: times.rows().map(|row| row.self_time).sum(),
+                    dominant_pass: times.fractions().first().map(|&(pass, _)| pass),
+                },
+            );
+        }
+        self.replies
+            .send(Reply::Done {
+                jobid,
+                result,
+                times,
+                duration,
+                attempts,
+                output,
+                stats: Box::new(stats),
+            })
+            .unwrap();
+    }
+
+    /// Run the job on `path` as many times as configured, stopping at the first failing run.
+    ///
+    /// The pass timings of the runs are added to `times`. Returns the result and output of the
+    /// last run, and the statistics of the pass timings over the runs.
+    fn run_repeated(
+        &self,
+        path: &Path,
+        times: &mut timing::PassTimes,
+    ) -> (TestResult, String, timing::PassTimesStats) {
+        let mut stats = timing::PassTimesStats::default();
+        let mut runs = 0;
+        loop {
+            runs += 1;
+            output::start_capture();
+            let result = run_job(&self.config, path, self.thread_num);
+            let output = output::end_capture();
+            let sample = timing::take_current();
+            stats.add_sample(&sample);
+            times.add(&sample);
+            if result.is_err() || runs >= self.config.repetitions {
+                return (result, output, stats);
+            }
+        }
+    }
+}
+
+/// Spawn a worker thread running jobs.
+fn worker_thread(
+    worker: Worker,
+    requests: Arc<JobQueue>,
+) -> thread::JoinHandle<timing::PassTimes> {
+    thread::Builder::new()
+        .name(format!("worker #{}", worker.thread_num))
+        .spawn(move || {
+            if worker.config.affinity {
+                let cpu = worker.thread_num % num_cpus::get();
+                if let Err(e) = pin_to_cpu(cpu) {
+                    eprintln!(
+                        "warning: cannot pin worker #{} to CPU {}: {}",
+                        worker.thread_num,
+                        cpu,
+                        e
+                    );
+                }
+            }
+            if worker.config.panic_policy == PanicPolicy::Catch {
+                install_panic_hook();
+                CAPTURE_BACKTRACE.with(|c| c.set(true));
+            }
+
+            // Timings of the jobs run by this thread so far.
+            let mut thread_times = timing::PassTimes::default();
+            // The queue is closed and empty when shutting down. Exit the thread.
+            while let Some(request) = requests.pop() {
+                worker.run(request, &mut thread_times);
+            }
+
+            // Timing is accumulated independently per thread.
+            // Timings from this worker thread will be aggregated by `ConcurrentRunner::join()`.
+            thread_times.add(&timing::take_current());
+            thread_times
+        })
+        .unwrap()
+}
+
+/// Expire the deadline of `queue` when the process is interrupted.
+///
+/// Signal handlers can't take locks, so this polls for the interrupt. It returns when the runner
+/// owning the queue is dropped.
+fn watch_interrupt(queue: &Weak<JobQueue>) {
+    while let Some(queue) = queue.upgrade() {
+        if interrupt::interrupted() {
+            queue.set_deadline(Some(Instant::now()));
+            return;
+        }
+        drop(queue);
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Restrict the current thread to run on `cpu` only.
+#[cfg(all(feature = "affinity", target_os = "linux"))]
+fn pin_to_cpu(cpu: usize) -> Result<(), String> {
+    // A zeroed `cpu_set_t` is the empty set.
+    let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+    unsafe { libc::CPU_SET(cpu, &mut set) };
+    let size = mem::size_of::<libc::cpu_set_t>();
+    // A pid of 0 means the calling thread.
+    if unsafe { libc::sched_setaffinity(0, size, &set) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error().to_string())
+    }
+}
+
+/// Restrict the current thread to run on `cpu` only.
+#[cfg(not(all(feature = "affinity", target_os = "linux")))]
+fn pin_to_cpu(_cpu: usize) -> Result<(), String> {
+    Err("CPU affinity requires the affinity feature on Linux".to_string())
+}
+
+/// Get the seed that randomized tests should use, if one was set with
+/// `ConcurrentRunner::set_seed()`.
+///
+/// This is the seed of the worker thread running the current test.
+pub fn worker_seed() -> Option<u64> {
+    SEED.with(Cell::get)
+}
+
+thread_local! {
+    /// Seed of the worker running on this thread.
+    static SEED: Cell<Option<u64>> = const { Cell::new(None) };
+
+    /// Should panics on this thread have their backtrace captured?
+    static CAPTURE_BACKTRACE: Cell<bool> = const { Cell::new(false) };
+
+    /// Backtrace of the last panic on this thread, if captured.
+    static BACKTRACE: RefCell<Option<Backtrace>> = const { RefCell::new(None) };
+}
+
+/// Install a panic hook capturing the backtraces of panics on threads that have
+/// `CAPTURE_BACKTRACE` set, before chaining to the previous hook.
+///
+/// Panic hooks are global, so the hook is only installed once.
+fn install_panic_hook() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let prev = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if CAPTURE_BACKTRACE.with(Cell::get) {
+                let backtrace = Backtrace::force_capture();
+                BACKTRACE.with(|bt| *bt.borrow_mut() = Some(backtrace));
+            }
+            prev(info);
+        }));
+    });
+}
+
+/// Run the job of `config` on `path` once, handling panics according to its panic policy.
+///
+/// With `PanicPolicy::Catch`, the error message of a panic includes its backtrace if it was
+/// captured by the hook installed by `install_panic_hook()`.
+fn run_job(config: &WorkerConfig, path: &Path, thread_num: usize) -> TestResult {
+    // A job that panics is reported as failed and never looked at again, so a broken invariant
+    // in its state can't be observed.
+    let run = AssertUnwindSafe(|| (config.job)(path));
+    match config.panic_policy {
+        PanicPolicy::Abort => run(),
+        PanicPolicy::Catch => {
+            catch_unwind(run).map_err(|e| {
+                // The test panicked, leaving us a `Box<Any>`.
+                let mut msg = match panic_message(&*e) {
+                    Some(msg) => format!("panicked in worker #{}: {}", thread_num, msg),
+                    None => format!("panicked in worker #{}", thread_num),
+                };
+                if let Some(backtrace) = BACKTRACE.with(|bt| bt.borrow_mut().take()) {
+                    msg += &format!("\nbacktrace:\n{}", backtrace);
+                }
+                msg
+            }).and_then(|result| result)
+        }
+    }
+}
+
+/// Async interface to the runner.
+///
+/// The worker threads are unchanged, but the replies can be awaited from an async task without
+/// blocking its executor thread.
+#[cfg(feature = "async")]
+mod async_io {
+    use super::{ConcurrentRunner, Reply};
+    use futures_core::Stream;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::Mutex;
+    use std::sync::mpsc::TryRecvError;
+    use std::task::{Context, Poll, Waker};
+
+    /// The waker of the task awaiting the next reply, if any.
+    #[derive(Default)]
+    pub struct WakerSlot(Mutex<Option<Waker>>);
+
+    impl WakerSlot {
+        fn register(&self, waker: &Waker) {
+            *self.0.lock().unwrap() = Some(waker.clone());
+        }
+
+        pub fn wake(&self) {
+            // Don't hold the lock while waking the task.
+            let waker = self.0.lock().unwrap().take();
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+
+    /// Future returned by `ConcurrentRunner::next_result_async()`.
+    pub struct NextReply<'a>(&'a mut ConcurrentRunner);
+
+    impl<'a> Future for NextReply<'a> {
+        type Output = Option<Reply>;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Reply>> {
+            self.get_mut().0.poll_reply(cx)
+        }
+    }
+
+    /// Stream of replies returned by `ConcurrentRunner::replies()`.
+    pub struct Replies<'a>(&'a mut ConcurrentRunner);
+
+    impl<'a> Stream for Replies<'a> {
+        type Item = Reply;
+
+        fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Reply>> {
+            self.get_mut().0.poll_reply(cx)
+        }
+    }
+
+    impl ConcurrentRunner {
+        /// Get a job reply asynchronously.
+        ///
+        /// This is the async equivalent of `get()`: The returned future resolves to the next
+        /// reply without blocking the executor thread while waiting.
+        pub fn next_result_async(&mut self) -> NextReply<'_> {
+            NextReply(self)
+        }
+
+        /// Get a stream of job replies, as returned by successive calls to `get()`.
+        pub fn replies(&mut self) -> Replies<'_> {
+            Replies(self)
+        }
+
+        fn poll_reply(&mut self, cx: &mut Context) -> Poll<Option<Reply>> {
+            if self.forwarded {
+                return Poll::Ready(None);
+            }
+            let mut inbox = self.inbox();
+            let mut registered = false;
+            loop {
+                if let Some(reply) = inbox.next_ready() {
+                    return Poll::Ready(Some(reply));
+                }
+                match self.reply_rx.try_recv() {
+                    Ok(reply) => inbox.accept(reply),
+                    Err(TryRecvError::Disconnected) => return Poll::Ready(inbox.release_held()),
+                    // Register before checking the channel again, so a reply sent in between
+                    // isn't missed.
+                    Err(TryRecvError::Empty) if !registered => {
+                        self.waker.register(cx.waker());
+                        registered = true;
+                    }
+                    Err(TryRecvError::Empty) => return Poll::Pending,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+pub use self::async_io::{NextReply, Replies};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Create a runner with `threads` worker threads running `job`.
+    fn runner<F>(threads: usize, job: F) -> ConcurrentRunner
+    where
+        F: Fn(&Path) -> TestResult + Send + Sync + 'static,
+    {
+        ConcurrentRunner::build("", threads, Arc::new(job), false)
+    }
+
+    /// A job that passes immediately.
+    fn pass(_: &Path) -> TestResult {
+        Ok(Duration::default())
+    }
+
+    /// A job that blocks while `gate` is locked, then records the path it ran on in `order`.
+    fn gated(
+        gate: &Arc<Mutex<()>>,
+        order: &Arc<Mutex<Vec<PathBuf>>>,
+    ) -> impl Fn(&Path) -> TestResult + Send + Sync + 'static {
+        let gate = gate.clone();
+        let order = order.clone();
+        move |path: &Path| {
+            drop(gate.lock().unwrap());
+            order.lock().unwrap().push(path.to_owned());
+            Ok(Duration::default())
+        }
+    }
+
+    /// Wait until `cond` holds.
+    fn wait_until<F: Fn() -> bool>(cond: F) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !cond() {
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Shut down and join `runner`, and get the jobids of its `Reply::Done` replies in the
+    /// order they are delivered.
+    fn finish(runner: &mut ConcurrentRunner) -> Vec<usize> {
+        runner.shutdown();
+        runner.join().unwrap();
+        let mut done = Vec::new();
+        while let Some(reply) = runner.get() {
+            if let Reply::Done { jobid, .. } = reply {
+                done.push(jobid);
+            }
+        }
+        done
+    }
+
+    #[test]
+    fn wait_for_unsubmitted() {
+        let mut runner = runner(1, pass);
+        runner.put(0, Path::new("0"));
+        assert_eq!(runner.wait_for(1), Err("job 1 was never submitted".to_string()));
+        assert!(runner.wait_for(0).is_ok());
+        finish(&mut runner);
+    }
+
+    #[test]
+    fn wait_for_cancelled() {
+        let gate = Arc::new(Mutex::new(()));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut runner = runner(1, gated(&gate, &order));
+        let closed = gate.lock().unwrap();
+        runner.put(0, Path::new("0"));
+        wait_until(|| runner.in_flight() == 1);
+        runner.put(1, Path::new("1"));
+        assert!(runner.cancel(1));
+        assert_eq!(runner.wait_for(1), Err("job 1 was cancelled".to_string()));
+        drop(closed);
+        assert_eq!(finish(&mut runner), [0]);
+    }
+
+    #[test]
+    fn wait_for_skipped() {
+        let mut runner = runner(1, pass);
+        runner.set_filter(|path| path != Path::new("skip"));
+        runner.put(0, Path::new("skip"));
+        runner.put(1, Path::new("run"));
+        // The `Reply::Skipped` has already been delivered.
+        assert!(matches!(runner.try_get(), Some(Reply::Skipped { jobid: 0 })));
+        assert_eq!(runner.wait_for(0), Err("job 0 was skipped".to_string()));
+        assert!(runner.wait_for(1).is_ok());
+        finish(&mut runner);
+    }
+
+    #[test]
+    fn wait_for_not_run() {
+        let gate = Arc::new(Mutex::new(()));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut runner = runner(1, gated(&gate, &order));
+        runner.set_tick_interval(Duration::from_millis(10));
+        let closed = gate.lock().unwrap();
+        runner.put(0, Path::new("0"));
+        wait_until(|| runner.in_flight() == 1);
+        runner.put(1, Path::new("1"));
+        runner.set_deadline(Some(Instant::now()));
+
+        // Job 1 is dropped from the queue on the next tick while waiting for it.
+        assert_eq!(runner.wait_for(1), Err("job 1 was never run".to_string()));
+        drop(closed);
+        assert_eq!(finish(&mut runner), [0]);
+        assert_eq!(runner.not_run(), [1]);
+    }
+
+    #[test]
+    fn job_timeout() {
+        let gate = Arc::new(Mutex::new(()));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut runner = runner(1, gated(&gate, &order));
+        runner.set_tick_interval(Duration::from_millis(10));
+        runner.set_job_timeout(Some(Duration::from_millis(50)));
+        let closed = gate.lock().unwrap();
+        runner.put(0, Path::new("stuck"));
+        loop {
+            match runner.get() {
+                Some(Reply::Timeout { jobid }) => {
+                    assert_eq!(jobid, 0);
+                    break;
+                }
+                Some(Reply::Done { .. }) => panic!("the stuck job completed"),
+                _ => {}
+            }
+        }
+
+        // The stuck worker isn't waited for.
+        runner.shutdown();
+        runner.join().unwrap();
+        drop(closed);
+
+        // The job finishes once unblocked, but it was already reported as failed.
+        wait_until(|| order.lock().unwrap().len() == 1);
+        while let Some(reply) = runner.get() {
+            assert!(!matches!(reply, Reply::Done { .. }), "Done after Timeout");
+        }
+        assert!(runner.build_report().entries[0].result.is_err());
+    }
+
+    #[test]
+    fn lazy_spawn() {
+        let mut runner = runner(8, pass);
+        runner.set_lazy(true);
+        for jobid in 0..3 {
+            runner.put(jobid, Path::new("job"));
+        }
+        assert_eq!(runner.num_threads(), 3);
+        let mut done = finish(&mut runner);
+        done.sort();
+        assert_eq!(done, [0, 1, 2]);
+    }
+
+    #[test]
+    fn job_boundaries() {
+        // Both jobs run on the same worker, so the timings of the first one would leak into the
+        // second one if they weren't taken at the job boundary.
+        let mut runner = runner(1, |path: &Path| {
+            for _ in 0..path.to_str().unwrap().parse().unwrap() {
+                let _gvn = timing::gvn();
+            }
+            Ok(Duration::default())
+        });
+        runner.put(0, Path::new("1"));
+        runner.put(1, Path::new("2"));
+        runner.shutdown();
+        timing::take_current();
+        runner.join().unwrap();
+
+        // The worker still accumulates the timings of all its jobs.
+        assert_eq!(runs(&timing::take_current(), timing::Pass::gvn), recorded(3));
+        let mut runs_by_job = Vec::new();
+        while let Some(reply) = runner.get() {
+            if let Reply::Done { jobid, times, .. } = reply {
+                runs_by_job.push((jobid, runs(&times, timing::Pass::gvn)));
+            }
+        }
+        runs_by_job.sort();
+        assert_eq!(runs_by_job, [(0, recorded(1)), (1, recorded(2))]);
+    }
+
+    #[test]
+    fn ordered() {
+        let gate = Arc::new(Mutex::new(()));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let slow = gated(&gate, &order);
+        let mut runner = runner(2, move |path: &Path| {
+            if path == Path::new("slow") {
+                slow(path)
+            } else {
+                pass(path)
+            }
+        });
+        runner.set_ordered(true);
+        let closed = gate.lock().unwrap();
+        runner.put(0, Path::new("slow"));
+        runner.put(1, Path::new("fast"));
+        wait_until(|| runner.completed.lock().unwrap().contains_key(&1));
+
+        // Job 1 is held back until job 0 completes.
+        while let Some(reply) = runner.try_get() {
+            assert!(reply.completed_jobid().is_none());
+        }
+        drop(closed);
+        assert_eq!(finish(&mut runner), [0, 1]);
+    }
+
+    #[test]
+    fn retry() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut runner = {
+            let calls = calls.clone();
+            runner(1, move |_| match calls.fetch_add(1, Ordering::Relaxed) {
+                0 | 1 => Err("flaky".to_string()),
+                _ => Ok(Duration::default()),
+            })
+        };
+        runner.set_max_retries(2);
+        runner.put(0, Path::new("job"));
+        runner.shutdown();
+        runner.join().unwrap();
+        loop {
+            match runner.get() {
+                Some(Reply::Done { result, attempts, .. }) => {
+                    assert!(result.is_ok());
+                    assert_eq!(attempts, 3);
+                    break;
+                }
+                Some(_) => {}
+                None => panic!("no reply for the job"),
+            }
+        }
+        assert_eq!(calls.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn priority() {
+        let gate = Arc::new(Mutex::new(()));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut runner = runner(1, gated(&gate, &order));
+        let closed = gate.lock().unwrap();
+        runner.put(0, Path::new("0"));
+        wait_until(|| runner.in_flight() == 1);
+        runner.put_with_priority(1, Path::new("1"), 0);
+        runner.put_with_priority(2, Path::new("2"), 5);
+        runner.put_with_priority(3, Path::new("3"), 1);
+        runner.put_with_priority(4, Path::new("4"), 0);
+        drop(closed);
+        finish(&mut runner);
+        let order = order.lock().unwrap();
+        let order: Vec<_> = order.iter().map(|path| path.to_str().unwrap()).collect();
+        assert_eq!(order, ["0", "2", "3", "1", "4"]);
+    }
+
+    #[test]
+    fn cancel_queued() {
+        let gate = Arc::new(Mutex::new(()));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut runner = runner(1, gated(&gate, &order));
+        let closed = gate.lock().unwrap();
+        runner.put(0, Path::new("0"));
+        wait_until(|| runner.in_flight() == 1);
+        runner.put(1, Path::new("1"));
+        runner.put(2, Path::new("2"));
+        assert!(runner.cancel(1));
+        assert!(!runner.cancel(1));
+        assert!(!runner.cancel(5));
+        assert_eq!(runner.pending(), 1);
+        drop(closed);
+        assert_eq!(finish(&mut runner), [0, 2]);
+        assert_eq!(order.lock().unwrap().len(), 2);
+        assert_eq!(runner.build_report().entries.len(), 2);
+    }
+
+    #[test]
+    fn on_reply() {
+        let gate = Arc::new(Mutex::new(()));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let slow = gated(&gate, &order);
+        let mut runner = runner(2, move |path: &Path| {
+            if path == Path::new("slow") {
+                slow(path)
+            } else {
+                pass(path)
+            }
+        });
+        runner.set_ordered(true);
+        let observed = Arc::new(Mutex::new(Vec::new()));
+        {
+            let observed = observed.clone();
+            runner.on_reply(move |reply| if let Some(jobid) = reply.completed_jobid() {
+                observed.lock().unwrap().push(jobid);
+            });
+        }
+        let (tx, rx) = channel();
+        runner.forward_to(tx);
+        let closed = gate.lock().unwrap();
+        runner.put(0, Path::new("slow"));
+        runner.put(1, Path::new("fast"));
+        wait_until(|| *observed.lock().unwrap() == [1]);
+        assert!(rx.try_iter().all(|reply| reply.completed_jobid().is_none()));
+        drop(closed);
+        runner.shutdown();
+        runner.join().unwrap();
+
+        // The observer sees the replies as they are received, and the forwarded replies are in
+        // jobid order.
+        let forwarded: Vec<_> = rx.iter().filter_map(|reply| reply.completed_jobid()).collect();
+        assert_eq!(forwarded, [0, 1]);
+        assert_eq!(*observed.lock().unwrap(), [1, 0]);
+        assert!(runner.get().is_none());
+    }
+
+    #[test]
+    fn deadline() {
+        let gate = Arc::new(Mutex::new(()));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut runner = runner(1, gated(&gate, &order));
+        let closed = gate.lock().unwrap();
+        runner.put(0, Path::new("0"));
+        wait_until(|| runner.in_flight() == 1);
+        runner.put(1, Path::new("1"));
+        runner.put(2, Path::new("2"));
+        runner.set_deadline(Some(Instant::now()));
+        runner.put(3, Path::new("3"));
+        drop(closed);
+
+        // The job in flight finishes, but the queued jobs and the jobs submitted after the
+        // deadline never run.
+        assert_eq!(finish(&mut runner), [0]);
+        assert_eq!(order.lock().unwrap().len(), 1);
+        assert_eq!(runner.not_run(), [1, 2, 3]);
+        assert_eq!(runner.verify_complete(), Ok(()));
+    }
+
+    #[test]
+    fn join_timeout() {
+        let gate = Arc::new(Mutex::new(()));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut runner = runner(2, gated(&gate, &order));
+        let closed = gate.lock().unwrap();
+        runner.put(0, Path::new("0"));
+        wait_until(|| runner.running.lock().unwrap().jobs.contains_key(&0));
+        runner.shutdown();
+
+        // The idle worker exits, but the one running the blocked job is still running.
+        let num = runner.running.lock().unwrap().jobs[&0].1;
+        assert_eq!(
+            runner.join_timeout(Duration::from_millis(50)),
+            Err(JoinError::Running(vec![num]))
+        );
+        drop(closed);
+        assert_eq!(runner.join_timeout(Duration::from_secs(10)), Ok(()));
+        assert_eq!(runner.build_report().entries.len(), 1);
+    }
+
+    #[test]
+    fn serial() {
+        let caller = thread::current().id();
+        let mut runner = ConcurrentRunner::build(
+            "",
+            1,
+            Arc::new(move |_: &Path| {
+                assert_eq!(thread::current().id(), caller);
+                let _gvn = timing::gvn();
+                Ok(Duration::default())
+            }),
+            true,
+        );
+        timing::take_current();
+        let dce = timing::dce();
+        for jobid in 0..3 {
+            runner.put(jobid, Path::new("job"));
+            // The job has already run when `put()` returns.
+            let done: Vec<_> = iter::from_fn(|| runner.try_get())
+                .filter_map(|reply| reply.completed_jobid())
+                .collect();
+            assert_eq!(done, [jobid]);
+        }
+        assert_eq!(runner.num_threads(), 0);
+        drop(dce);
+
+        // The timings of the caller are kept apart from the timings of the jobs.
+        let times = timing::take_current();
+        assert_eq!(runs(&times, timing::Pass::dce), recorded(1));
+        assert_eq!(runs(&times, timing::Pass::gvn), 0);
+        assert_eq!(finish(&mut runner), []);
+        assert_eq!(runs(&timing::take_current(), timing::Pass::gvn), recorded(3));
+    }
+
+    #[test]
+    fn bounded_queue() {
+        let gate = Arc::new(Mutex::new(()));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut runner = runner(1, gated(&gate, &order));
+        let closed = gate.lock().unwrap();
+        runner.put(0, Path::new("0"));
+        wait_until(|| runner.in_flight() == 1);
+
+        // The queue holds 4 jobs per worker thread.
+        for jobid in 1..5 {
+            assert_eq!(runner.try_put(jobid, Path::new("queued")), Ok(()));
+        }
+        assert_eq!(runner.try_put(5, Path::new("full")), Err(PathBuf::from("full")));
+        assert_eq!(runner.pending(), 4);
+
+        // `put()` waits for a worker to pull the next job.
+        thread::scope(|s| {
+            let put = s.spawn(|| runner.put(5, Path::new("5")));
+            thread::sleep(Duration::from_millis(50));
+            assert!(!put.is_finished());
+            drop(closed);
+            put.join().unwrap();
+        });
+        assert_eq!(finish(&mut runner), [0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn submit_dir() {
+        let root = env::temp_dir().join(format!("cretonne-submit-dir-{}", ::std::process::id()));
+        fs::remove_dir_all(&root).ok();
+        for file in &["b.cton", "a.cton", "sub2/c.cton", "sub1/e.txt", "sub1/d.cton"] {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+
+        let mut runner = runner(1, pass);
+        runner.put(2, Path::new("first"));
+        let count = runner
+            .submit_dir(&root, |path| path.extension() == Some("cton".as_ref()))
+            .unwrap();
+        assert_eq!(count, 4);
+        let paths: Vec<_> = (3..7)
+            .map(|jobid| runner.path(jobid).unwrap().strip_prefix(&root).unwrap())
+            .collect();
+        assert_eq!(paths, ["a.cton", "b.cton", "sub1/d.cton", "sub2/c.cton"].map(Path::new));
+        finish(&mut runner);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    /// Drive `future` to completion on the current thread, parking it until it is woken.
+    #[cfg(feature = "async")]
+    fn block_on<F: ::std::future::Future + Unpin>(mut future: F) -> F::Output {
+        use std::pin::Pin;
+        use std::task::{Context, Poll, Wake, Waker};
+
+        struct Unpark(thread::Thread);
+
+        impl Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = Pin::new(&mut future).poll(&mut cx) {
+                return output;
+            }
+            let parked = Instant::now();
+            thread::park_timeout(Duration::from_secs(10));
+            assert!(parked.elapsed() < Duration::from_secs(10), "never woken");
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn poll_reply() {
+        use futures_core::Stream;
+        use std::future::poll_fn;
+        use std::pin::Pin;
+
+        // The job outlasts the first poll, so the awaiting task has to be woken.
+        let mut runner = runner(1, |_: &Path| {
+            thread::sleep(Duration::from_millis(50));
+            Ok(Duration::default())
+        });
+        runner.put(0, Path::new("0"));
+        loop {
+            match block_on(runner.next_result_async()) {
+                Some(Reply::Done { jobid, .. }) => {
+                    assert_eq!(jobid, 0);
+                    break;
+                }
+                Some(_) => {}
+                None => panic!("no reply for job 0"),
+            }
+        }
+
+        // The stream ends once the runner is shut down and all replies are delivered.
+        runner.put(1, Path::new("1"));
+        runner.shutdown();
+        let mut replies = runner.replies();
+        let mut done = Vec::new();
+        while let Some(reply) = block_on(poll_fn(|cx| Pin::new(&mut replies).poll_next(cx))) {
+            if let Reply::Done { jobid, .. } = reply {
+                done.push(jobid);
+            }
+        }
+        assert_eq!(done, [1]);
+        runner.join().unwrap();
+    }
+
+    /// A job that panics on the path "panic", and passes otherwise.
+    fn panicky(path: &Path) -> TestResult {
+        assert!(path != Path::new("panic"), "boom");
+        Ok(Duration::default())
+    }
+
+    #[test]
+    fn panic_abort() {
+        let mut runner = runner(1, panicky);
+        runner.set_panic_policy(PanicPolicy::Abort);
+        runner.put(0, Path::new("panic"));
+        runner.shutdown();
+
+        // The panic unwinds the worker, and is propagated by `join()`.
+        let e = catch_unwind(AssertUnwindSafe(|| runner.join())).unwrap_err();
+        assert_eq!(panic_message(&*e), Some("boom"));
+        assert_eq!(runner.verify_complete(), Err(vec![0]));
+    }
+
+    #[test]
+    fn panic_caught() {
+        let mut runner = runner(1, panicky);
+        runner.put(0, Path::new("panic"));
+        runner.put(1, Path::new("pass"));
+        runner.shutdown();
+        let mut results = HashMap::new();
+        while let Some(reply) = runner.get() {
+            if let Reply::Done { jobid, result, .. } = reply {
+                results.insert(jobid, result);
+            }
+        }
+
+        // The panic is reported with its backtrace, and the worker goes on with the next job.
+        let msg = results[&0].clone().unwrap_err();
+        assert!(msg.starts_with("panicked in worker #0: boom\nbacktrace:\n"), "{}", msg);
+        assert!(results[&1].is_ok());
+        runner.join().unwrap();
+    }
+
+    /// A job that writes a line to its output, and fails on the path "fail".
+    fn chatty(path: &Path) -> TestResult {
+        writeln!(output::TestOutput, "ran {}", path.display()).unwrap();
+        if path == Path::new("fail") {
+            return Err("bad".to_string());
+        }
+        Ok(Duration::default())
+    }
+
+    #[test]
+    fn captured_output() {
+        let mut runner = runner(1, chatty);
+        runner.put(0, Path::new("a"));
+        runner.put(1, Path::new("b"));
+        runner.shutdown();
+        let mut outputs = Vec::new();
+        while let Some(reply) = runner.get() {
+            if let Reply::Done { jobid, output, .. } = reply {
+                outputs.push((jobid, output));
+            }
+        }
+
+        // Both jobs run on the same worker, but each gets only its own output.
+        outputs.sort();
+        assert_eq!(outputs, [(0, "ran a\n".to_string()), (1, "ran b\n".to_string())]);
+        runner.join().unwrap();
+    }
+
+    #[test]
+    fn log_dir() {
+        let dir = env::temp_dir().join(format!("cretonne-log-dir-{}", ::std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        let mut runner = runner(1, chatty);
+        runner.set_log_dir(&dir).unwrap();
+        runner.put(0, Path::new("pass"));
+        runner.put(1, Path::new("fail"));
+        finish(&mut runner);
+
+        // Strip the timestamps and durations, which vary from run to run.
+        let log = fs::read_to_string(dir.join("worker-0.log")).unwrap();
+        let lines: Vec<_> = log
+            .lines()
+            .map(|line| match line.find("] ") {
+                Some(i) if line.starts_with('[') => line[i + 2..].split(" in ").next().unwrap(),
+                _ => line,
+            })
+            .collect();
+        assert_eq!(
+            lines,
+            ["start pass", "pass pass", "    ran pass", "start fail", "FAIL fail", "    ran fail"]
+        );
+        assert!(log.contains(" ms: bad\n"), "{}", log);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn filter() {
+        let mut runner = runner(1, pass);
+        runner.set_filter(|path| !path.to_str().unwrap().starts_with("skip"));
+        runner.put(0, Path::new("skip0"));
+        runner.put(1, Path::new("run"));
+        runner.put(2, Path::new("skip2"));
+        assert_eq!(runner.num_skipped(), 2);
+        runner.shutdown();
+        runner.join().unwrap();
+        let mut skipped = Vec::new();
+        let mut done = Vec::new();
+        while let Some(reply) = runner.get() {
+            match reply {
+                Reply::Skipped { jobid } => skipped.push(jobid),
+                Reply::Done { jobid, .. } => done.push(jobid),
+                _ => {}
+            }
+        }
+        skipped.sort();
+        assert_eq!(skipped, [0, 2]);
+        assert_eq!(done, [1]);
+    }
+
+    #[test]
+    fn repetitions() {
+        // The job on "fail" fails on its second run.
+        let runs_by_path = Arc::new(Mutex::new(HashMap::new()));
+        let counter = runs_by_path.clone();
+        let mut runner = runner(1, move |path: &Path| {
+            let _gvn = timing::gvn();
+            let mut counter = counter.lock().unwrap();
+            let count = counter.entry(path.to_owned()).or_insert(0);
+            *count += 1;
+            if path == Path::new("fail") && *count == 2 {
+                return Err("bad".to_string());
+            }
+            Ok(Duration::default())
+        });
+        runner.set_repetitions(3);
+        runner.put(0, Path::new("pass"));
+        runner.put(1, Path::new("fail"));
+        runner.shutdown();
+        let mut done = Vec::new();
+        while let Some(reply) = runner.get() {
+            if let Reply::Done { jobid, result, times, .. } = reply {
+                done.push((jobid, result.is_ok(), runs(&times, timing::Pass::gvn)));
+            }
+        }
+        runner.join().unwrap();
+
+        // The timings of a job cover all its runs, up to the first failing one.
+        done.sort();
+        assert_eq!(done, [(0, true, recorded(3)), (1, false, recorded(2))]);
+        let runs_by_path = runs_by_path.lock().unwrap();
+        assert_eq!(runs_by_path[Path::new("pass")], 3);
+        assert_eq!(runs_by_path[Path::new("fail")], 2);
+    }
+
+    #[test]
+    fn seed() {
+        let seeds = Arc::new(Mutex::new(Vec::new()));
+        let recorded_seeds = seeds.clone();
+        let mut runner = runner(2, move |_: &Path| {
+            recorded_seeds.lock().unwrap().push(worker_seed());
+            Ok(Duration::default())
+        });
+        runner.set_seed(0x10);
+        for jobid in 0..8 {
+            runner.put(jobid, Path::new("job"));
+        }
+        finish(&mut runner);
+
+        // Each worker runs its jobs with the base seed combined with its number.
+        let seeds = seeds.lock().unwrap();
+        assert_eq!(seeds.len(), 8);
+        assert!(seeds.iter().all(|&seed| seed == Some(0x10) || seed == Some(0x11)), "{:?}", seeds);
+        assert_eq!(worker_seed(), None);
+    }
+
+    #[test]
+    fn queue_latency() {
+        let gate = Arc::new(Mutex::new(()));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut runner = runner(1, gated(&gate, &order));
+        let closed = gate.lock().unwrap();
+        runner.put(0, Path::new("0"));
+        wait_until(|| runner.in_flight() == 1);
+        runner.put(1, Path::new("1"));
+
+        // Job 1 waits in the queue until job 0 is done.
+        thread::sleep(Duration::from_millis(50));
+        drop(closed);
+        let mut latencies = HashMap::new();
+        while latencies.len() < 2 {
+            if let Some(Reply::Starting { jobid, queue_latency, .. }) = runner.get() {
+                latencies.insert(jobid, queue_latency);
+            }
+        }
+        assert!(latencies[&1] >= Duration::from_millis(50), "{:?}", latencies);
+        assert!(latencies[&0] < latencies[&1], "{:?}", latencies);
+        finish(&mut runner);
+    }
+
+    #[test]
+    fn pending_in_flight() {
+        let gate = Arc::new(Mutex::new(()));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut runner = runner(1, gated(&gate, &order));
+        let closed = gate.lock().unwrap();
+        runner.put(0, Path::new("0"));
+        wait_until(|| runner.in_flight() == 1);
+        runner.put(1, Path::new("1"));
+        runner.put(2, Path::new("2"));
+        assert_eq!((runner.pending(), runner.in_flight()), (2, 1));
+
+        drop(closed);
+        assert_eq!(finish(&mut runner), [0, 1, 2]);
+        assert_eq!((runner.pending(), runner.in_flight()), (0, 0));
+    }
+
+    #[test]
+    fn put_all() {
+        let mut runner = runner(1, pass);
+        runner.put(4, Path::new("first"));
+        let jobids = runner.put_all(["a", "b", "c"].iter().map(PathBuf::from));
+        assert_eq!(jobids, 5..8);
+        assert_eq!(runner.path(6), Some(Path::new("b")));
+        assert_eq!(runner.put_all(iter::empty()), 8..8);
+        let mut done = finish(&mut runner);
+        done.sort();
+        assert_eq!(done, [4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn with_job() {
+        let paths = Arc::new(Mutex::new(Vec::new()));
+        let ran = paths.clone();
+        let mut runner = ConcurrentRunner::with_job(move |path: &Path| {
+            ran.lock().unwrap().push(path.to_owned());
+            Err(format!("not a test: {}", path.display()))
+        });
+        runner.put(0, Path::new("bench.cton"));
+        runner.shutdown();
+        runner.join().unwrap();
+
+        // The job runs instead of the test, and its result is reported as is.
+        assert_eq!(*paths.lock().unwrap(), [PathBuf::from("bench.cton")]);
+        let report = runner.build_report();
+        assert_eq!(report.entries[0].result, Err("not a test: bench.cton".to_string()));
+    }
+
+    #[test]
+    fn labeled() {
+        let mut runner = ConcurrentRunner::new_labeled("x86");
+        assert_eq!(runner.label(), "x86");
+        runner.shutdown();
+        let tagged = runner.join_tagged();
+        assert_eq!(tagged.label, "x86");
+    }
+
+    #[test]
+    fn ticks() {
+        let gate = Arc::new(Mutex::new(()));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut runner = runner(1, gated(&gate, &order));
+        runner.set_tick_interval(Duration::from_millis(10));
+        let closed = gate.lock().unwrap();
+        runner.put(0, Path::new("0"));
+
+        // Ticks keep coming while the job is stuck.
+        let mut ticks = 0;
+        while ticks < 3 {
+            match runner.get() {
+                Some(Reply::Tick) => ticks += 1,
+                Some(Reply::Done { .. }) => panic!("the gated job completed"),
+                _ => {}
+            }
+        }
+        drop(closed);
+        assert_eq!(finish(&mut runner), [0]);
+    }
+
+    /// Number of times `pass` was run according to `times`.
+    fn runs(times: &timing::PassTimes, pass: timing::Pass) -> u64 {
+        times.rows().find(|row| row.pass == pass).map_or(0, |row| row.count)
+    }
+
+    /// Number of runs recorded for a pass that ran `n` times. None are recorded when timing is
+    /// disabled, or compiled out with the `no-timing` feature.
+    fn recorded(n: u64) -> u64 {
+        if timing::is_enabled() { n } else { 0 }
+    }
+
+    #[test]
+    fn job_times() {
+        // Both jobs run on the same worker, so the timings of the first one would leak into the
+        // second one if they weren't taken at the job boundary.
+        let mut runner = ConcurrentRunner::with_threads(1);
+        let path = Path::new("../../filetests/parser/tiny.cton");
+        runner.put(0, path);
+        runner.put(1, path);
+        let mut done = Vec::new();
+        while done.len() < 2 {
+            if let Some(Reply::Done { jobid, result, times, .. }) = runner.get() {
+                assert!(result.is_ok(), "{}: {:?}", jobid, result);
+                done.push((jobid, runs(&times, timing::Pass::process_file)));
+            }
+        }
+        done.sort();
+        assert_eq!(done, [(0, recorded(1)), (1, recorded(1))]);
+
+        // The timings of the jobs are still aggregated when joining.
+        runner.shutdown();
+        timing::take_current();
+        runner.join().unwrap();
+        assert_eq!(runs(&timing::take_current(), timing::Pass::process_file), recorded(2));
+    }
 }
@@ -0,0 +1,142 @@
+//! Flakiness audits.
+//!
+//! A `FlakyAudit` runs every test file several times with a `ConcurrentRunner`, and reports the
+//! files whose outcome isn't the same in every run. Unlike retrying failed tests, this finds tests
+//! that sometimes pass and sometimes fail, or that fail with different errors, which points at
+//! nondeterminism in the compiler, e.g. in the register allocator.
+
+use concurrent::{ConcurrentRunner, Reply};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// A file whose outcome differed between runs.
+#[derive(Clone, Debug)]
+pub struct FlakyFile {
+    /// The path of the test file.
+    pub path: PathBuf,
+
+    /// Number of runs that passed.
+    pub passed: usize,
+
+    /// Error messages of the runs that failed, in the order the runs completed.
+    pub failures: Vec<String>,
+}
+
+/// Outcomes of the runs of a file so far.
+struct Runs {
+    path: PathBuf,
+    /// The outcome of each completed run, without the duration of the runs that passed.
+    outcomes: Vec<Result<(), String>>,
+}
+
+/// Run each test file several times and reconcile the outcomes.
+#[derive(Default)]
+pub struct FlakyAudit {
+    /// The file run by each submitted job, as an index into `files`.
+    jobs: HashMap<usize, usize>,
+    files: Vec<Runs>,
+}
+
+impl FlakyAudit {
+    /// Create an empty audit.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Submit `copies` runs of each file in `paths` to `runner`.
+    ///
+    /// Each run is a separate job, with jobids allocated by `ConcurrentRunner::put_all()`. All the
+    /// files are submitted once before any of them is submitted again, so the runs of a file are
+    /// spread over the worker threads and the duration of the audit.
+    pub fn submit(&mut self, runner: &mut ConcurrentRunner, paths: &[PathBuf], copies: u32) {
+        let first = self.files.len();
+        self.files.extend(paths.iter().map(|path| {
+            Runs {
+                path: path.clone(),
+                outcomes: Vec::new(),
+            }
+        }));
+        for _ in 0..copies {
+            let jobids = runner.put_all(paths.iter().cloned());
+            for (offset, jobid) in jobids.enumerate() {
+                self.jobs.insert(jobid, first + offset);
+            }
+        }
+    }
+
+    /// Record the outcome of a run from one of the replies of the runner.
+    ///
+    /// `Reply::Done` and `Reply::Timeout` are recorded for the jobs submitted by `submit()`, and
+    /// other replies are ignored, so every reply from the runner can be passed here.
+    pub fn add_reply(&mut self, reply: &Reply) {
+        let (jobid, outcome) = match *reply {
+            Reply::Done { jobid, ref result, .. } => (jobid, result.clone().map(|_| ())),
+            Reply::Timeout { jobid } => (jobid, Err("timed out".to_string())),
+            _ => return,
+        };
+        if let Some(&file) = self.jobs.get(&jobid) {
+            self.files[file].outcomes.push(outcome);
+        }
+    }
+
+    /// Get the files whose runs didn't all have the same outcome, in the order they were
+    /// submitted.
+    ///
+    /// Two failed runs only have the same outcome if they failed with the same error message.
+    pub fn flaky(&self) -> Vec<FlakyFile> {
+        self.files
+            .iter()
+            .filter(|runs| runs.outcomes.windows(2).any(|w| w[0] != w[1]))
+            .map(|runs| {
+                FlakyFile {
+                    path: runs.path.clone(),
+                    passed: runs.outcomes.iter().filter(|o| o.is_ok()).count(),
+                    failures: runs.outcomes
+                        .iter()
+                        .filter_map(|o| o.as_ref().err().cloned())
+                        .collect(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[test]
+    fn audit() {
+        // `flaky.cton` fails every other run, whichever worker runs it.
+        let flaky_runs = AtomicUsize::new(0);
+        let mut runner = ConcurrentRunner::with_job(move |path: &Path| {
+            match path.to_str().unwrap() {
+                "flaky.cton" if flaky_runs.fetch_add(1, Ordering::Relaxed) % 2 == 1 => {
+                    Err("unlucky".to_string())
+                }
+                "broken.cton" => Err("broken".to_string()),
+                _ => Ok(Duration::default()),
+            }
+        });
+        let paths: Vec<PathBuf> = ["stable.cton", "flaky.cton", "broken.cton"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        let mut audit = FlakyAudit::new();
+        audit.submit(&mut runner, &paths, 3);
+        runner.shutdown();
+        runner.join().unwrap();
+        while let Some(reply) = runner.get() {
+            audit.add_reply(&reply);
+        }
+
+        let flaky = audit.flaky();
+        assert_eq!(flaky.len(), 1);
+        assert_eq!(flaky[0].path, Path::new("flaky.cton"));
+        assert_eq!(flaky[0].passed, 2);
+        assert_eq!(flaky[0].failures, ["unlucky"]);
+    }
+}
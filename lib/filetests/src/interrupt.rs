@@ -0,0 +1,63 @@
+//! Handling Ctrl-C during a test run.
+//!
+//! With the `ctrlc` feature, `handle_interrupts()` installs a SIGINT handler which records the
+//! interrupt instead of killing the process. A `ConcurrentRunner` set up with
+//! `ConcurrentRunner::stop_on_interrupt()` then stops pulling queued jobs, as if its deadline had
+//! passed, and lets the jobs in flight finish so the harness can report partial results. A second
+//! Ctrl-C kills the process as usual.
+//!
+//! Without the feature, or on platforms other than Unix, nothing is ever interrupted.
+
+#[cfg(all(feature = "ctrlc", unix))]
+mod details {
+    use libc;
+    use std::io;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Once;
+
+    /// Has SIGINT been received?
+    static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+    extern "C" fn on_sigint(_: libc::c_int) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        // Let the next Ctrl-C kill the process. Both calls are async-signal-safe.
+        unsafe { libc::signal(libc::SIGINT, libc::SIG_DFL) };
+    }
+
+    /// Install the SIGINT handler. This only installs it once, so it can be called repeatedly.
+    pub fn handle_interrupts() -> io::Result<()> {
+        static INSTALL: Once = Once::new();
+        let mut result = Ok(());
+        INSTALL.call_once(|| {
+            let handler = on_sigint as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            if unsafe { libc::signal(libc::SIGINT, handler) } == libc::SIG_ERR {
+                result = Err(io::Error::last_os_error());
+            }
+        });
+        result
+    }
+
+    /// Has the process been interrupted since the handler was installed?
+    pub fn interrupted() -> bool {
+        INTERRUPTED.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(not(all(feature = "ctrlc", unix)))]
+mod details {
+    use std::io;
+
+    /// Installing a SIGINT handler is not supported without the `ctrlc` feature.
+    pub fn handle_interrupts() -> io::Result<()> {
+        Err(io::Error::other(
+            "interrupt handling requires the ctrlc feature on Unix",
+        ))
+    }
+
+    /// Has the process been interrupted? This is always false.
+    pub fn interrupted() -> bool {
+        false
+    }
+}
+
+pub use self::details::{handle_interrupts, interrupted};
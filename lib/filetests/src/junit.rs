@@ -0,0 +1,206 @@
+//! JUnit XML reports.
+//!
+//! A `JunitReport` collects the results of the jobs from the replies of a `ConcurrentRunner`, and
+//! writes them as a JUnit `<testsuite>` that CI dashboards can ingest.
+
+use concurrent::Reply;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use secs;
+
+/// The outcome of a test case.
+enum Outcome {
+    Pass,
+    Fail(String),
+    Skipped,
+}
+
+/// A test case of a JUnit report.
+struct TestCase {
+    jobid: usize,
+    path: PathBuf,
+    outcome: Outcome,
+    duration: Duration,
+}
+
+/// Results of the tests in a run, to be written as JUnit XML.
+#[derive(Default)]
+pub struct JunitReport {
+    cases: Vec<TestCase>,
+}
+
+/// Escape a string for use in XML text or attribute values.
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            // Other control characters aren't allowed in XML 1.0.
+            '\n' | '\r' | '\t' => out.push(c),
+            c if (c as u32) < 0x20 => out.push('?'),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+impl JunitReport {
+    /// Create an empty report.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the outcome of a job from one of its replies.
+    ///
+    /// `path` is the file that was submitted as the job, as returned by
+    /// `ConcurrentRunner::path()`. `Reply::Done`, `Reply::Timeout`, and `Reply::Skipped` add a
+    /// test case, and other replies are ignored, so every reply from the runner can be passed
+    /// here.
+    pub fn add_reply(&mut self, path: &Path, reply: &Reply) {
+        match *reply {
+            Reply::Done {
+                jobid,
+                ref result,
+                duration,
+                ..
+            } => {
+                self.cases.push(TestCase {
+                    jobid,
+                    path: path.to_owned(),
+                    outcome: match *result {
+                        Ok(_) => Outcome::Pass,
+                        Err(ref msg) => Outcome::Fail(msg.clone()),
+                    },
+                    duration,
+                })
+            }
+            Reply::Timeout { jobid } => {
+                self.cases.push(TestCase {
+                    jobid,
+                    path: path.to_owned(),
+                    outcome: Outcome::Fail("timed out".to_string()),
+                    duration: Duration::default(),
+                })
+            }
+            Reply::Skipped { jobid } => {
+                self.cases.push(TestCase {
+                    jobid,
+                    path: path.to_owned(),
+                    outcome: Outcome::Skipped,
+                    duration: Duration::default(),
+                })
+            }
+            _ => {}
+        }
+    }
+
+    /// Render the report as a JUnit `<testsuite>` named `suite`, with one `<testcase>` per
+    /// file, ordered by jobid.
+    ///
+    /// Failed tests have a `<failure>` element with their error message, and skipped tests have a
+    /// `<skipped/>` element.
+    pub fn to_xml(&self, suite: &str) -> String {
+        let mut cases: Vec<&TestCase> = self.cases.iter().collect();
+        cases.sort_by_key(|c| c.jobid);
+        let (mut failures, mut skipped) = (0, 0);
+        for case in &cases {
+            match case.outcome {
+                Outcome::Pass => {}
+                Outcome::Fail(_) => failures += 1,
+                Outcome::Skipped => skipped += 1,
+            }
+        }
+        let total: Duration = cases.iter().map(|c| c.duration).sum();
+
+        let mut s = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        writeln!(
+            s,
+            "<testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"0\" skipped=\"{}\" \
+             time=\"{:.3}\">",
+            xml_escape(suite),
+            cases.len(),
+            failures,
+            skipped,
+            secs(total)
+        ).unwrap();
+        for case in cases {
+            write!(
+                s,
+                "  <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+                xml_escape(&case.path.to_string_lossy()),
+                xml_escape(suite),
+                secs(case.duration)
+            ).unwrap();
+            match case.outcome {
+                Outcome::Pass => s.push_str("/>\n"),
+                Outcome::Skipped => s.push_str(">\n    <skipped/>\n  </testcase>\n"),
+                Outcome::Fail(ref msg) => {
+                    let summary = msg.lines().next().unwrap_or("");
+                    writeln!(
+                        s,
+                        ">\n    <failure message=\"{}\">{}</failure>\n  </testcase>",
+                        xml_escape(summary),
+                        xml_escape(msg)
+                    ).unwrap();
+                }
+            }
+        }
+        s.push_str("</testsuite>\n");
+        s
+    }
+
+    /// Write the report as a JUnit `<testsuite>` named `suite` to the file at `path`.
+    pub fn write(&self, suite: &str, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_xml(suite))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape() {
+        assert_eq!(xml_escape("plain"), "plain");
+        assert_eq!(
+            xml_escape("<a href=\"x\">'&'</a>\n\u{1}"),
+            "&lt;a href=&quot;x&quot;&gt;&apos;&amp;&apos;&lt;/a&gt;\n?"
+        );
+    }
+
+    #[test]
+    fn render() {
+        let mut report = JunitReport::new();
+        report.add_reply(Path::new("a.cton"), &Reply::Tick);
+        let ok = Ok(Duration::default());
+        report.add_reply(Path::new("a.cton"), &Reply::done(1, ok, Duration::from_millis(1500)));
+        let err = Err("bad <v>\nin f".to_string());
+        report.add_reply(Path::new("b.cton"), &Reply::done(0, err, Duration::from_millis(250)));
+        report.add_reply(Path::new("c.cton"), &Reply::Skipped { jobid: 2 });
+        report.add_reply(Path::new("d.cton"), &Reply::Timeout { jobid: 3 });
+        assert_eq!(
+            report.to_xml("x86"),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <testsuite name=\"x86\" tests=\"4\" failures=\"2\" errors=\"0\" skipped=\"1\" \
+             time=\"1.750\">\n\
+             \x20 <testcase name=\"b.cton\" classname=\"x86\" time=\"0.250\">\n\
+             \x20   <failure message=\"bad &lt;v&gt;\">bad &lt;v&gt;\nin f</failure>\n\
+             \x20 </testcase>\n\
+             \x20 <testcase name=\"a.cton\" classname=\"x86\" time=\"1.500\"/>\n\
+             \x20 <testcase name=\"c.cton\" classname=\"x86\" time=\"0.000\">\n\
+             \x20   <skipped/>\n\
+             \x20 </testcase>\n\
+             \x20 <testcase name=\"d.cton\" classname=\"x86\" time=\"0.000\">\n\
+             \x20   <failure message=\"timed out\">timed out</failure>\n\
+             \x20 </testcase>\n\
+             </testsuite>\n"
+        );
+    }
+}
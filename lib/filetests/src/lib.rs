@@ -12,18 +12,39 @@
 extern crate cretonne;
 extern crate cton_reader;
 extern crate filecheck;
+#[cfg(feature = "async")]
+extern crate futures_core;
+#[cfg(all(unix, any(feature = "ctrlc", feature = "affinity")))]
+extern crate libc;
 extern crate num_cpus;
 
+pub use baseline::{compare, FileRegression};
+pub use concurrent::{worker_seed, ConcurrentRunner, JoinError, PanicPolicy, Reply};
+pub use flaky::{FlakyAudit, FlakyFile};
+pub use interrupt::{handle_interrupts, interrupted};
+pub use junit::JunitReport;
+pub use output::TestOutput;
+pub use report::{ReportEntry, RunReport};
+pub use summary::{FileResult, RunSummary};
+#[cfg(feature = "async")]
+pub use concurrent::{NextReply, Replies};
 use cton_reader::TestCommand;
 use runner::TestRunner;
 use std::path::Path;
 use std::time;
 
+mod baseline;
 mod concurrent;
+mod flaky;
+mod interrupt;
+mod junit;
 mod match_directive;
+mod output;
+mod report;
 mod runner;
 mod runone;
 mod subtest;
+mod summary;
 
 mod test_binemit;
 mod test_cat;
@@ -40,7 +61,22 @@ mod test_simple_gvn;
 mod test_verifier;
 
 /// The result of running the test in a file.
-type TestResult = Result<time::Duration, String>;
+pub type TestResult = Result<time::Duration, String>;
+
+/// Convert a duration to nanoseconds.
+fn nanos(dur: time::Duration) -> u64 {
+    dur.as_secs() * 1_000_000_000 + u64::from(dur.subsec_nanos())
+}
+
+/// Convert a duration to floating point seconds.
+fn secs(dur: time::Duration) -> f64 {
+    dur.as_secs() as f64 + f64::from(dur.subsec_nanos()) * 1e-9
+}
+
+/// Convert a duration to floating point milliseconds.
+fn millis(dur: time::Duration) -> f64 {
+    secs(dur) * 1e3
+}
 
 /// Main entry point for `cton-util test`.
 ///
@@ -0,0 +1,52 @@
+//! Output of the tests.
+//!
+//! Tests are run concurrently, so anything they print to stdout is interleaved with the output of
+//! the other tests. Tests should write their diagnostics to `TestOutput` instead: The output of
+//! each test is captured separately, and reported along with its result.
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+
+thread_local! {
+    /// Output captured on this thread since `start_capture()`, if capturing.
+    static CAPTURED: RefCell<Option<Vec<u8>>> = const { RefCell::new(None) };
+}
+
+/// Writer for the diagnostics of a test.
+///
+/// While a test is run by the test runner, the output written here is captured and delivered with
+/// the result of the test in `Reply::Done`. Otherwise, it goes to stdout.
+pub struct TestOutput;
+
+impl Write for TestOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let captured = CAPTURED.with(|c| match *c.borrow_mut() {
+            Some(ref mut out) => {
+                out.extend_from_slice(buf);
+                true
+            }
+            None => false,
+        });
+        if captured {
+            Ok(buf.len())
+        } else {
+            io::stdout().write(buf)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+/// Start capturing the output written to `TestOutput` on this thread, discarding any output
+/// captured previously.
+pub fn start_capture() {
+    CAPTURED.with(|c| *c.borrow_mut() = Some(Vec::new()));
+}
+
+/// Stop capturing, and return the output captured on this thread.
+pub fn end_capture() -> String {
+    let out = CAPTURED.with(|c| c.borrow_mut().take()).unwrap_or_default();
+    String::from_utf8_lossy(&out).into_owned()
+}
@@ -0,0 +1,203 @@
+//! Reports combining test results with pass timings.
+//!
+//! A `RunReport` is built by `ConcurrentRunner::build_report()` from the results and pass
+//! timings of the jobs run by the runner. It has one entry per test file, and it can be rendered
+//! as a text table, as Markdown, or as JSON for dashboards.
+
+use cretonne::timing::Pass;
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+use {millis, TestResult};
+
+/// The result and timing summary of a single test file.
+#[derive(Clone, Debug)]
+pub struct ReportEntry {
+    /// The jobid of the test.
+    pub jobid: usize,
+
+    /// The path of the test file.
+    pub path: PathBuf,
+
+    /// The result of running the test.
+    pub result: TestResult,
+
+    /// Total time spent in timed passes while running the test.
+    pub compile_time: Duration,
+
+    /// The pass with the largest self time, if any pass ran.
+    pub dominant_pass: Option<Pass>,
+}
+
+impl ReportEntry {
+    /// Get a short description of the result: `pass` or `fail`.
+    pub fn status(&self) -> &'static str {
+        match self.result {
+            Ok(_) => "pass",
+            Err(_) => "fail",
+        }
+    }
+
+    /// Get the name of the dominant pass, or `-` if no pass ran.
+    fn dominant_name(&self) -> &'static str {
+        self.dominant_pass.map_or("-", Pass::name)
+    }
+}
+
+/// The results and timing summaries of all the tests in a run, ordered by jobid.
+#[derive(Clone, Debug, Default)]
+pub struct RunReport {
+    /// One entry per completed test.
+    pub entries: Vec<ReportEntry>,
+}
+
+/// Quote a string as a JSON string literal.
+pub fn json_str(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+impl RunReport {
+    /// Render the report as a plain text table.
+    pub fn to_table(&self) -> String {
+        let mut s = String::new();
+        writeln!(s, "Result  Time (ms)  Dominant pass         Test").unwrap();
+        writeln!(s, "------  ---------  --------------------  ----").unwrap();
+        for e in &self.entries {
+            writeln!(
+                s,
+                "{:6}  {:9.3}  {:20}  {}",
+                e.status(),
+                millis(e.compile_time),
+                e.dominant_name(),
+                e.path.display()
+            ).unwrap();
+        }
+        s
+    }
+
+    /// Render the report as a Markdown table.
+    pub fn to_markdown(&self) -> String {
+        let mut s = String::new();
+        writeln!(s, "| Test | Result | Time (ms) | Dominant pass |").unwrap();
+        writeln!(s, "|------|--------|----------:|---------------|").unwrap();
+        for e in &self.entries {
+            writeln!(
+                s,
+                "| `{}` | {} | {:.3} | {} |",
+                e.path.display(),
+                e.status(),
+                millis(e.compile_time),
+                e.dominant_name()
+            ).unwrap();
+        }
+        s
+    }
+
+    /// Render the report as a JSON array with one object per test.
+    ///
+    /// Failed tests have an `error` field with the failure message.
+    pub fn to_json(&self) -> String {
+        let mut s = String::from("[");
+        for (i, e) in self.entries.iter().enumerate() {
+            if i > 0 {
+                s.push(',');
+            }
+            write!(
+                s,
+                "\n  {{\"jobid\": {}, \"path\": {}, \"result\": \"{}\", \"compile_time_ms\": {:.3}, \
+                 \"dominant_pass\": {}",
+                e.jobid,
+                json_str(&e.path.to_string_lossy()),
+                e.status(),
+                millis(e.compile_time),
+                e.dominant_pass.map_or("null".to_string(), |p| json_str(p.name()))
+            ).unwrap();
+            if let Err(ref msg) = e.result {
+                write!(s, ", \"error\": {}", json_str(msg)).unwrap();
+            }
+            s.push('}');
+        }
+        s.push_str("\n]\n");
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A report of a passed test and a failed test.
+    fn report() -> RunReport {
+        RunReport {
+            entries: vec![
+                ReportEntry {
+                    jobid: 0,
+                    path: PathBuf::from("a.cton"),
+                    result: Ok(Duration::from_millis(2)),
+                    compile_time: Duration::from_micros(1500),
+                    dominant_pass: Some(Pass::gvn),
+                },
+                ReportEntry {
+                    jobid: 1,
+                    path: PathBuf::from("b\"c.cton"),
+                    result: Err("bad\tvalue\n".to_string()),
+                    compile_time: Duration::default(),
+                    dominant_pass: None,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn escape() {
+        assert_eq!(json_str("plain"), "\"plain\"");
+        assert_eq!(json_str("\"a\\b\"\r\n\t\u{1}"), "\"\\\"a\\\\b\\\"\\r\\n\\t\\u0001\"");
+    }
+
+    #[test]
+    fn table() {
+        assert_eq!(
+            report().to_table(),
+            "Result  Time (ms)  Dominant pass         Test\n\
+             ------  ---------  --------------------  ----\n\
+             pass        1.500  gvn                   a.cton\n\
+             fail        0.000  -                     b\"c.cton\n"
+        );
+    }
+
+    #[test]
+    fn markdown() {
+        assert_eq!(
+            report().to_markdown(),
+            "| Test | Result | Time (ms) | Dominant pass |\n\
+             |------|--------|----------:|---------------|\n\
+             | `a.cton` | pass | 1.500 | gvn |\n\
+             | `b\"c.cton` | fail | 0.000 | - |\n"
+        );
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(
+            report().to_json(),
+            "[\n  {\"jobid\": 0, \"path\": \"a.cton\", \"result\": \"pass\", \
+             \"compile_time_ms\": 1.500, \"dominant_pass\": \"gvn\"},\n  \
+             {\"jobid\": 1, \"path\": \"b\\\"c.cton\", \"result\": \"fail\", \
+             \"compile_time_ms\": 0.000, \"dominant_pass\": null, \
+             \"error\": \"bad\\tvalue\\n\"}\n]\n"
+        );
+    }
+}
@@ -4,12 +4,16 @@
 //! scanning directories for tests.
 
 use concurrent::{ConcurrentRunner, Reply};
+use cretonne::timing;
+use std::env;
 use std::error::Error;
 use std::ffi::OsStr;
 use std::fmt::{self, Display};
+use std::io::{self, IsTerminal};
 use std::path::{Path, PathBuf};
 use std::time;
-use {runone, TestResult};
+use summary::RunSummary;
+use {interrupt, output, runone, TestResult};
 
 /// Timeout in seconds when we're not making progress.
 const TIMEOUT_PANIC: usize = 10;
@@ -17,9 +21,25 @@ const TIMEOUT_PANIC: usize = 10;
 /// Timeout for reporting slow tests without panicking.
 const TIMEOUT_SLOW: usize = 3;
 
+/// ANSI escape sequences for coloring the reported results.
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+
+/// Should the reported results be colored?
+///
+/// Colors are only used when stdout is a terminal, so piped output stays clean, and they can be
+/// disabled by setting the `NO_COLOR` environment variable.
+fn use_color() -> bool {
+    env::var_os("NO_COLOR").is_none() && io::stdout().is_terminal()
+}
+
 struct QueueEntry {
     path: PathBuf,
     state: State,
+    // Output written by the test to `TestOutput`, once it is done.
+    output: String,
 }
 
 #[derive(PartialEq, Eq, Debug)]
@@ -28,6 +48,7 @@ enum State {
     Queued,
     Running,
     Done(TestResult),
+    Skipped,
 }
 
 impl QueueEntry {
@@ -50,6 +71,7 @@ impl Display for QueueEntry {
                 )
             }
             State::Done(Err(ref e)) => write!(f, "FAIL {}: {}", p, e),
+            State::Skipped => write!(f, "SKIP {}", p),
             _ => write!(f, "{}", p),
         }
     }
@@ -58,6 +80,9 @@ impl Display for QueueEntry {
 pub struct TestRunner {
     verbose: bool,
 
+    // Color the reported results?
+    color: bool,
+
     // Directories that have not yet been scanned.
     dir_stack: Vec<PathBuf>,
 
@@ -77,6 +102,9 @@ pub struct TestRunner {
     ticks_since_progress: usize,
 
     threads: Option<ConcurrentRunner>,
+
+    // Summary of the concurrent jobs, reported when the run is interrupted.
+    summary: RunSummary,
 }
 
 impl TestRunner {
@@ -84,6 +112,7 @@ impl TestRunner {
     pub fn new(verbose: bool) -> Self {
         Self {
             verbose,
+            color: use_color(),
             dir_stack: Vec::new(),
             tests: Vec::new(),
             new_tests: 0,
@@ -91,6 +120,7 @@ impl TestRunner {
             errors: 0,
             ticks_since_progress: 0,
             threads: None,
+            summary: RunSummary::new(),
         }
     }
 
@@ -109,13 +139,20 @@ impl TestRunner {
         self.tests.push(QueueEntry {
             path: file.into(),
             state: State::New,
+            output: String::new(),
         });
     }
 
     /// Begin running tests concurrently.
     pub fn start_threads(&mut self) {
         assert!(self.threads.is_none());
-        self.threads = Some(ConcurrentRunner::new());
+        let mut conc = ConcurrentRunner::new();
+        if cfg!(feature = "ctrlc") {
+            if let Err(err) = conc.stop_on_interrupt() {
+                println!("warning: can't handle Ctrl-C: {}", err);
+            }
+        }
+        self.threads = Some(conc);
     }
 
     /// Scan any directories pushed so far.
@@ -179,15 +216,27 @@ impl TestRunner {
 
     /// Report on the next in-order job, if it's done.
     fn report_job(&self) -> bool {
-        let jobid = self.reported_tests;
-        if let Some(&QueueEntry { state: State::Done(ref result), .. }) = self.tests.get(jobid) {
-            if self.verbose || result.is_err() {
-                println!("{}", self.tests[jobid]);
+        let entry = match self.tests.get(self.reported_tests) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        let (color, failed) = match entry.state {
+            State::Done(Ok(_)) => (GREEN, false),
+            State::Done(Err(_)) => (RED, true),
+            State::Skipped => (YELLOW, false),
+            _ => return false,
+        };
+        if self.verbose || failed {
+            if self.color {
+                println!("{}{}{}", color, entry, RESET);
+            } else {
+                println!("{}", entry);
+            }
+            for line in entry.output.lines() {
+                println!("    {}", line);
             }
-            true
-        } else {
-            false
         }
+        true
     }
 
     /// Schedule any new jobs to run.
@@ -201,8 +250,9 @@ impl TestRunner {
             } else {
                 // Run test synchronously.
                 self.tests[jobid].state = State::Running;
+                output::start_capture();
                 let result = runone::run(self.tests[jobid].path());
-                self.finish_job(jobid, result);
+                self.finish_job(jobid, result, output::end_capture());
             }
             self.new_tests = jobid + 1;
         }
@@ -214,12 +264,13 @@ impl TestRunner {
     }
 
     /// Report the end of a job.
-    fn finish_job(&mut self, jobid: usize, result: TestResult) {
+    fn finish_job(&mut self, jobid: usize, result: TestResult, output: String) {
         assert_eq!(self.tests[jobid].state, State::Running);
         if result.is_err() {
             self.errors += 1;
         }
         self.tests[jobid].state = State::Done(result);
+        self.tests[jobid].output = output;
 
         // Reports jobs in order.
         while self.report_job() {
@@ -229,14 +280,36 @@ impl TestRunner {
 
     /// Handle a reply from the async threads.
     fn handle_reply(&mut self, reply: Reply) {
+        match reply {
+            Reply::Done { jobid, .. } |
+            Reply::Timeout { jobid } |
+            Reply::Skipped { jobid } => self.summary.add_reply(self.tests[jobid].path(), &reply),
+            _ => {}
+        }
         match reply {
             Reply::Starting { jobid, .. } => {
                 assert_eq!(self.tests[jobid].state, State::Queued);
                 self.tests[jobid].state = State::Running;
             }
-            Reply::Done { jobid, result } => {
+            Reply::Done {
+                jobid,
+                result,
+                output,
+                ..
+            } => {
                 self.ticks_since_progress = 0;
-                self.finish_job(jobid, result)
+                self.finish_job(jobid, result, output)
+            }
+            Reply::Timeout { jobid } => {
+                self.ticks_since_progress = 0;
+                self.finish_job(jobid, Err("timed out".to_string()), String::new())
+            }
+            Reply::Skipped { jobid } => {
+                assert_eq!(self.tests[jobid].state, State::Queued);
+                self.tests[jobid].state = State::Skipped;
+                while self.report_job() {
+                    self.reported_tests += 1;
+                }
             }
             Reply::Tick => {
                 self.ticks_since_progress += 1;
@@ -273,7 +346,12 @@ impl TestRunner {
                     None => break,
                 }
             }
-            conc.join();
+            if let Err(errors) = conc.join() {
+                for msg in errors {
+                    println!("{}", msg);
+                    self.errors += 1;
+                }
+            }
         }
     }
 
@@ -320,12 +398,28 @@ impl TestRunner {
         }
     }
 
+    /// Print out the partial results of an interrupted run, including the pass timings of the
+    /// jobs that finished.
+    fn report_interrupted(&self) -> TestResult {
+        println!(
+            "interrupted with {}/{} tests finished",
+            self.summary.files().len(),
+            self.tests.len()
+        );
+        print!("{}", self.summary.to_table(10));
+        print!("{}", timing::snapshot());
+        Err("interrupted".to_string())
+    }
+
     /// Scan pushed directories for tests and run them.
     pub fn run(&mut self) -> TestResult {
         let started = time::Instant::now();
         self.scan_dirs();
         self.schedule_jobs();
         self.drain_threads();
+        if interrupt::interrupted() {
+            return self.report_interrupted();
+        }
         self.report_slow_tests();
         println!("{} tests", self.tests.len());
         match self.errors {
@@ -28,6 +28,13 @@ fn read_to_string<P: AsRef<Path>>(path: P) -> io::Result<String> {
 ///
 /// If running this test causes a panic, it will propagate as normal.
 pub fn run(path: &Path) -> TestResult {
+    let result = run_file(path);
+    // Make sure the test didn't leave the timing instrumentation in an inconsistent state.
+    result.and_then(|dur| timing::check_discipline().map(|()| dur))
+}
+
+/// Load `path` and run the test in it, timed as a `process_file` pass.
+fn run_file(path: &Path) -> TestResult {
     let _tt = timing::process_file();
     dbg!("---\nFile: {}", path.to_string_lossy());
     let started = time::Instant::now();
@@ -126,6 +133,9 @@ fn run_one_test<'a>(
 
     context.flags = flags;
     context.isa = isa;
+    if let Some(isa) = isa {
+        timing::set_isa(isa.name());
+    }
 
     // Should we run the verifier before this test?
     if !context.verified && test.needs_verifier() {
@@ -0,0 +1,291 @@
+//! Summary of a test run.
+//!
+//! A `RunSummary` is built incrementally from the replies of a `ConcurrentRunner` as they are
+//! received, and summarizes the run as the counts of passed, failed, and skipped tests, along with
+//! a table of the slowest test files. This is the test suite analog of the `PassTimes` report.
+//! It can also be rendered as JSON for CI tooling.
+
+use concurrent::Reply;
+use cretonne::timing::PassTimes;
+use report::json_str;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use millis;
+
+/// The outcome of a single test file that was run.
+#[derive(Clone, Debug)]
+pub struct FileResult {
+    /// The jobid of the test.
+    pub jobid: usize,
+
+    /// The path of the test file.
+    pub path: PathBuf,
+
+    /// Wall-clock time spent running the test.
+    pub duration: Duration,
+
+    /// The error message if the test failed, or `None` if it passed.
+    pub error: Option<String>,
+}
+
+impl FileResult {
+    /// Did the test pass?
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Counts and durations of the tests in a run.
+#[derive(Clone)]
+pub struct RunSummary {
+    /// The tests that were run, in the order they completed.
+    pub(crate) files: Vec<FileResult>,
+
+    /// Number of tests skipped by the filter.
+    pub(crate) skipped: usize,
+
+    /// Aggregated pass timings of the tests.
+    pub(crate) times: PassTimes,
+
+    /// Names of the passes whose timings were dropped when loading a baseline, because they no
+    /// longer exist.
+    pub(crate) ignored_passes: Vec<String>,
+
+    /// Time the summary was created.
+    started: Instant,
+
+    /// Wall-clock time from the creation of the summary to the last reply.
+    pub(crate) wall_time: Duration,
+}
+
+impl RunSummary {
+    /// Create an empty summary. The wall time of the run is measured from now.
+    pub fn new() -> Self {
+        Self {
+            files: Vec::new(),
+            skipped: 0,
+            times: PassTimes::default(),
+            ignored_passes: Vec::new(),
+            started: Instant::now(),
+            wall_time: Duration::default(),
+        }
+    }
+
+    /// Record the outcome of a job from one of its replies.
+    ///
+    /// `path` is the file that was submitted as the job, as returned by
+    /// `ConcurrentRunner::path()`. Every reply from the runner can be passed here: `Reply::Done`,
+    /// `Reply::Timeout`, and `Reply::Skipped` are counted, and all the replies extend the wall
+    /// time of the run. A timed out test counts as failed, with no duration.
+    pub fn add_reply(&mut self, path: &Path, reply: &Reply) {
+        self.wall_time = self.started.elapsed();
+        let (jobid, duration, error) = match *reply {
+            Reply::Done {
+                jobid,
+                ref result,
+                duration,
+                ref times,
+                ..
+            } => {
+                self.times.add(times);
+                (jobid, duration, result.as_ref().err().cloned())
+            }
+            Reply::Timeout { jobid } => {
+                (jobid, Duration::default(), Some("timed out".to_string()))
+            }
+            Reply::Skipped { .. } => {
+                self.skipped += 1;
+                return;
+            }
+            _ => return,
+        };
+        self.files.push(FileResult {
+            jobid,
+            path: path.to_owned(),
+            duration,
+            error,
+        });
+    }
+
+    /// Get the tests that were run, in the order they completed.
+    pub fn files(&self) -> &[FileResult] {
+        &self.files
+    }
+
+    /// Get the number of tests that passed.
+    pub fn passed(&self) -> usize {
+        self.files.iter().filter(|f| f.passed()).count()
+    }
+
+    /// Get the number of tests that failed or timed out.
+    pub fn failed(&self) -> usize {
+        self.files.len() - self.passed()
+    }
+
+    /// Get the number of tests skipped by the filter.
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// Get the aggregated pass timings of the tests.
+    pub fn times(&self) -> &PassTimes {
+        &self.times
+    }
+
+    /// Get the names of the passes whose timings were dropped when this summary was loaded from a
+    /// baseline, because they no longer exist.
+    pub fn ignored_passes(&self) -> &[String] {
+        &self.ignored_passes
+    }
+
+    /// Get the wall-clock time from the creation of the summary to the last reply.
+    pub fn wall_time(&self) -> Duration {
+        self.wall_time
+    }
+
+    /// Get the `n` slowest tests, slowest first.
+    pub fn slowest(&self, n: usize) -> Vec<&FileResult> {
+        let mut files: Vec<&FileResult> = self.files.iter().collect();
+        files.sort_by(|a, b| b.duration.cmp(&a.duration).then(a.jobid.cmp(&b.jobid)));
+        files.truncate(n);
+        files
+    }
+
+    /// Render the summary as a plain text table of the `n` slowest tests, followed by the counts
+    /// of passed, failed, and skipped tests.
+    pub fn to_table(&self, n: usize) -> String {
+        let slowest = self.slowest(n);
+        let mut s = String::new();
+        writeln!(s, "Slowest {} of {} tests:", slowest.len(), self.files.len()).unwrap();
+        writeln!(s, "Time (ms)  Result  Test").unwrap();
+        writeln!(s, "---------  ------  ----").unwrap();
+        for f in slowest {
+            writeln!(
+                s,
+                "{:9.3}  {:6}  {}",
+                millis(f.duration),
+                if f.passed() { "pass" } else { "fail" },
+                f.path.display()
+            ).unwrap();
+        }
+        writeln!(
+            s,
+            "{} passed, {} failed, {} skipped in {:.3} s",
+            self.passed(),
+            self.failed(),
+            self.skipped,
+            millis(self.wall_time) * 1e-3
+        ).unwrap();
+        s
+    }
+
+    /// Render the summary as a JSON object for CI tooling.
+    ///
+    /// The object has the number of test files submitted, passed, failed, and skipped, the wall
+    /// time of the run in nanoseconds, the aggregated pass timings as encoded by
+    /// `PassTimes::to_json()`, and the failed files with their error messages:
+    ///
+    /// ```text
+    /// {"files": 3, "passed": 1, "failed": 1, "skipped": 1, "wall_time_ns": 1200,
+    ///  "times": {...}, "failures": [{"path": "a.cton", "error": "..."}]}
+    /// ```
+    pub fn to_json(&self) -> String {
+        let failures: Vec<String> = self.files
+            .iter()
+            .filter_map(|f| {
+                f.error.as_ref().map(|msg| {
+                    format!(
+                        "{{\"path\": {}, \"error\": {}}}",
+                        json_str(&f.path.to_string_lossy()),
+                        json_str(msg)
+                    )
+                })
+            })
+            .collect();
+        let wall_time_ns = self.wall_time.as_secs() * 1_000_000_000 +
+            u64::from(self.wall_time.subsec_nanos());
+        format!(
+            "{{\"files\": {}, \"passed\": {}, \"failed\": {}, \"skipped\": {}, \
+             \"wall_time_ns\": {}, \"times\": {}, \"failures\": [{}]}}\n",
+            self.files.len() + self.skipped,
+            self.passed(),
+            self.failed(),
+            self.skipped,
+            wall_time_ns,
+            self.times.to_json(),
+            failures.join(", ")
+        )
+    }
+}
+
+impl Default for RunSummary {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A summary of two passed tests, two failed tests, and a skipped test, with a wall time
+    /// of 1.2 s.
+    fn summary() -> RunSummary {
+        let ok = Ok(Duration::default());
+        let err = Err("boom".to_string());
+        let replies = [
+            ("a.cton", Reply::done(0, ok.clone(), Duration::from_millis(2))),
+            ("b.cton", Reply::done(1, err, Duration::from_millis(5))),
+            ("c.cton", Reply::Timeout { jobid: 2 }),
+            ("d.cton", Reply::Skipped { jobid: 3 }),
+            ("e.cton", Reply::done(4, ok, Duration::from_millis(1))),
+            ("e.cton", Reply::Tick),
+        ];
+        let mut summary = RunSummary::new();
+        for &(path, ref reply) in &replies {
+            summary.add_reply(Path::new(path), reply);
+        }
+        summary.wall_time = Duration::from_millis(1200);
+        summary
+    }
+
+    #[test]
+    fn counts() {
+        let summary = summary();
+        assert_eq!(summary.files().len(), 4);
+        assert_eq!(summary.passed(), 2);
+        assert_eq!(summary.failed(), 2);
+        assert_eq!(summary.skipped(), 1);
+        let slowest: Vec<usize> = summary.slowest(3).iter().map(|f| f.jobid).collect();
+        assert_eq!(slowest, [1, 0, 4]);
+    }
+
+    #[test]
+    fn table() {
+        assert_eq!(
+            summary().to_table(2),
+            "Slowest 2 of 4 tests:\n\
+             Time (ms)  Result  Test\n\
+             ---------  ------  ----\n\
+             \x20   5.000  fail    b.cton\n\
+             \x20   2.000  pass    a.cton\n\
+             2 passed, 2 failed, 1 skipped in 1.200 s\n"
+        );
+    }
+
+    #[test]
+    fn json() {
+        let summary = summary();
+        assert_eq!(
+            summary.to_json(),
+            format!(
+                "{{\"files\": 5, \"passed\": 2, \"failed\": 2, \"skipped\": 1, \
+                 \"wall_time_ns\": 1200000000, \"times\": {}, \"failures\": \
+                 [{{\"path\": \"b.cton\", \"error\": \"boom\"}}, \
+                 {{\"path\": \"c.cton\", \"error\": \"timed out\"}}]}}\n",
+                summary.times().to_json()
+            )
+        );
+    }
+}
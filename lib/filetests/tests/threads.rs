@@ -0,0 +1,62 @@
+//! Test that `ConcurrentRunner` doesn't leak threads.
+//!
+//! This counts the threads of the whole process, so it lives in its own test binary.
+
+#![cfg(target_os = "linux")]
+
+extern crate cton_filetests;
+
+use cton_filetests::{ConcurrentRunner, Reply};
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Get the number of threads of the current process.
+fn num_threads() -> usize {
+    let status = fs::read_to_string("/proc/self/status").unwrap();
+    let line = status.lines().find(|line| line.starts_with("Threads:")).unwrap();
+    line["Threads:".len()..].trim().parse().unwrap()
+}
+
+/// Wait for the number of threads to get back to `baseline`.
+///
+/// A joined thread may still be counted for a short while after `join()` returns.
+fn settle(baseline: usize) -> usize {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while num_threads() != baseline && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+    }
+    num_threads()
+}
+
+/// Run a few trivial jobs with their replies forwarded, and drop the runner, joining it first
+/// if `join` is set.
+fn run(join: bool) {
+    let mut runner = ConcurrentRunner::with_job(|_| Ok(Duration::from_millis(1)));
+    let (tx, rx) = channel();
+    runner.forward_to(tx);
+    for jobid in 0..4 {
+        runner.put(jobid, Path::new("job"));
+    }
+    if join {
+        runner.shutdown();
+        runner.join().unwrap();
+        let done = rx.iter().filter(|reply| matches!(*reply, Reply::Done { .. }));
+        assert_eq!(done.count(), 4);
+    }
+}
+
+#[test]
+fn no_leaked_threads() {
+    let baseline = num_threads();
+    for _ in 0..20 {
+        run(true);
+        assert_eq!(settle(baseline), baseline);
+    }
+    for _ in 0..20 {
+        run(false);
+    }
+    assert_eq!(settle(baseline), baseline);
+}